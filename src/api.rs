@@ -0,0 +1,1265 @@
+use futures_util::StreamExt;
+use reqwest::RequestBuilder;
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::Message;
+use crate::cassette::Cassette;
+use crate::keyring::Keyring;
+use crate::prompt;
+use crate::usage::Usage;
+
+pub const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(into = "WireMessage")]
+pub struct ApiMessage {
+    pub role: String,
+    /// Null in replies that were refused.
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub content: String,
+    /// Set instead of `content` when the model declines to answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<String>,
+    /// Reasoning returned alongside the answer by models and providers that expose it.
+    /// Never sent back.
+    #[serde(default, alias = "reasoning", skip_serializing)]
+    pub reasoning_content: Option<String>,
+    /// Spoken audio, when the request asked for the `audio` modality. Never sent back.
+    #[serde(default, skip_serializing)]
+    pub audio: Option<ApiAudio>,
+    /// Ids of uploaded files sent along with `content`.
+    #[serde(skip)]
+    pub file_ids: Vec<String>,
+    /// Marks the end of the prompt prefix the provider should cache.
+    #[serde(skip)]
+    pub cache_breakpoint: bool,
+}
+
+/// An [`ApiMessage`] as it goes over the wire. With files attached or a cache breakpoint,
+/// the content is sent as a list of parts instead of a plain string.
+#[derive(Serialize)]
+struct WireMessage {
+    role: String,
+    content: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refusal: Option<String>,
+}
+
+impl From<ApiMessage> for WireMessage {
+    fn from(message: ApiMessage) -> Self {
+        let content = if message.file_ids.is_empty() && !message.cache_breakpoint {
+            serde_json::Value::String(message.content)
+        } else {
+            let mut text = serde_json::json!({ "type": "text", "text": message.content });
+            if message.cache_breakpoint {
+                text["cache_control"] = serde_json::json!({ "type": "ephemeral" });
+            }
+            let mut parts = vec![text];
+            parts.extend(
+                message
+                    .file_ids
+                    .iter()
+                    .map(|id| serde_json::json!({ "type": "file", "file": { "file_id": id } })),
+            );
+            serde_json::Value::Array(parts)
+        };
+        WireMessage {
+            role: message.role,
+            content,
+            refusal: message.refusal,
+        }
+    }
+}
+
+/// Audio generated by the model for a reply.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ApiAudio {
+    /// Base64-encoded audio in the requested format.
+    #[cfg(feature = "audio")]
+    #[serde(default)]
+    pub data: String,
+    /// What the audio says; replies with audio have no `content`.
+    #[serde(default)]
+    pub transcript: String,
+}
+
+/// Voice and encoding for replies requested with the `audio` modality.
+#[derive(Serialize, Debug, Clone)]
+pub struct AudioOptions {
+    pub voice: String,
+    pub format: String,
+}
+
+fn null_as_empty<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// How the conversation is sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// A messages array to `/chat/completions`.
+    #[default]
+    Chat,
+    /// One flattened prompt string to `/completions`.
+    Prompt,
+}
+
+/// Optional request parameters chosen on the command line.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RequestOptions {
+    /// Processing tier, e.g. `auto`, `default` or `flex`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<String>,
+    /// Sampling temperature, 0 to 2.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// Nucleus sampling: only consider tokens within this much probability mass.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    /// Minimum probability relative to the likeliest token. Only some OpenAI-compatible
+    /// servers (llama.cpp, vLLM and the like) support it; OpenAI itself does not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_p: Option<f32>,
+    /// Most tokens to generate for the reply.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// `{"type": "json_object"}` for JSON mode, or a `json_schema` format for structured
+    /// outputs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<serde_json::Value>,
+    /// Ask for the log probability of each token in the reply.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    /// How many of the most likely alternatives to return for each token.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u32>,
+    /// Output types to generate, e.g. `["text", "audio"]`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modalities: Option<Vec<String>>,
+    /// Voice settings, required when `modalities` includes audio.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio: Option<AudioOptions>,
+    /// Mark the stable start of the prompt as cacheable, where the model supports it.
+    #[serde(skip)]
+    pub cache_prefix: bool,
+    /// Warn when the reply comes from a different model than the one asked for.
+    #[serde(skip)]
+    pub warn_model_mismatch: bool,
+    #[serde(skip)]
+    pub format: Format,
+    /// Per-message template for `Format::Prompt`.
+    #[serde(skip)]
+    pub prompt_template: String,
+}
+
+#[derive(Serialize)]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Vec<ApiMessage>,
+    #[serde(flatten)]
+    pub options: RequestOptions,
+    /// Ask for the reply as server-sent events, a piece at a time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+/// Extra settings for streamed replies.
+#[derive(Serialize, Clone, Copy)]
+struct StreamOptions {
+    /// Send the token usage in a last chunk, since streamed replies otherwise have none.
+    include_usage: bool,
+}
+
+impl ChatRequest {
+    pub fn new(model: &str, messages: &[Message]) -> Self {
+        ChatRequest {
+            model: model.to_string(),
+            messages: messages.iter().map(|m| m.to_api(model)).collect(),
+            options: RequestOptions::default(),
+            stream: None,
+            stream_options: None,
+        }
+    }
+
+    /// Asks for the reply to be streamed; see [`ApiClient::chat_stream`].
+    pub fn streamed(mut self) -> Self {
+        self.stream = Some(true);
+        self.stream_options = Some(StreamOptions {
+            include_usage: true,
+        });
+        self
+    }
+
+    pub fn with_options(mut self, options: &RequestOptions) -> Self {
+        self.options = options.clone();
+        if options.cache_prefix && supports_cache_control(&self.model) {
+            // The leading system messages (system prompt, pins, summary) only change when
+            // the summary does, so they make a good cached prefix.
+            let prefix = self
+                .messages
+                .iter()
+                .take_while(|m| matches!(m.role.as_str(), "system" | "developer"))
+                .count();
+            if let Some(last) = prefix.checked_sub(1) {
+                self.messages[last].cache_breakpoint = true;
+            }
+        }
+        self
+    }
+}
+
+/// Whether `model` takes explicit `cache_control` hints. Claude models do, whether reached
+/// directly or through a router such as OpenRouter; OpenAI caches prompt prefixes on its
+/// own and rejects the hints.
+pub fn supports_cache_control(model: &str) -> bool {
+    model.to_lowercase().contains("claude")
+}
+
+#[derive(Serialize)]
+struct CompletionRequest<'a> {
+    model: &'a str,
+    prompt: String,
+    #[serde(flatten)]
+    options: &'a RequestOptions,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+#[derive(Deserialize)]
+struct CompletionChoice {
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CompletionResponse {
+    #[serde(default)]
+    model: Option<String>,
+    choices: Vec<CompletionChoice>,
+    usage: Option<Usage>,
+}
+
+impl From<CompletionResponse> for ChatResponse {
+    fn from(response: CompletionResponse) -> Self {
+        ChatResponse {
+            choices: response
+                .choices
+                .into_iter()
+                .map(|c| ChatChoice {
+                    message: ApiMessage {
+                        role: "assistant".to_string(),
+                        content: c.text,
+                        refusal: None,
+                        reasoning_content: None,
+                        audio: None,
+                        file_ids: Vec::new(),
+                        cache_breakpoint: false,
+                    },
+                    logprobs: None,
+                    finish_reason: c.finish_reason,
+                })
+                .collect(),
+            usage: response.usage,
+            service_tier: None,
+            model: response.model,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChatChoice {
+    pub message: ApiMessage,
+    /// Present when the request asked for `logprobs`.
+    #[serde(default)]
+    pub logprobs: Option<Logprobs>,
+    /// Why generation stopped: `stop`, or `length` when the token limit cut it short.
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+}
+
+impl ChatChoice {
+    /// True if the reply was cut off by the token limit.
+    pub fn truncated(&self) -> bool {
+        self.finish_reason.as_deref() == Some("length")
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Logprobs {
+    #[serde(default)]
+    pub content: Option<Vec<TokenLogprob>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChatResponse {
+    pub choices: Vec<ChatChoice>,
+    pub usage: Option<Usage>,
+    /// The tier the request was actually processed on.
+    #[serde(default)]
+    pub service_tier: Option<String>,
+    /// The model that actually answered, often a dated snapshot of the one asked for.
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+impl ChatResponse {
+    /// Warns when the reply came from another model than `requested`. A dated snapshot of
+    /// it (`gpt-4o-2024-08-06` for `gpt-4o`) is the same model and passes.
+    pub fn check_model(&self, requested: &str) {
+        let Some(served) = &self.model else {
+            return;
+        };
+        let snapshot = served
+            .strip_prefix(requested)
+            .and_then(|rest| rest.strip_prefix('-'))
+            .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()));
+        if served != requested && !snapshot {
+            eprintln!("Requested {}, but the reply came from {}.", requested, served);
+        }
+    }
+
+    /// Warns when the API processed the request on a different tier than was asked for.
+    pub fn check_service_tier(&self, options: &RequestOptions) {
+        if let (Some(requested), Some(served)) = (&options.service_tier, &self.service_tier)
+            && requested != "auto"
+            && requested != served
+        {
+            eprintln!(
+                "Requested the '{}' service tier, but the request was served on '{}'.",
+                requested, served
+            );
+        }
+    }
+}
+
+/// One server-sent event of a streamed reply.
+#[derive(Deserialize)]
+struct ChatStreamChunk {
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    choices: Vec<ChatStreamChoice>,
+    /// Only in the last chunk, and only when `stream_options` asked for it.
+    #[serde(default)]
+    usage: Option<Usage>,
+    #[serde(default)]
+    service_tier: Option<String>,
+    /// Some servers report a failure partway through as a chunk of its own.
+    #[serde(default)]
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct ChatStreamChoice {
+    #[serde(default)]
+    index: usize,
+    #[serde(default)]
+    delta: ChatStreamDelta,
+    /// `/completions` streams send text instead of a delta.
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct ChatStreamDelta {
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    refusal: Option<String>,
+    #[serde(default, alias = "reasoning")]
+    reasoning_content: Option<String>,
+}
+
+/// A streamed reply being put back together. Events can be split anywhere between
+/// network chunks, so bytes are buffered until a whole line has arrived.
+#[derive(Default)]
+struct StreamedReply {
+    /// Everything received, for the cassette and for servers that ignore `stream`.
+    body: Vec<u8>,
+    pending: Vec<u8>,
+    events: usize,
+    /// Whether the `[DONE]` sentinel has arrived.
+    done: bool,
+    content: String,
+    refusal: Option<String>,
+    reasoning_content: Option<String>,
+    finish_reason: Option<String>,
+    model: Option<String>,
+    usage: Option<Usage>,
+    service_tier: Option<String>,
+}
+
+impl StreamedReply {
+    /// Adds a chunk of the body, passing any new reply text to `on_delta`.
+    fn feed(&mut self, bytes: &[u8], on_delta: &mut impl FnMut(&str)) -> Result<(), Error> {
+        self.body.extend_from_slice(bytes);
+        self.pending.extend_from_slice(bytes);
+        while let Some(end) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            self.line(&line, on_delta)?;
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, line: &[u8], on_delta: &mut impl FnMut(&str)) -> Result<(), Error> {
+        let line = String::from_utf8_lossy(line);
+        // Comments, `event:` lines and blank separators carry nothing we need.
+        let Some(data) = line.trim_end().strip_prefix("data:").map(str::trim_start) else {
+            return Ok(());
+        };
+        self.events += 1;
+        if data == "[DONE]" {
+            self.done = true;
+            return Ok(());
+        }
+        let chunk: ChatStreamChunk = decode(data)?;
+        if let Some(error) = chunk.error {
+            let message = error["message"].as_str().map_or_else(|| error.to_string(), str::to_string);
+            return Err(Error::Other(format!("the API stopped the reply partway: {}", message)));
+        }
+        self.model = chunk.model.or(self.model.take());
+        self.usage = chunk.usage.or(self.usage.take());
+        self.service_tier = chunk.service_tier.or(self.service_tier.take());
+        for choice in chunk.choices.into_iter().filter(|c| c.index == 0) {
+            if let Some(text) = choice.delta.content.or(choice.text) {
+                on_delta(&text);
+                self.content.push_str(&text);
+            }
+            if let Some(refusal) = choice.delta.refusal {
+                self.refusal.get_or_insert_default().push_str(&refusal);
+            }
+            if let Some(reasoning) = choice.delta.reasoning_content {
+                self.reasoning_content.get_or_insert_default().push_str(&reasoning);
+            }
+            self.finish_reason = choice.finish_reason.or(self.finish_reason.take());
+        }
+        Ok(())
+    }
+
+    /// `cause`, carrying the reply so far if any of it had arrived.
+    fn broken_off(self, cause: Error) -> Error {
+        if self.content.is_empty() {
+            cause
+        } else {
+            Error::Partial {
+                partial: self.content,
+                cause: Box::new(cause),
+            }
+        }
+    }
+
+    /// The whole reply as a [`ChatResponse`]. A body with no events at all came from a
+    /// server that answered in one piece anyway; it is decoded as usual and passed to
+    /// `on_delta` whole. A stream that ends with neither `[DONE]` nor a finish reason was
+    /// cut short.
+    fn finish(mut self, path: &str, on_delta: &mut impl FnMut(&str)) -> Result<ChatResponse, Error> {
+        let rest = std::mem::take(&mut self.pending);
+        if let Err(e) = self.line(&rest, on_delta) {
+            return Err(self.broken_off(e));
+        }
+        if self.events > 0 && !self.done && self.finish_reason.is_none() {
+            return Err(self.broken_off(Error::Other("the stream ended before the reply was finished".to_string())));
+        }
+        if self.events == 0 {
+            let body = String::from_utf8_lossy(&self.body);
+            let response: ChatResponse = match path {
+                "/completions" => decode::<CompletionResponse>(&body)?.into(),
+                _ => decode(&body)?,
+            };
+            if let Some(choice) = response.choices.first() {
+                on_delta(&choice.message.content);
+            }
+            return Ok(response);
+        }
+        Ok(ChatResponse {
+            choices: vec![ChatChoice {
+                message: ApiMessage {
+                    role: "assistant".to_string(),
+                    content: self.content,
+                    refusal: self.refusal,
+                    reasoning_content: self.reasoning_content,
+                    audio: None,
+                    file_ids: Vec::new(),
+                    cache_breakpoint: false,
+                },
+                logprobs: None,
+                finish_reason: self.finish_reason,
+            }],
+            usage: self.usage,
+            service_tier: self.service_tier,
+            model: self.model,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct UploadedFile {
+    id: String,
+}
+
+/// How failed requests are retried, and when to stop trying altogether.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 disables retrying.
+    pub max_retries: u32,
+    /// HTTP statuses worth retrying. Network errors are always retried.
+    pub retry_on: Vec<u16>,
+    /// Fraction (0.0-1.0) of each backoff delay that is randomized.
+    pub jitter: f64,
+    /// Consecutive failed requests that open the circuit breaker; 0 disables it.
+    pub breaker_threshold: u32,
+    /// How long requests are refused once the breaker opens.
+    pub breaker_cooldown: Duration,
+}
+
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+impl RetryPolicy {
+    fn is_retriable(&self, status: reqwest::StatusCode) -> bool {
+        self.retry_on.contains(&status.as_u16())
+    }
+
+    /// Exponential backoff for the given attempt, randomized by `jitter` so that many
+    /// clients don't retry in lockstep.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let base = BASE_BACKOFF
+            .saturating_mul(1 << attempt.min(16))
+            .min(MAX_BACKOFF);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        base.mul_f64(1.0 - jitter + 2.0 * jitter * random_unit())
+    }
+}
+
+/// A random number in `[0, 1)`, good enough for spreading out retries.
+pub fn random_unit() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// HTTP client for the OpenAI API. Every request carries the auth header plus any
+/// `--header` values the user supplied.
+pub struct ApiClient {
+    http: reqwest::Client,
+    base_url: String,
+    keys: Keyring,
+    headers: HeaderMap,
+    retry: RetryPolicy,
+    breaker: Mutex<Breaker>,
+    cassette: Option<Cassette>,
+    /// The `--mock` reply template; when set nothing goes over the network.
+    mock: Option<String>,
+    /// `--max-requests`: how many calls this client may make in all.
+    max_requests: Option<usize>,
+    /// Calls made so far, retries aside.
+    requests: AtomicUsize,
+    show_headers: bool,
+    /// `--stream-idle-timeout`: the longest wait for the next piece of a streamed reply.
+    stream_idle_timeout: Option<Duration>,
+}
+
+impl ApiClient {
+    pub fn new(base_url: &str, keys: Keyring, headers: HeaderMap, retry: RetryPolicy) -> Self {
+        ApiClient {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            keys,
+            headers,
+            retry,
+            breaker: Mutex::new(Breaker::default()),
+            cassette: None,
+            mock: None,
+            max_requests: None,
+            requests: AtomicUsize::new(0),
+            show_headers: false,
+            stream_idle_timeout: None,
+        }
+    }
+
+    /// Prints the diagnostic response headers of every request to stderr.
+    pub fn with_show_headers(mut self, show: bool) -> Self {
+        self.show_headers = show;
+        self
+    }
+
+    /// Records requests to, or replays them from, `cassette`.
+    pub fn with_cassette(mut self, cassette: Cassette) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    /// Answers every request locally from `template` instead of calling the API.
+    pub fn with_mock(mut self, template: &str) -> Self {
+        self.mock = Some(template.to_string());
+        self
+    }
+
+    /// Refuses every call after the first `max` of them.
+    pub fn with_max_requests(mut self, max: Option<usize>) -> Self {
+        self.max_requests = max;
+        self
+    }
+
+    /// Gives up on a streamed reply once nothing has arrived for `idle`.
+    pub fn with_stream_idle_timeout(mut self, idle: Option<Duration>) -> Self {
+        self.stream_idle_timeout = idle;
+        self
+    }
+
+    /// Counts a call against `--max-requests`, refusing it once the limit is reached.
+    fn count_request(&self) -> Result<(), Error> {
+        let made = self.requests.fetch_add(1, Ordering::Relaxed);
+        match self.max_requests {
+            Some(max) if made >= max => Err(Error::Other(format!(
+                "the session has used up its --max-requests limit of {}; no more requests will be sent",
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Starts a POST to `path` (relative to the API base, e.g. `/chat/completions`), with
+    /// the key for `model`.
+    pub fn post(&self, path: &str, model: &str) -> RequestBuilder {
+        self.http
+            .post(format!("{}{}", self.base_url, path))
+            .header(CONTENT_TYPE, "application/json")
+            .header(AUTHORIZATION, format!("Bearer {}", self.keys.key_for(Some(model))))
+            .headers(self.headers.clone())
+    }
+
+    /// Sends a chat completion request, turning non-success statuses into errors carrying
+    /// the response body. Transient failures are retried according to the retry policy.
+    /// With `Format::Prompt` the messages go out as a single prompt to `/completions`.
+    pub async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse, Error> {
+        self.chat_stream(request, |_| {}).await
+    }
+
+    /// Like [`ApiClient::chat`], but a [streamed](ChatRequest::streamed) request has each
+    /// piece of the reply passed to `on_delta` as it arrives. The response is the same
+    /// either way.
+    pub async fn chat_stream(&self, request: &ChatRequest, mut on_delta: impl FnMut(&str)) -> Result<ChatResponse, Error> {
+        let streamed = request.stream == Some(true);
+        let response: ChatResponse = match request.options.format {
+            Format::Chat if streamed => self.stream("/chat/completions", &request.model, request, &mut on_delta).await?,
+            Format::Chat => self.send("/chat/completions", &request.model, request).await?,
+            Format::Prompt => {
+                let body = CompletionRequest {
+                    model: &request.model,
+                    prompt: prompt::flatten(&request.messages, &request.options.prompt_template),
+                    options: &request.options,
+                    stream: request.stream,
+                    stream_options: request.stream_options,
+                };
+                if streamed {
+                    self.stream("/completions", &request.model, &body, &mut on_delta).await?
+                } else {
+                    let response: CompletionResponse = self.send("/completions", &request.model, &body).await?;
+                    response.into()
+                }
+            }
+        };
+        if request.options.warn_model_mismatch {
+            response.check_model(&request.model);
+        }
+        Ok(response)
+    }
+
+    async fn send<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        model: &str,
+        body: &impl Serialize,
+    ) -> Result<T, Error> {
+        self.count_request()?;
+        if let Some(template) = &self.mock {
+            return decode(&crate::mock::respond(template, path, &request_json(body)?));
+        }
+        let recorded_request = match &self.cassette {
+            Some(cassette) => {
+                let request = request_json(body)?;
+                if let Some(response) = cassette.lookup(path, &request) {
+                    return decode(&response.map_err(Error::Other)?);
+                }
+                Some((cassette, request))
+            }
+            None => None,
+        };
+        let text = self.respond(path, model, body).await?.text().await?;
+        if let Some((cassette, request)) = &recorded_request {
+            cassette.save(path, request.clone(), &text);
+        }
+        decode(&text)
+    }
+
+    /// Sends a streamed request to `path` and reads the reply as it arrives. Failures
+    /// before the first byte are retried just as in [`ApiClient::send`].
+    async fn stream(
+        &self,
+        path: &str,
+        model: &str,
+        body: &impl Serialize,
+        on_delta: &mut impl FnMut(&str),
+    ) -> Result<ChatResponse, Error> {
+        self.count_request()?;
+        let mut reply = StreamedReply::default();
+        if let Some(template) = &self.mock {
+            reply.feed(crate::mock::respond(template, path, &request_json(body)?).as_bytes(), on_delta)?;
+            return reply.finish(path, on_delta);
+        }
+        let recorded_request = match &self.cassette {
+            Some(cassette) => {
+                let request = request_json(body)?;
+                if let Some(response) = cassette.lookup(path, &request) {
+                    reply.feed(response.map_err(Error::Other)?.as_bytes(), on_delta)?;
+                    return reply.finish(path, on_delta);
+                }
+                Some((cassette, request))
+            }
+            None => None,
+        };
+
+        let mut chunks = self.respond(path, model, body).await?.bytes_stream();
+        loop {
+            let next = match self.stream_idle_timeout {
+                Some(idle) => match tokio::time::timeout(idle, chunks.next()).await {
+                    Ok(next) => next,
+                    Err(_) => {
+                        let stalled = format!("nothing arrived for {}s (--stream-idle-timeout)", idle.as_secs());
+                        return Err(reply.broken_off(Error::Other(stalled)));
+                    }
+                },
+                None => chunks.next().await,
+            };
+            let Some(chunk) = next else {
+                break;
+            };
+            let fed = chunk.map_err(Error::from).and_then(|chunk| reply.feed(&chunk, on_delta));
+            if let Err(e) = fed {
+                return Err(reply.broken_off(e));
+            }
+        }
+        if let Some((cassette, request)) = &recorded_request {
+            cassette.save(path, request.clone(), &String::from_utf8_lossy(&reply.body));
+        }
+        reply.finish(path, on_delta)
+    }
+
+    /// Posts `body` to `path` until it succeeds or the retry policy gives up, and returns
+    /// the successful response with its body still unread.
+    async fn respond(&self, path: &str, model: &str, body: &impl Serialize) -> Result<reqwest::Response, Error> {
+        self.check_breaker()?;
+
+        let mut attempt = 0;
+        loop {
+            let mut retry_after = None;
+            let response = self.post(path, model).json(body).send().await;
+            if self.show_headers
+                && let Ok(res) = &response
+            {
+                print_headers(res.headers());
+            }
+            let error =
+                match response {
+                    Ok(res) if res.status().is_success() => {
+                        self.record_outcome(true);
+                        return Ok(res);
+                    }
+                    Ok(res) => {
+                        let status = res.status();
+                        retry_after = res
+                            .headers()
+                            .get(reqwest::header::RETRY_AFTER)
+                            .and_then(|v| v.to_str().ok())
+                            .and_then(|v| v.parse().ok())
+                            .map(Duration::from_secs);
+                        let error = Error::from(ApiError {
+                            status,
+                            body: error_body(status, &res.text().await?),
+                        });
+                        if !self.retry.is_retriable(status) || matches!(error, Error::OutOfQuota(_)) {
+                            // The API answered, it just didn't like the request.
+                            self.record_outcome(true);
+                            return Err(error);
+                        }
+                        error
+                    }
+                    Err(e) => Error::Network(e),
+                };
+
+            if attempt >= self.retry.max_retries {
+                self.record_outcome(false);
+                return Err(error);
+            }
+            let delay = retry_after.unwrap_or_else(|| self.retry.backoff(attempt));
+            eprintln!(
+                "Request failed, retrying in {:.1}s... ({})",
+                delay.as_secs_f64(),
+                error
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Refuses to send while the circuit breaker is open.
+    fn check_breaker(&self) -> Result<(), Error> {
+        let mut breaker = self.breaker.lock().unwrap();
+        if let Some(until) = breaker.open_until {
+            let now = Instant::now();
+            if now < until {
+                return Err(Error::Other(format!(
+                    "The API has failed {} times in a row; pausing requests for another {}s.",
+                    breaker.consecutive_failures,
+                    (until - now).as_secs() + 1
+                )));
+            }
+            // Cooldown is over: let a request through to probe the API.
+            breaker.open_until = None;
+        }
+        Ok(())
+    }
+
+    fn record_outcome(&self, reached_api: bool) {
+        let mut breaker = self.breaker.lock().unwrap();
+        if reached_api {
+            breaker.consecutive_failures = 0;
+            return;
+        }
+        breaker.consecutive_failures += 1;
+        let threshold = self.retry.breaker_threshold;
+        if threshold > 0 && breaker.consecutive_failures >= threshold {
+            breaker.open_until = Some(Instant::now() + self.retry.breaker_cooldown);
+            eprintln!(
+                "The API has failed {} times in a row; pausing requests for {}s.",
+                breaker.consecutive_failures,
+                self.retry.breaker_cooldown.as_secs()
+            );
+        }
+    }
+
+    /// Uploads a file to `/files` for use in messages and returns its file id. The body is
+    /// built by hand since it is the only multipart request.
+    pub async fn upload_file(&self, name: &str, data: &[u8]) -> Result<String, Error> {
+        let boundary = format!("gpt-cli-{:016x}", (random_unit() * u64::MAX as f64) as u64);
+        let mut body = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"purpose\"\r\n\r\nuser_data\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{name}\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n",
+            b = boundary,
+            name = name.replace(['"', '\r', '\n'], "_"),
+        )
+        .into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+        self.count_request()?;
+        if self.mock.is_some() {
+            return Ok(format!("file-mock-{}", name));
+        }
+
+        self.check_breaker()?;
+        let res = self
+            .http
+            .post(format!("{}/files", self.base_url))
+            .header(
+                CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", boundary),
+            )
+            .header(AUTHORIZATION, format!("Bearer {}", self.keys.key_for(None)))
+            .headers(self.headers.clone())
+            .body(body)
+            .send()
+            .await?;
+        let status = res.status();
+        let text = res.text().await?;
+        if !status.is_success() {
+            return Err(ApiError {
+                status,
+                body: error_body(status, &text),
+            }
+            .into());
+        }
+        let file: UploadedFile = decode(&text)?;
+        Ok(file.id)
+    }
+
+    /// Starts a GET to `path` (relative to the API base, e.g. `/models`).
+    pub fn get(&self, path: &str) -> RequestBuilder {
+        self.http
+            .get(format!("{}{}", self.base_url, path))
+            .header(AUTHORIZATION, format!("Bearer {}", self.keys.key_for(None)))
+            .headers(self.headers.clone())
+    }
+}
+
+/// Response headers worth seeing when diagnosing a request; OpenAI support asks for the
+/// request id.
+const SHOWN_HEADERS: &[&str] = &[
+    "x-request-id",
+    "openai-model",
+    "openai-processing-ms",
+    "openai-version",
+    "x-ratelimit-limit-requests",
+    "x-ratelimit-remaining-requests",
+    "x-ratelimit-reset-requests",
+    "x-ratelimit-limit-tokens",
+    "x-ratelimit-remaining-tokens",
+    "x-ratelimit-reset-tokens",
+];
+
+/// Prints the headers in [`SHOWN_HEADERS`] that are present, for `--show-headers`.
+fn print_headers(headers: &HeaderMap) {
+    let shown: Vec<String> = SHOWN_HEADERS
+        .iter()
+        .filter_map(|&name| {
+            let value = headers.get(name)?.to_str().ok()?;
+            Some(format!("  {}: {}", name, value))
+        })
+        .collect();
+    if !shown.is_empty() {
+        eprintln!("[headers]\n{}", shown.join("\n"));
+    }
+}
+
+/// How much of an unexpected response body is shown in error messages.
+const SNIPPET_CHARS: usize = 200;
+
+/// The start of `body` on one line, for error messages.
+fn snippet(body: &str) -> String {
+    let flat: String = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    if flat.chars().count() > SNIPPET_CHARS {
+        format!("{}...", flat.chars().take(SNIPPET_CHARS).collect::<String>())
+    } else {
+        flat
+    }
+}
+
+/// Parses a successful response body, explaining what arrived instead when it isn't the
+/// expected JSON (a proxy's HTML error page, say).
+pub fn decode<T: serde::de::DeserializeOwned>(body: &str) -> Result<T, Error> {
+    serde_json::from_str(body).map_err(|e| {
+        Error::Json(format!(
+            "unexpected response from the API ({}). Received: {}",
+            e,
+            snippet(body)
+        ))
+    })
+}
+
+/// A request body as JSON, for the cassette and `--mock` to look at.
+fn request_json(body: &impl Serialize) -> Result<serde_json::Value, Error> {
+    serde_json::to_value(body).map_err(|e| Error::Json(format!("could not encode the request: {}", e)))
+}
+
+/// Why an API call failed, so callers can tell the kinds of failure apart.
+#[derive(Debug)]
+pub enum Error {
+    /// The key was missing, wrong or not allowed to do this (401 or 403).
+    Auth(ApiError),
+    /// Too many requests for now (429); waiting or another model may help.
+    RateLimited(ApiError),
+    /// The account is out of credits; nothing helps until it is topped up.
+    OutOfQuota(ApiError),
+    /// The request was longer than the model's context window.
+    ContextLength(ApiError),
+    /// Any other error status.
+    Status(ApiError),
+    /// The API couldn't be reached, or the connection failed midway.
+    Network(reqwest::Error),
+    /// The response wasn't the JSON that was expected.
+    Json(String),
+    /// Refused without asking the API: the circuit breaker, `--max-requests` or a `--replay`
+    /// miss.
+    Other(String),
+    /// A streamed reply broke off after `partial` had arrived.
+    Partial { partial: String, cause: Box<Error> },
+}
+
+impl Error {
+    /// The error response, if the API answered at all.
+    pub fn api_error(&self) -> Option<&ApiError> {
+        match self {
+            Error::Auth(e)
+            | Error::RateLimited(e)
+            | Error::OutOfQuota(e)
+            | Error::ContextLength(e)
+            | Error::Status(e) => Some(e),
+            Error::Partial { cause, .. } => cause.api_error(),
+            Error::Network(_) | Error::Json(_) | Error::Other(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Auth(e)
+            | Error::RateLimited(e)
+            | Error::OutOfQuota(e)
+            | Error::ContextLength(e)
+            | Error::Status(e) => e.fmt(f),
+            Error::Network(e) => e.fmt(f),
+            Error::Json(message) | Error::Other(message) => f.write_str(message),
+            Error::Partial { cause, .. } => cause.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Network(e) => Some(e),
+            Error::Partial { cause, .. } => cause.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<ApiError> for Error {
+    fn from(error: ApiError) -> Self {
+        match error.status.as_u16() {
+            _ if error.out_of_quota() => Error::OutOfQuota(error),
+            _ if error.code().as_deref() == Some("context_length_exceeded") => {
+                Error::ContextLength(error)
+            }
+            401 | 403 => Error::Auth(error),
+            429 => Error::RateLimited(error),
+            _ => Error::Status(error),
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        Error::Network(error)
+    }
+}
+
+/// A request the API answered with an error status.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: reqwest::StatusCode,
+    body: String,
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.body)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl ApiError {
+    /// Whether a different model might succeed: this one wasn't found, is overloaded or
+    /// is rate limited.
+    pub fn model_unavailable(&self) -> bool {
+        matches!(self.status.as_u16(), 404 | 429 | 503 | 529) && !self.out_of_quota()
+    }
+
+    /// The `code` of the API's JSON error, or its `type` when it has no code.
+    pub fn code(&self) -> Option<String> {
+        let body: serde_json::Value = serde_json::from_str(&self.body).ok()?;
+        let error = body.get("error")?;
+        [error.get("code"), error.get("type")]
+            .into_iter()
+            .flatten()
+            .find_map(|v| v.as_str().map(str::to_string))
+    }
+
+    /// Whether the account behind the key has run out of credits. Sent as a 429, but no
+    /// amount of retrying or switching models will help.
+    pub fn out_of_quota(&self) -> bool {
+        self.code().as_deref() == Some("insufficient_quota")
+    }
+}
+
+/// The part of a streamed reply that arrived before `error` broke it off, if any did.
+pub fn partial_reply<'a>(error: &'a (dyn std::error::Error + 'static)) -> Option<&'a str> {
+    match error.downcast_ref::<Error>() {
+        Some(Error::Partial { partial, .. }) => Some(partial),
+        _ => None,
+    }
+}
+
+/// Whether `error` is an API [`Error`] saying the account is out of quota.
+pub fn is_out_of_quota(error: &(dyn std::error::Error + 'static)) -> bool {
+    matches!(error.downcast_ref::<Error>(), Some(Error::OutOfQuota(_)))
+}
+
+/// What to tell the user when the account is out of quota.
+pub const OUT_OF_QUOTA: &str = "the account behind this API key is out of quota (insufficient_quota). Add credits or raise the usage limit in the provider's billing settings, then try again.";
+
+/// The error for a failed request: the API's JSON error as is, anything else shortened.
+fn error_body(status: reqwest::StatusCode, body: &str) -> String {
+    if serde_json::from_str::<serde_json::Value>(body).is_ok() {
+        body.to_string()
+    } else {
+        format!("HTTP {} with a non-JSON body: {}", status, snippet(body))
+    }
+}
+
+/// Parses `--header` values of the form `Name: Value` into a header map.
+pub fn parse_headers(raw: &[String]) -> Result<HeaderMap, String> {
+    let mut headers = HeaderMap::new();
+    for entry in raw {
+        let (name, value) = entry
+            .split_once(':')
+            .ok_or_else(|| format!("invalid header '{}': expected 'Name: Value'", entry))?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| format!("invalid header name in '{}'", entry))?;
+        let value = HeaderValue::from_str(value.trim())
+            .map_err(|_| format!("invalid header value in '{}'", entry))?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Message;
+
+    #[test]
+    fn request_maps_roles_without_touching_the_messages() {
+        let messages = vec![Message::new("system", "Be brief."), Message::new("user", "Hi")];
+        let o3 = ChatRequest::new("o3", &messages);
+        assert_eq!(o3.messages[0].role, "developer");
+        assert_eq!(o3.messages[1].role, "user");
+        let gpt = ChatRequest::new("gpt-4o", &[Message::new("developer", "Be brief.")]);
+        assert_eq!(gpt.messages[0].role, "system");
+        assert_eq!(messages[0].role, "system");
+    }
+
+    /// Feeds `body` to a [`StreamedReply`] in `size`-byte pieces and finishes it.
+    fn stream_in_pieces(body: &str, size: usize) -> Result<(ChatResponse, String), Error> {
+        let mut reply = StreamedReply::default();
+        let mut shown = String::new();
+        for piece in body.as_bytes().chunks(size) {
+            if let Err(e) = reply.feed(piece, &mut |text| shown.push_str(text)) {
+                return Err(reply.broken_off(e));
+            }
+        }
+        let response = reply.finish("/chat/completions", &mut |text| shown.push_str(text))?;
+        Ok((response, shown))
+    }
+
+    #[test]
+    fn streamed_request_asks_for_usage() {
+        let request = ChatRequest::new("gpt-4o", &[Message::new("user", "Hi")]).streamed();
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["stream"], true);
+        assert_eq!(json["stream_options"]["include_usage"], true);
+        let plain = serde_json::to_value(ChatRequest::new("gpt-4o", &[])).unwrap();
+        assert!(plain.get("stream_options").is_none());
+    }
+
+    #[test]
+    fn usage_chunk_is_kept() {
+        let body = concat!(
+            "data: {\"model\":\"gpt-4o-2024-08-06\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hel\"}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"lo\"},\"finish_reason\":\"stop\"}]}\n\n",
+            "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":12,\"completion_tokens\":2,\"total_tokens\":14}}\n\n",
+            "data: [DONE]\n\n",
+        );
+        for size in [1, 7, body.len()] {
+            let (response, shown) = stream_in_pieces(body, size).unwrap();
+            assert_eq!(shown, "Hello");
+            assert_eq!(response.choices[0].message.content, "Hello");
+            assert_eq!(response.choices[0].finish_reason.as_deref(), Some("stop"));
+            let usage = response.usage.unwrap();
+            assert_eq!((usage.prompt_tokens, usage.completion_tokens), (12, 2));
+            assert_eq!(response.model.as_deref(), Some("gpt-4o-2024-08-06"));
+        }
+    }
+
+    #[test]
+    fn stream_without_a_usage_chunk_has_no_usage() {
+        let body = "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"}}]}\n\ndata: [DONE]\n\n";
+        let (response, _) = stream_in_pieces(body, 5).unwrap();
+        assert!(response.usage.is_none());
+    }
+
+    /// The text kept from a stream that broke off, and why it did.
+    fn broken_off(body: &str) -> (String, String) {
+        match stream_in_pieces(body, 3) {
+            Err(Error::Partial { partial, cause }) => (partial, cause.to_string()),
+            Err(e) => panic!("lost the partial reply: {}", e),
+            Ok(_) => panic!("a broken-off stream was taken as finished"),
+        }
+    }
+
+    #[test]
+    fn truncated_stream_keeps_the_partial_reply() {
+        let body = concat!(
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"The first\"}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\" half\"}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"del",
+        );
+        let (partial, cause) = broken_off(body);
+        assert_eq!(partial, "The first half");
+        assert!(cause.contains("unexpected response"), "{}", cause);
+
+        let (partial, cause) = broken_off(&body[..body.rfind("data:").unwrap()]);
+        assert_eq!(partial, "The first half");
+        assert_eq!(cause, "the stream ended before the reply was finished");
+    }
+
+    #[test]
+    fn error_event_keeps_the_partial_reply() {
+        let body = concat!(
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Partly\"}}]}\n\n",
+            "data: {\"error\":{\"message\":\"server overloaded\"}}\n\n",
+        );
+        let (partial, cause) = broken_off(body);
+        assert_eq!(partial, "Partly");
+        assert!(cause.contains("server overloaded"), "{}", cause);
+    }
+
+    #[test]
+    fn stream_breaking_before_any_text_has_nothing_to_keep() {
+        let body = "data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\"}}]}\n\n";
+        assert!(matches!(stream_in_pieces(body, 4), Err(Error::Other(_))));
+    }
+
+    #[test]
+    fn finish_reason_without_done_is_finished() {
+        let body = "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"},\"finish_reason\":\"stop\"}]}\n\n";
+        assert_eq!(stream_in_pieces(body, 4).unwrap().1, "Hi");
+    }
+}
@@ -0,0 +1,94 @@
+//! `--audio-output`: replies spoken by the model itself, built only with the `audio` feature.
+
+use std::error::Error;
+use std::path::PathBuf;
+
+use crate::Args;
+use crate::api::ApiAudio;
+use crate::hooks;
+
+/// Encoding requested for spoken replies.
+pub const FORMAT: &str = "wav";
+
+/// The platform's usual command-line audio player, if it has one.
+fn default_player() -> Option<&'static str> {
+    if cfg!(target_os = "macos") {
+        Some("afplay")
+    } else if cfg!(target_os = "linux") {
+        Some("aplay -q")
+    } else {
+        None
+    }
+}
+
+/// Decodes standard base64, ignoring whitespace.
+fn decode_base64(data: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(data.len() / 4 * 3);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in data.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ => return Err(format!("invalid base64 character '{}'", c as char)),
+        };
+        buffer = buffer << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+/// Saves the reply's audio under `--audio-save`, or plays it. Ctrl+C stops playback
+/// without ending the session.
+pub async fn output(args: &Args, audio: &ApiAudio) -> Result<(), Box<dyn Error>> {
+    let wav = decode_base64(&audio.data)?;
+    if wav.is_empty() {
+        return Err("the reply came back without audio".into());
+    }
+    let name = format!(
+        "reply_{}.{}",
+        chrono::Local::now().format("%Y%m%d_%H%M%S"),
+        FORMAT
+    );
+
+    if let Some(dir) = &args.audio_save {
+        let path = PathBuf::from(dir).join(name);
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(&path, wav)?;
+        println!("Audio saved to {}\n", path.display());
+        return Ok(());
+    }
+
+    let player = args
+        .audio_player
+        .as_deref()
+        .or(default_player())
+        .ok_or("no audio player is known for this platform; set one with --audio-player")?;
+    let path = std::env::temp_dir().join(format!("gpt_{}_{}", std::process::id(), name));
+    std::fs::write(&path, wav)?;
+    let mut child = hooks::shell(&format!("{} '{}'", player, path.display()))
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("could not start '{}': {}", player, e))?;
+    let result = tokio::select! {
+        status = child.wait() => match status {
+            Ok(status) if !status.success() => Err(format!("'{}' failed ({})", player, status).into()),
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.into()),
+        },
+        _ = tokio::signal::ctrl_c() => {
+            let _ = child.kill().await;
+            println!("(audio stopped)");
+            Ok(())
+        }
+    };
+    let _ = std::fs::remove_file(&path);
+    result
+}
@@ -0,0 +1,217 @@
+//! `--batch FILE`: every non-empty line of the file is sent as its own standalone prompt
+//! and the replies are printed in order. `--estimate-batch FILE` prices such a file
+//! without sending it.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Write};
+
+use crate::api::{ApiClient, RequestOptions};
+use crate::redact::Redactor;
+use crate::sink::ReplySink;
+use crate::usage::{Currency, UsageTotals};
+use crate::{Args, Message, input, models, session, tokens};
+
+/// How much of each prompt `--estimate-batch` shows.
+const PROMPT_PREVIEW_CHARS: usize = 50;
+
+/// The contents of the batch file at `path`, or of stdin for `-`, decoded as
+/// `--on-invalid-utf8` says.
+fn read(path: &str, args: &Args) -> Result<String, Box<dyn Error>> {
+    let (bytes, source) = match path {
+        "-" => {
+            let mut bytes = Vec::new();
+            std::io::stdin().read_to_end(&mut bytes)?;
+            (bytes, "stdin")
+        }
+        _ => (
+            std::fs::read(path).map_err(|e| format!("could not read {}: {}", path, e))?,
+            path,
+        ),
+    };
+    Ok(input::decode(
+        bytes,
+        args.on_invalid_utf8,
+        source,
+        1,
+        &mut false,
+    )?)
+}
+
+/// The prompts in a batch file with their line numbers. Blank lines are skipped.
+fn prompts<'a>(text: &'a str, path: &str) -> Result<Vec<(usize, &'a str)>, Box<dyn Error>> {
+    let prompts: Vec<(usize, &str)> = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect();
+    if prompts.is_empty() {
+        let source = if path == "-" { "stdin" } else { path };
+        return Err(format!("{} has no prompts; nothing was sent", source).into());
+    }
+    Ok(prompts)
+}
+
+/// The messages sent for one prompt: the system prompt, if any, and the prompt itself.
+fn context(args: &Args, prompt: &str) -> Vec<Message> {
+    let mut context: Vec<Message> = session::system_prompt(
+        args.system.as_deref(),
+        args.target_length,
+        args.inject_datetime
+            .then_some(args.datetime_format.as_str()),
+    )
+    .map(|s| Message::new("system", s))
+    .into_iter()
+    .collect();
+    context.push(Message::new("user", prompt));
+    context
+}
+
+/// Prints the estimated input tokens and cost of each prompt in `path` for `--model`, and
+/// the total, without sending anything. Replies can't be known in advance, so they are
+/// only included as an upper bound when `--max-tokens` caps them.
+pub fn estimate(path: &str, args: &Args, currency: &Currency) -> Result<(), Box<dyn Error>> {
+    let info = models::lookup(&args.model).ok_or_else(|| {
+        format!(
+            "there is no price for {} in the local table (see --list-models)",
+            args.model
+        )
+    })?;
+    let text = read(path, args)?;
+    let prompts = prompts(&text, path)?;
+
+    let cost = |tokens: usize, price: f64| tokens as f64 * price / 1_000_000.0;
+    println!("{:>6} {:>8} {:>12}  PROMPT", "LINE", "TOKENS", "COST");
+    let mut total = 0;
+    for &(line, prompt) in &prompts {
+        let tokens: usize = context(args, prompt)
+            .iter()
+            .map(|m| tokens::estimate(&m.content))
+            .sum();
+        total += tokens;
+        let mut preview: String = prompt.chars().take(PROMPT_PREVIEW_CHARS).collect();
+        if preview.len() < prompt.len() {
+            preview.push('…');
+        }
+        println!(
+            "{:>6} {:>8} {:>12}  {}",
+            line,
+            tokens,
+            currency.format(cost(tokens, info.input_price), 5),
+            preview
+        );
+    }
+    println!(
+        "{:>6} {:>8} {:>12}  {} prompts to {}",
+        "TOTAL",
+        total,
+        currency.format(cost(total, info.input_price), 5),
+        prompts.len(),
+        args.model
+    );
+    match args.max_tokens {
+        Some(max_tokens) => {
+            let replies = cost(max_tokens as usize * prompts.len(), info.output_price);
+            println!(
+                "\nReplies add at most {} (--max-tokens {} each), so at most {} in all.",
+                currency.format(replies, 5),
+                max_tokens,
+                currency.format(cost(total, info.input_price) + replies, 5)
+            );
+        }
+        None => println!(
+            "\nReplies are not included; they cost {} per million tokens. Pass --max-tokens to bound them.",
+            currency.format(info.output_price, 2)
+        ),
+    }
+    Ok(())
+}
+
+/// Sends each prompt in `path` with no history. Unless `--continue-on-error` is given, the
+/// first failure stops the batch.
+pub async fn run(
+    client: &ApiClient,
+    path: &str,
+    args: &Args,
+    options: &RequestOptions,
+    redactor: &Redactor,
+    mut output: Option<&mut File>,
+    mut sink: Option<&mut ReplySink>,
+) -> Result<(), Box<dyn Error>> {
+    let text = read(path, args)?;
+    // Every line is redacted before the first one is sent.
+    let prompts = prompts(&text, path)?
+        .into_iter()
+        .map(|(line, prompt)| {
+            let prompt = crate::redact(redactor, prompt, &format!("{}:{}", path, line))?;
+            Ok((line, prompt))
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+    let mut usage = UsageTotals::default();
+    let mut failures = 0;
+    for (line, prompt) in &prompts {
+        let context = context(args, prompt);
+        let error =
+            match crate::request_reply(client, &args.model, &context, options, &mut usage, None)
+                .await
+            {
+                Ok(Some((model, choice))) => {
+                    let mut message = Message::reply(&choice.message);
+                    let extracted = args
+                        .extract
+                        .as_deref()
+                        .map(|field| crate::extract_field(&message.content, field))
+                        .transpose();
+                    match extracted {
+                        Err(e) => e,
+                        Ok(content) => {
+                            if let Some(content) = content {
+                                message.content = content;
+                            }
+                            if let Some(file) = &mut output
+                                && let Err(e) =
+                                    writeln!(file, "{}", crate::reply_text(args, &message))
+                            {
+                                eprintln!(
+                                    "Could not write to {}: {}",
+                                    args.output.as_deref().unwrap_or_default(),
+                                    e
+                                );
+                            }
+                            if let Some(sink) = &mut sink {
+                                sink.send(&model, &crate::reply_text(args, &message)).await;
+                            }
+                            if !args.output_only {
+                                if !args.raw {
+                                    println!("> {}", prompt);
+                                }
+                                crate::display_reply(args, &model, &message);
+                            }
+                            continue;
+                        }
+                    }
+                }
+                Ok(None) => "the API returned no choices".to_string(),
+                Err(e) if crate::api::is_out_of_quota(e.as_ref()) => {
+                    return Err(crate::api::OUT_OF_QUOTA.into());
+                }
+                Err(e) => e.to_string(),
+            };
+        failures += 1;
+        eprintln!("{}:{}: {}", path, line, error);
+        if !args.continue_on_error {
+            return Err(format!(
+                "batch stopped at line {} (use --continue-on-error to keep going)",
+                line
+            )
+            .into());
+        }
+    }
+
+    if failures > 0 {
+        return Err(format!("{} of {} prompts failed", failures, prompts.len()).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,62 @@
+//! `--benchmark`: the same prompt sent to several models, with latency, usage and cost
+//! reported side by side.
+
+use std::error::Error;
+use std::time::Instant;
+
+use crate::Message;
+use crate::api::{ApiClient, ChatRequest, RequestOptions};
+use crate::usage::{Currency, UsageTotals};
+
+/// Sends `prompt` to each model in turn and prints a comparison table.
+pub async fn run(
+    client: &ApiClient,
+    prompt: &str,
+    models: &[String],
+    system: Option<&str>,
+    options: &RequestOptions,
+    currency: &Currency,
+) -> Result<(), Box<dyn Error>> {
+    let mut messages: Vec<Message> = system
+        .map(|s| Message::new("system", s))
+        .into_iter()
+        .collect();
+    messages.push(Message::new("user", prompt));
+
+    println!(
+        "{:<28} {:>10} {:>10} {:>12} {:>10}",
+        "MODEL", "LATENCY", "PROMPT", "COMPLETION", "COST"
+    );
+    let mut failures = 0;
+    for model in models {
+        let started = Instant::now();
+        match client
+            .chat(&ChatRequest::new(model, &messages).with_options(options))
+            .await
+        {
+            Ok(response) => {
+                let elapsed = started.elapsed();
+                let mut usage = UsageTotals::default();
+                usage.record(model, response.usage.as_ref());
+                println!(
+                    "{:<28} {:>10} {:>10} {:>12} {:>10}",
+                    model,
+                    format!("{:.2}s", elapsed.as_secs_f64()),
+                    usage.prompt_tokens(),
+                    usage.completion_tokens(),
+                    currency.format(usage.cost(), 5)
+                );
+            }
+            Err(e) => {
+                failures += 1;
+                println!("{:<28} {:>10}", model, "failed");
+                eprintln!("{}: {}", model, e);
+            }
+        }
+    }
+
+    if failures == models.len() {
+        return Err("every benchmark request failed".into());
+    }
+    Ok(())
+}
@@ -0,0 +1,101 @@
+//! `--record` / `--replay`: saves API exchanges to a file and serves them back later, so a
+//! session can be reproduced offline without a key.
+//!
+//! The file has one JSON object per line: the request path, the request body and the raw
+//! response body.
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::sync::Mutex;
+
+/// One recorded exchange.
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    path: String,
+    request: serde_json::Value,
+    response: String,
+}
+
+pub enum Cassette {
+    Record(Mutex<File>),
+    /// The recorded entries, and whether each has been served yet.
+    Replay(Mutex<Vec<(Entry, bool)>>),
+}
+
+impl Cassette {
+    /// Appends successful exchanges to `path`, creating it if needed.
+    pub fn record(path: &str) -> Result<Cassette, Box<dyn Error>> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("could not open {}: {}", path, e))?;
+        Ok(Cassette::Record(Mutex::new(file)))
+    }
+
+    /// Loads a recording made with [`Cassette::record`].
+    pub fn replay(path: &str) -> Result<Cassette, Box<dyn Error>> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+        let mut entries = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Entry =
+                serde_json::from_str(line).map_err(|e| format!("{}:{}: {}", path, index + 1, e))?;
+            entries.push((entry, false));
+        }
+        Ok(Cassette::Replay(Mutex::new(entries)))
+    }
+
+    /// The recorded response for this request when replaying. Identical requests are
+    /// answered in the order they were recorded; once those run out the last one repeats.
+    pub fn lookup(
+        &self,
+        path: &str,
+        request: &serde_json::Value,
+    ) -> Option<Result<String, String>> {
+        let Cassette::Replay(entries) = self else {
+            return None;
+        };
+        let mut entries = entries.lock().unwrap();
+        let mut matching = entries
+            .iter_mut()
+            .filter(|(entry, _)| entry.path == path && entry.request == *request)
+            .peekable();
+        if matching.peek().is_none() {
+            return Some(Err(
+                "no recorded response matches this request (--replay never uses the network)"
+                    .to_string(),
+            ));
+        }
+        let mut last = None;
+        for (entry, served) in matching {
+            if !*served {
+                *served = true;
+                return Some(Ok(entry.response.clone()));
+            }
+            last = Some(entry.response.clone());
+        }
+        last.map(Ok)
+    }
+
+    /// Writes an exchange to the recording, when recording.
+    pub fn save(&self, path: &str, request: serde_json::Value, response: &str) {
+        let Cassette::Record(file) = self else {
+            return;
+        };
+        let entry = Entry {
+            path: path.to_string(),
+            request,
+            response: response.to_string(),
+        };
+        let line = serde_json::to_string(&entry).unwrap_or_default();
+        if let Err(e) = writeln!(file.lock().unwrap(), "{}", line) {
+            eprintln!("Could not write to the recording: {}", e);
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! `/extract-code` and `--extract-code`: writes the fenced code blocks from the replies
+//! to files, so a conversation about code ends with the code on disk.
+//!
+//! A block's file name comes from its fence (```` ```rust src/main.rs ````), a comment on
+//! its first line (`// src/main.rs`) or a name in backticks or bold on the line before it.
+//! Blocks with no name get `snippet_N` and an extension for their language. When several
+//! blocks name the same file, the last one wins.
+
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::Message;
+
+/// One fenced block and the file it is meant for, if it says.
+struct Block {
+    language: String,
+    code: String,
+    file: Option<String>,
+}
+
+/// What [`extract`] would write: each file, its contents and how many blocks named it.
+pub struct Extracted {
+    pub files: BTreeMap<PathBuf, (String, usize)>,
+}
+
+/// Collects the code blocks of the assistant messages in `conversation`, with the paths
+/// under `dir` they go to.
+pub fn extract(conversation: &[Message], dir: &Path) -> Extracted {
+    let mut files: BTreeMap<PathBuf, (String, usize)> = BTreeMap::new();
+    let mut unnamed = 0;
+    for msg in conversation.iter().filter(|m| m.role == "assistant") {
+        for block in blocks(&msg.display_content()) {
+            let name = match block.file.filter(|name| is_safe(name)) {
+                Some(name) => name,
+                None => {
+                    unnamed += 1;
+                    format!("snippet_{}.{}", unnamed, extension(&block.language))
+                }
+            };
+            let entry = files.entry(dir.join(name)).or_default();
+            entry.0 = block.code;
+            entry.1 += 1;
+        }
+    }
+    Extracted { files }
+}
+
+impl Extracted {
+    /// The files that already exist on disk and would be overwritten.
+    pub fn existing(&self) -> Vec<&PathBuf> {
+        self.files.keys().filter(|path| path.exists()).collect()
+    }
+
+    /// Writes every file, creating directories as needed, and prints what was written.
+    pub fn write(&self) -> std::io::Result<()> {
+        for (path, (code, versions)) in &self.files {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, format!("{}\n", code))?;
+            if *versions > 1 {
+                println!(
+                    "Wrote {} (the last of {} versions)",
+                    path.display(),
+                    versions
+                );
+            } else {
+                println!("Wrote {}", path.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The fenced blocks in `text`. An unclosed fence is not a block.
+fn blocks(text: &str) -> Vec<Block> {
+    let mut out = Vec::new();
+    let mut previous = "";
+    let mut open: Option<(String, Option<String>, Vec<&str>)> = None;
+    for line in text.lines() {
+        let fence = line.trim_start().strip_prefix("```");
+        match (&mut open, fence) {
+            (None, Some(info)) => {
+                let mut words = info.split_whitespace();
+                let language = words.next().unwrap_or_default().to_lowercase();
+                let named = words
+                    .chain(std::iter::once(language.as_str()))
+                    .map(|word| word.trim_start_matches("title=").trim_matches(['"', '\'']))
+                    .find(|word| looks_like_file(word))
+                    .or_else(|| named_in(previous))
+                    .map(str::to_string);
+                // A fence with only a file name (```main.rs) has no language.
+                let language = if looks_like_file(&language) {
+                    String::new()
+                } else {
+                    language
+                };
+                open = Some((language, named, Vec::new()));
+            }
+            (Some(_), Some(_)) => {
+                let (language, file, code) = open.take().unwrap();
+                let file = file.or_else(|| named_in_comment(code.first().copied()));
+                // The text before this block doesn't describe the next one.
+                previous = "";
+                out.push(Block {
+                    language,
+                    code: code.join("\n"),
+                    file,
+                });
+            }
+            (Some((_, _, code)), None) => code.push(line),
+            (None, None) => {
+                if !line.trim().is_empty() {
+                    previous = line;
+                }
+            }
+        }
+    }
+    out
+}
+
+/// A file name written in backticks or bold on the line introducing a block, such as
+/// "Update `src/main.rs`:".
+fn named_in(line: &str) -> Option<&str> {
+    line.split(['`', '*'])
+        .map(str::trim)
+        .rfind(|word| looks_like_file(word))
+}
+
+/// A file name in a comment on a block's first line, such as `// src/main.rs` or
+/// `# file: app.py`.
+fn named_in_comment(first: Option<&str>) -> Option<String> {
+    let first = first?.trim();
+    let comment = ["//", "#", "--", "/*", "<!--"]
+        .iter()
+        .find_map(|marker| first.strip_prefix(marker))?;
+    let comment = comment
+        .trim()
+        .trim_end_matches("*/")
+        .trim_end_matches("-->")
+        .trim();
+    let name = comment
+        .strip_prefix("file:")
+        .or_else(|| comment.strip_prefix("filename:"))
+        .unwrap_or(comment)
+        .trim();
+    looks_like_file(name).then(|| name.to_string())
+}
+
+/// Whether `word` reads as a file name: no spaces, and an extension after a dot.
+fn looks_like_file(word: &str) -> bool {
+    !word.is_empty()
+        && !word.contains(char::is_whitespace)
+        && Path::new(word).extension().is_some_and(|ext| {
+            let ext = ext.to_string_lossy();
+            !ext.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+/// Names from a reply are only used if they stay inside the output directory.
+fn is_safe(name: &str) -> bool {
+    Path::new(name)
+        .components()
+        .all(|part| matches!(part, Component::Normal(_) | Component::CurDir))
+}
+
+/// The usual extension for a fence language.
+fn extension(language: &str) -> &str {
+    match language {
+        "rust" => "rs",
+        "python" => "py",
+        "javascript" | "node" => "js",
+        "typescript" => "ts",
+        "c++" => "cpp",
+        "bash" | "shell" | "zsh" | "console" => "sh",
+        "ruby" => "rb",
+        "markdown" => "md",
+        "csharp" | "c#" => "cs",
+        "yml" => "yaml",
+        "text" | "plaintext" => "txt",
+        other if !other.is_empty() && other.chars().all(|c| c.is_ascii_alphanumeric()) => other,
+        _ => "txt",
+    }
+}
@@ -0,0 +1,66 @@
+//! `--format-code`: runs fenced code blocks in replies through a formatter before they
+//! are shown. The stored reply is never changed.
+
+use std::collections::BTreeMap;
+
+use crate::hooks;
+
+/// Formatter used for a fence language when the config's `[formatters]` has none.
+fn default_formatter(language: &str) -> Option<&'static str> {
+    match language {
+        "rust" | "rs" => Some("rustfmt --edition 2024"),
+        "go" => Some("gofmt"),
+        "python" | "py" => Some("black -q -"),
+        _ => None,
+    }
+}
+
+/// Reformats every code block whose language has a formatter, which reads the code on
+/// stdin and prints the result. Blocks that fail to format are shown as they were, with
+/// a note on stderr.
+pub async fn format_blocks(text: &str, formatters: &BTreeMap<String, String>) -> String {
+    let mut out = Vec::new();
+    let mut block: Option<(String, Vec<&str>)> = None;
+    for line in text.lines() {
+        let fence = line.trim_start().strip_prefix("```");
+        match (&mut block, fence) {
+            (None, Some(language)) => {
+                out.push(line.to_string());
+                block = Some((language.trim().to_lowercase(), Vec::new()));
+            }
+            (Some(_), Some(_)) => {
+                let (language, code) = block.take().unwrap();
+                let code = code.join("\n");
+                out.push(format_one(&language, &code, formatters).await);
+                out.push(line.to_string());
+            }
+            (Some((_, code)), None) => code.push(line),
+            (None, None) => out.push(line.to_string()),
+        }
+    }
+    // An unclosed fence is left alone.
+    if let Some((_, code)) = block {
+        out.extend(code.iter().map(|line| line.to_string()));
+    }
+    out.join("\n")
+}
+
+async fn format_one(language: &str, code: &str, formatters: &BTreeMap<String, String>) -> String {
+    let command = match formatters.get(language) {
+        Some(command) => command.as_str(),
+        None => match default_formatter(language) {
+            Some(command) => command,
+            None => return code.to_string(),
+        },
+    };
+    match hooks::run("formatter", command, &format!("{}\n", code)).await {
+        Ok(formatted) if !formatted.trim().is_empty() => formatted.trim_end().to_string(),
+        Ok(_) => code.to_string(),
+        Err(e) => {
+            let error = e.to_string();
+            let first_line = error.lines().next().unwrap_or_default();
+            eprintln!("Could not format the {} block: {}", language, first_line);
+            code.to_string()
+        }
+    }
+}
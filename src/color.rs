@@ -0,0 +1,54 @@
+//! Colors for the role labels, from the config file's `[colors]` section, e.g.
+//! `assistant = "bold cyan"` or `user = "#87afd7"`.
+
+/// The roles whose labels can be colored.
+pub const ROLES: &[&str] = &["user", "assistant"];
+
+/// Turns a color spec into the parameters of an SGR escape sequence. A spec is one or more
+/// words: a color name (`red`, `bright-blue`, ...), a 256-color number, a `#rrggbb` hex
+/// color, or one of `bold`, `dim`, `italic` and `underline`.
+pub fn parse(spec: &str) -> Result<String, String> {
+    const NAMES: [&str; 8] = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+    let mut codes = Vec::new();
+    for word in spec.split_whitespace() {
+        let word = word.to_lowercase();
+        let code = match word.as_str() {
+            "bold" => "1".to_string(),
+            "dim" => "2".to_string(),
+            "italic" => "3".to_string(),
+            "underline" => "4".to_string(),
+            _ => {
+                if let Some(index) = NAMES.iter().position(|name| *name == word) {
+                    (30 + index).to_string()
+                } else if let Some(index) = word
+                    .strip_prefix("bright-")
+                    .and_then(|name| NAMES.iter().position(|n| *n == name))
+                {
+                    (90 + index).to_string()
+                } else if let Ok(number) = word.parse::<u8>() {
+                    format!("38;5;{}", number)
+                } else if let Some(hex) = word
+                    .strip_prefix('#')
+                    .filter(|hex| hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+                {
+                    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+                    format!("38;2;{};{};{}", channel(0), channel(2), channel(4))
+                } else {
+                    return Err(format!("unknown color '{}'", word));
+                }
+            }
+        };
+        codes.push(code);
+    }
+    if codes.is_empty() {
+        return Err("empty color".to_string());
+    }
+    Ok(codes.join(";"))
+}
+
+/// `text` in the color given by `sgr`, from [`parse`].
+pub fn paint(text: &str, sgr: &str) -> String {
+    format!("\x1b[{}m{}\x1b[0m", sgr, text)
+}
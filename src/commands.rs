@@ -0,0 +1,175 @@
+/// A slash command typed at the prompt in place of a message.
+#[derive(Debug)]
+pub enum Command {
+    /// `/replay <MODEL>`: re-run every user message against another model.
+    Replay(String),
+    /// `/summarize`: fold the whole conversation into the summary now and print it.
+    Summarize,
+    /// `/summary [edit]`: print the summary, or open it in `$EDITOR` to correct it.
+    Summary { edit: bool },
+    /// `/resummarize <FROM> <TO>`: rebuild the summary from messages FROM..=TO (by id).
+    Resummarize(u64, u64),
+    /// `/rebuild-summary`: regenerate the summary in one pass from every summarized message.
+    RebuildSummary,
+    /// `/model [MODEL]`: switch the model used for the rest of the session, or show it.
+    Model(Option<String>),
+    /// `/next-model`: switch to the next of the `--favorite-models`.
+    NextModel,
+    /// `/export <PATH>`: save the conversation so far, as Markdown if PATH ends in `.md`.
+    Export(String),
+    /// `/extract-code <DIR>`: write the code blocks from the replies to files in DIR.
+    ExtractCode(String),
+    /// `/pin [TEXT]`: pin a fact so it is always in the context, or list the pins.
+    Pin(Option<String>),
+    /// `/unpin [N]`: remove pin number N, or all pins.
+    Unpin(Option<usize>),
+    /// `/ask <TEXT>`: send TEXT with the current context, but keep neither it nor the reply.
+    Ask(String),
+    /// `/run <CMD>`: run a shell command, after confirmation, and send its output.
+    Run(String),
+    /// `/template [NAME key=value ...]`: send a filled-in template, or list the templates.
+    Template(String),
+    /// `/delete <ID>`: remove the message with this id from the conversation.
+    Delete(u64),
+    /// `/context-add <TEXT_OR_FILE>`: stage context for the next request only.
+    ContextAdd(String),
+    /// `/fetch <URL>`: stage the readable text of a web page for the next request only.
+    Fetch(String),
+    /// `/rehydrate [off]`: send the whole conversation instead of the summary while it fits
+    /// the model's context window, or go back to the summary.
+    Rehydrate(bool),
+    /// `/attach <PATH>`: upload a file and send it with the next message.
+    Attach(String),
+    /// `/compact <N>`: replace the oldest N messages with one summary message, for good.
+    Compact(usize),
+    /// `/checkpoint [NAME]`: remember the conversation as it is now under NAME, or list the
+    /// checkpoints.
+    Checkpoint(Option<String>),
+    /// `/diff <A> <B>`: show which messages differ between two checkpoints; `now` is the
+    /// conversation as it is.
+    Diff(String, String),
+    /// `/push-system <TEXT>`: use TEXT as the system prompt until the matching `/pop-system`.
+    PushSystem(String),
+    /// `/pop-system`: go back to the system prompt from before the last `/push-system`.
+    PopSystem,
+    /// `/info`: show the model, the system prompt in effect and the size of the session.
+    Info,
+    /// `/merge`: combine adjacent messages with the same role for good.
+    Merge,
+    /// `/developer <TEXT>`: add a developer instruction to the conversation.
+    Developer(String),
+    /// `/temp <T> <MESSAGE>`: send one message with temperature T.
+    Temp(String, String),
+    /// `/continue`: ask for the rest of a streamed reply that broke off partway.
+    Continue,
+}
+
+/// Stands for the current conversation in `/diff`.
+pub const NOW: &str = "now";
+
+/// Parses `input` as a slash command. Returns `None` for ordinary messages, including ones
+/// that start with a slash but no command name, such as a path, and ones escaped with `//`.
+pub fn parse(input: &str) -> Option<Result<Command, String>> {
+    let rest = input.strip_prefix('/')?;
+    if rest.starts_with('/') {
+        return None;
+    }
+    let (name, arg) = match rest.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (rest, ""),
+    };
+
+    Some(match name {
+        "replay" if !arg.is_empty() => Ok(Command::Replay(arg.to_string())),
+        "replay" => Err("Usage: /replay <MODEL>".to_string()),
+        "summarize" => Ok(Command::Summarize),
+        "summary" if arg.is_empty() => Ok(Command::Summary { edit: false }),
+        "summary" if arg == "edit" => Ok(Command::Summary { edit: true }),
+        "summary" => Err("Usage: /summary [edit]".to_string()),
+        "resummarize" => {
+            let ids: Vec<Result<u64, _>> = arg
+                .split_whitespace()
+                .map(|id| id.trim_start_matches('#').parse())
+                .collect();
+            match ids.as_slice() {
+                [Ok(from), Ok(to)] => Ok(Command::Resummarize(*from, *to)),
+                _ => Err("Usage: /resummarize <FROM> <TO>".to_string()),
+            }
+        }
+        "rebuild-summary" => Ok(Command::RebuildSummary),
+        "model" if !arg.is_empty() => Ok(Command::Model(Some(arg.to_string()))),
+        "model" => Ok(Command::Model(None)),
+        "next-model" => Ok(Command::NextModel),
+        "export" if !arg.is_empty() => Ok(Command::Export(arg.to_string())),
+        "export" => Err("Usage: /export <PATH>".to_string()),
+        "extract-code" if !arg.is_empty() => Ok(Command::ExtractCode(arg.to_string())),
+        "extract-code" => Err("Usage: /extract-code <DIR>".to_string()),
+        "pin" if !arg.is_empty() => Ok(Command::Pin(Some(arg.to_string()))),
+        "pin" => Ok(Command::Pin(None)),
+        "run" if !arg.is_empty() => Ok(Command::Run(arg.to_string())),
+        "run" => Err("Usage: /run <CMD>".to_string()),
+        "delete" => match arg.trim_start_matches('#').parse() {
+            Ok(id) => Ok(Command::Delete(id)),
+            Err(_) => Err("Usage: /delete <ID>".to_string()),
+        },
+        "context-add" if !arg.is_empty() => Ok(Command::ContextAdd(arg.to_string())),
+        "context-add" => Err("Usage: /context-add <TEXT_OR_FILE>".to_string()),
+        "ask" if !arg.is_empty() => Ok(Command::Ask(arg.to_string())),
+        "ask" => Err("Usage: /ask <TEXT>".to_string()),
+        "fetch" if !arg.is_empty() => Ok(Command::Fetch(arg.to_string())),
+        "fetch" => Err("Usage: /fetch <URL>".to_string()),
+        "rehydrate" if arg.is_empty() => Ok(Command::Rehydrate(true)),
+        "rehydrate" if arg == "off" => Ok(Command::Rehydrate(false)),
+        "rehydrate" => Err("Usage: /rehydrate [off]".to_string()),
+        "attach" if !arg.is_empty() => Ok(Command::Attach(arg.to_string())),
+        "attach" => Err("Usage: /attach <PATH>".to_string()),
+        "compact" => match arg.parse() {
+            Ok(n) if n > 1 => Ok(Command::Compact(n)),
+            _ => Err("Usage: /compact <N>, with N at least 2".to_string()),
+        },
+        "checkpoint" if arg.is_empty() => Ok(Command::Checkpoint(None)),
+        "checkpoint" if arg.contains(char::is_whitespace) || arg == NOW => Err(format!(
+            "Usage: /checkpoint [NAME], with NAME one word other than '{}'",
+            NOW
+        )),
+        "checkpoint" => Ok(Command::Checkpoint(Some(arg.to_string()))),
+        "diff" => match arg.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [a, b] => Ok(Command::Diff(a.to_string(), b.to_string())),
+            _ => Err(format!(
+                "Usage: /diff <A> <B> (checkpoint names, or '{}')",
+                NOW
+            )),
+        },
+        "push-system" if !arg.is_empty() => Ok(Command::PushSystem(arg.to_string())),
+        "push-system" => Err("Usage: /push-system <TEXT>".to_string()),
+        "pop-system" => Ok(Command::PopSystem),
+        "info" => Ok(Command::Info),
+        "merge" => Ok(Command::Merge),
+        "developer" if !arg.is_empty() => Ok(Command::Developer(arg.to_string())),
+        "developer" => Err("Usage: /developer <TEXT>".to_string()),
+        "temp" => match arg.split_once(char::is_whitespace) {
+            Some((value, message)) if !message.trim().is_empty() => {
+                Ok(Command::Temp(value.to_string(), message.trim().to_string()))
+            }
+            _ => Err("Usage: /temp <T> <MESSAGE>".to_string()),
+        },
+        "template" => Ok(Command::Template(arg.to_string())),
+        "continue" => Ok(Command::Continue),
+        "unpin" if arg.is_empty() => Ok(Command::Unpin(None)),
+        "unpin" => match arg.parse() {
+            Ok(n) if n > 0 => Ok(Command::Unpin(Some(n))),
+            _ => Err("Usage: /unpin [N]".to_string()),
+        },
+        _ => return None,
+    })
+}
+
+/// The message to send for `input`: a leading `//` stands for a single `/`, so that a
+/// message can start with a command name.
+pub fn unescape(input: &str) -> &str {
+    if input.starts_with("//") {
+        &input[1..]
+    } else {
+        input
+    }
+}
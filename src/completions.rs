@@ -0,0 +1,356 @@
+//! Shell completion scripts generated from the `Args` definition.
+
+use clap::{Arg, ValueEnum, ValueHint};
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+const BIN_NAME: &str = env!("CARGO_BIN_NAME");
+
+/// Builds the completion script for `shell` from the visible arguments of `cmd`.
+pub fn generate(shell: Shell, cmd: &clap::Command) -> String {
+    let args: Vec<&Arg> = cmd
+        .get_arguments()
+        .filter(|a| !a.is_hide_set() && a.get_long().is_some())
+        .collect();
+    match shell {
+        Shell::Bash => bash(&args),
+        Shell::Zsh => zsh(&args),
+        Shell::Fish => fish(&args),
+        Shell::Powershell => powershell(&args),
+    }
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    arg.get_action().takes_values()
+}
+
+fn help(arg: &Arg) -> String {
+    arg.get_help()
+        .map(|h| h.to_string())
+        .unwrap_or_default()
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Options whose value is a path get filename completion.
+fn takes_path(arg: &Arg) -> bool {
+    matches!(
+        arg.get_value_hint(),
+        ValueHint::AnyPath | ValueHint::FilePath | ValueHint::DirPath
+    ) || arg.get_value_names().is_some_and(|names| {
+        names
+            .iter()
+            .any(|n| matches!(n.as_str(), "PATH" | "FILE" | "DIR"))
+    })
+}
+
+fn possible_values(arg: &Arg) -> Vec<String> {
+    arg.get_possible_values()
+        .iter()
+        .filter(|v| !v.is_hide_set())
+        .map(|v| v.get_name().to_string())
+        .collect()
+}
+
+fn bash(args: &[&Arg]) -> String {
+    let mut words = Vec::new();
+    let mut cases = String::new();
+    for arg in args {
+        let long = format!("--{}", arg.get_long().unwrap());
+        let mut names = vec![long.clone()];
+        if let Some(short) = arg.get_short() {
+            names.push(format!("-{}", short));
+        }
+        if takes_value(arg) {
+            let values = possible_values(arg);
+            let reply = if !values.is_empty() {
+                format!(
+                    "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                    values.join(" ")
+                )
+            } else if takes_path(arg) {
+                "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string()
+            } else {
+                "COMPREPLY=()".to_string()
+            };
+            let _ = writeln!(
+                cases,
+                "        {})\n            {}\n            return 0\n            ;;",
+                names.join("|"),
+                reply
+            );
+        }
+        words.extend(names);
+    }
+
+    format!(
+        r#"_{bin}() {{
+    local cur prev
+    cur="${{COMP_WORDS[COMP_CWORD]}}"
+    prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    case "$prev" in
+{cases}    esac
+    COMPREPLY=($(compgen -W "{words}" -- "$cur"))
+}}
+complete -F _{bin} -o bashdefault -o default {bin}
+"#,
+        bin = BIN_NAME,
+        cases = cases,
+        words = words.join(" ")
+    )
+}
+
+fn zsh_escape(text: &str) -> String {
+    text.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+fn zsh(args: &[&Arg]) -> String {
+    let mut specs = String::new();
+    for arg in args {
+        let long = arg.get_long().unwrap();
+        let value = if takes_value(arg) {
+            let values = possible_values(arg);
+            if !values.is_empty() {
+                format!(":{}:({})", long, values.join(" "))
+            } else if takes_path(arg) {
+                format!(":{}:_files", long)
+            } else {
+                format!(":{}:", long)
+            }
+        } else {
+            String::new()
+        };
+        // Repeatable options may appear many times; others exclude their own aliases.
+        let prefix = match (arg.get_action(), arg.get_short()) {
+            (clap::ArgAction::Append, _) => "*".to_string(),
+            (_, Some(short)) => format!("(-{} --{})", short, long),
+            (_, None) => String::new(),
+        };
+        let names = match arg.get_short() {
+            Some(short) => format!("'{}'{{-{},--{}}}'", prefix, short, long),
+            None => format!("'{}--{}", prefix, long),
+        };
+        let _ = writeln!(
+            specs,
+            "    {}[{}]{}' \\",
+            names,
+            zsh_escape(&help(arg)),
+            value
+        );
+    }
+
+    format!(
+        "#compdef {bin}\n\n_arguments -s \\\n{specs}    && return 0\n",
+        bin = BIN_NAME,
+        specs = specs
+    )
+}
+
+fn fish(args: &[&Arg]) -> String {
+    let mut out = String::new();
+    for arg in args {
+        let _ = write!(
+            out,
+            "complete -c {} -l {}",
+            BIN_NAME,
+            arg.get_long().unwrap()
+        );
+        if let Some(short) = arg.get_short() {
+            let _ = write!(out, " -s {}", short);
+        }
+        if takes_value(arg) {
+            let values = possible_values(arg);
+            if !values.is_empty() {
+                let _ = write!(out, " -r -f -a \"{}\"", values.join(" "));
+            } else if takes_path(arg) {
+                out.push_str(" -r -F");
+            } else {
+                out.push_str(" -r -f");
+            }
+        }
+        let help = help(arg);
+        if !help.is_empty() {
+            let _ = write!(
+                out,
+                " -d '{}'",
+                help.replace('\\', "\\\\").replace('\'', "\\'")
+            );
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn powershell(args: &[&Arg]) -> String {
+    let mut results = String::new();
+    let mut cases = String::new();
+    for arg in args {
+        let long = arg.get_long().unwrap();
+        let help = help(arg).replace('\'', "''");
+        let mut names = vec![format!("--{}", long)];
+        if let Some(short) = arg.get_short() {
+            names.push(format!("-{}", short));
+        }
+        let values = possible_values(arg);
+        for name in names {
+            if takes_value(arg) && !values.is_empty() {
+                let quoted: Vec<String> = values.iter().map(|v| format!("'{}'", v)).collect();
+                let _ = writeln!(cases, "        '{}' {{ {} }}", name, quoted.join(", "));
+            }
+            let _ = writeln!(
+                results,
+                "        [CompletionResult]::new('{}', '{}', [CompletionResultType]::ParameterName, '{}')",
+                name,
+                long,
+                if help.is_empty() { long } else { &help }
+            );
+        }
+    }
+
+    format!(
+        r#"using namespace System.Management.Automation
+
+Register-ArgumentCompleter -Native -CommandName '{bin}' -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $previous = $commandAst.CommandElements |
+        Where-Object {{ $_.Extent.EndOffset -lt $cursorPosition }} |
+        Select-Object -Last 1
+    $values = switch -Exact -CaseSensitive ("$previous") {{
+{cases}    }}
+    if ($values) {{
+        $values | Where-Object {{ $_ -like "$wordToComplete*" }} |
+            ForEach-Object {{ [CompletionResult]::new($_, $_, [CompletionResultType]::ParameterValue, $_) }}
+        return
+    }}
+    @(
+{results}    ) | Where-Object {{ $_.CompletionText -like "$wordToComplete*" }}
+}}
+"#,
+        bin = BIN_NAME,
+        cases = cases,
+        results = results
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    /// The visible long options, the ones every script has to offer.
+    fn visible(cmd: &clap::Command) -> Vec<&Arg> {
+        cmd.get_arguments()
+            .filter(|a| !a.is_hide_set() && a.get_long().is_some())
+            .collect()
+    }
+
+    /// Whether `script` offers `--long` and, right where it completes its value, every
+    /// possible value.
+    fn completes(shell: Shell, script: &str, arg: &Arg) -> bool {
+        let long = arg.get_long().unwrap();
+        let values = if takes_value(arg) {
+            possible_values(arg)
+        } else {
+            Vec::new()
+        };
+        let (flag, listed) = match shell {
+            Shell::Bash => (
+                format!("--{}", long),
+                format!("compgen -W \"{}\"", values.join(" ")),
+            ),
+            Shell::Zsh => (
+                format!("--{}", long),
+                format!(":{}:({})", long, values.join(" ")),
+            ),
+            Shell::Fish => (
+                format!("-l {}", long),
+                format!("-a \"{}\"", values.join(" ")),
+            ),
+            Shell::Powershell => (
+                format!("'--{}'", long),
+                values
+                    .iter()
+                    .map(|v| format!("'{}'", v))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+        };
+        // Each entry starts with the flag and lists its values within the next line.
+        script.match_indices(&flag).any(|(at, _)| {
+            let rest = &script[at + flag.len()..];
+            let bounded = rest.starts_with(|c: char| !c.is_ascii_alphanumeric() && c != '-');
+            let entry: String = rest.split_inclusive('\n').take(2).collect();
+            bounded && (values.is_empty() || entry.contains(&listed))
+        })
+    }
+
+    #[test]
+    fn every_script_completes_every_flag_and_value() {
+        let cmd = crate::Args::command();
+        let args = visible(&cmd);
+        assert!(
+            args.iter()
+                .any(|a| takes_value(a) && !possible_values(a).is_empty())
+        );
+        for &shell in Shell::value_variants() {
+            let script = generate(shell, &cmd);
+            for arg in &args {
+                assert!(
+                    completes(shell, &script, arg),
+                    "{:?} doesn't complete --{}",
+                    shell,
+                    arg.get_long().unwrap()
+                );
+            }
+        }
+    }
+
+    /// Runs `shell -n` on the script for `kind`, if that shell is installed.
+    fn syntax_check(shell: &str, kind: Shell) {
+        let installed = std::process::Command::new(shell)
+            .args(["-c", "exit 0"])
+            .status()
+            .is_ok_and(|status| status.success());
+        if !installed {
+            eprintln!("{} is not installed; skipping its syntax check", shell);
+            return;
+        }
+        let path =
+            std::env::temp_dir().join(format!("gpt-completions-{}-{}", std::process::id(), shell));
+        std::fs::write(&path, generate(kind, &crate::Args::command())).unwrap();
+        let output = std::process::Command::new(shell)
+            .arg("-n")
+            .arg(&path)
+            .output()
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(
+            output.status.success(),
+            "{} -n rejected the script: {}",
+            shell,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    #[test]
+    fn bash_accepts_its_script() {
+        syntax_check("bash", Shell::Bash);
+    }
+
+    #[test]
+    fn zsh_accepts_its_script() {
+        syntax_check("zsh", Shell::Zsh);
+    }
+}
@@ -0,0 +1,428 @@
+//! The optional config file, `~/.config/rust_gpt_cli/config.toml` by default.
+//!
+//! Only the subset of TOML the config needs is understood: `[section]` headers and
+//! `key = value` pairs whose values are strings, numbers, booleans or arrays of those.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::color;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// Section name -> key -> value. Keys before the first header live in the `""` section.
+pub type Table = BTreeMap<String, BTreeMap<String, Value>>;
+
+#[derive(Debug)]
+pub struct ConfigError {
+    path: PathBuf,
+    line: usize,
+    message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.path.display(), self.line, self.message)
+    }
+}
+
+impl Error for ConfigError {}
+
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Used when `OPENAI_API_KEY` isn't set.
+    pub api_key: Option<String>,
+    /// Keys used in turn, one per request, to spread rate limits. Replaces `api_key`.
+    pub api_keys: Vec<String>,
+    /// Keys for particular models (or aliases), from the `[keys]` section.
+    pub model_keys: BTreeMap<String, String>,
+    /// Model used when `--model` isn't given.
+    pub model: Option<String>,
+    /// System prompt used when neither `--system` nor `--system-file` is given.
+    pub system: Option<String>,
+    /// Short names for models, from the `[aliases]` section.
+    pub aliases: BTreeMap<String, String>,
+    /// Prompt templates, from the `[templates]` section.
+    pub templates: BTreeMap<String, String>,
+    /// Code formatter commands by fence language, from the `[formatters]` section.
+    pub formatters: BTreeMap<String, String>,
+    /// Role label colors, as SGR parameters, from the `[colors]` section.
+    pub colors: BTreeMap<String, String>,
+}
+
+/// The config file used when `--config` isn't given.
+pub fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+/// Writes `value` as a TOML basic string.
+pub fn quote(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Directory holding the config file and other per-user state.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("rust_gpt_cli"))
+}
+
+impl Config {
+    /// Loads the config from `path`, or from the default location when `path` is `None`.
+    /// A missing default config file is not an error.
+    pub fn load(path: Option<&str>) -> Result<Config, Box<dyn Error>> {
+        let (path, required) = match path {
+            Some(path) => (PathBuf::from(path), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => {
+                return Ok(Config::default());
+            }
+            Err(e) => return Err(format!("{}: {}", path.display(), e).into()),
+        };
+        let table = parse(&text, &path)?;
+        Config::from_table(&table, &path)
+    }
+
+    fn from_table(table: &Table, path: &Path) -> Result<Config, Box<dyn Error>> {
+        let mut config = Config::default();
+        if let Some(root) = table.get("") {
+            let string = |key: &str| -> Result<Option<String>, String> {
+                match root.get(key) {
+                    Some(value) => value
+                        .as_str()
+                        .map(|s| Some(expand_env(s)))
+                        .ok_or_else(|| format!("{}: '{}' must be a string", path.display(), key)),
+                    None => Ok(None),
+                }
+            };
+            config.api_key = string("api_key")?;
+            if let Some(value) = root.get("api_keys") {
+                let keys: Option<Vec<&str>> = match value {
+                    Value::Array(items) => items.iter().map(Value::as_str).collect(),
+                    _ => None,
+                };
+                config.api_keys = keys
+                    .ok_or_else(|| {
+                        format!("{}: 'api_keys' must be an array of strings", path.display())
+                    })?
+                    .into_iter()
+                    .map(expand_env)
+                    .collect();
+            }
+            config.model = string("model")?;
+            config.system = string("system")?;
+        }
+        if let Some(aliases) = table.get("aliases") {
+            for (name, value) in aliases {
+                let model = value.as_str().ok_or_else(|| {
+                    format!("{}: alias '{}' must be a string", path.display(), name)
+                })?;
+                config.aliases.insert(name.clone(), expand_env(model));
+            }
+        }
+        if let Some(keys) = table.get("keys") {
+            for (model, value) in keys {
+                let key = value.as_str().ok_or_else(|| {
+                    format!("{}: key for '{}' must be a string", path.display(), model)
+                })?;
+                config.model_keys.insert(model.clone(), expand_env(key));
+            }
+        }
+        if let Some(templates) = table.get("templates") {
+            for (name, value) in templates {
+                let template = value.as_str().ok_or_else(|| {
+                    format!("{}: template '{}' must be a string", path.display(), name)
+                })?;
+                config.templates.insert(name.clone(), expand_env(template));
+            }
+        }
+        if let Some(formatters) = table.get("formatters") {
+            for (language, value) in formatters {
+                let command = value.as_str().ok_or_else(|| {
+                    format!(
+                        "{}: formatter '{}' must be a string",
+                        path.display(),
+                        language
+                    )
+                })?;
+                config
+                    .formatters
+                    .insert(language.to_lowercase(), expand_env(command));
+            }
+        }
+        if let Some(colors) = table.get("colors") {
+            for (role, value) in colors {
+                if !color::ROLES.contains(&role.as_str()) {
+                    return Err(format!(
+                        "{}: [colors] has no '{}'; colors can be set for {}",
+                        path.display(),
+                        role,
+                        color::ROLES.join(" and ")
+                    )
+                    .into());
+                }
+                let sgr = value
+                    .as_str()
+                    .ok_or_else(|| format!("{}: color '{}' must be a string", path.display(), role))
+                    .and_then(|spec| {
+                        color::parse(spec)
+                            .map_err(|e| format!("{}: color '{}': {}", path.display(), role, e))
+                    })?;
+                config.colors.insert(role.clone(), sgr);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Expands a model alias to the full model id. Unknown names are returned unchanged.
+    pub fn resolve_model(&self, name: &str) -> String {
+        self.aliases
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+}
+
+/// Expands `$VAR`, `${VAR}` and a leading `~/` from the environment. Unset variables are
+/// left as written. Only applied to paths and config values, never to message text.
+pub fn expand_env(input: &str) -> String {
+    let mut out = String::new();
+    let mut rest = input;
+    if let Some(tail) = rest.strip_prefix("~/")
+        && let Some(home) = std::env::var_os("HOME")
+    {
+        out.push_str(&home.to_string_lossy());
+        out.push('/');
+        rest = tail;
+    }
+
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        let (name, len) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+        let valid = name
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+        match std::env::var(name) {
+            Ok(value) if valid => out.push_str(&value),
+            _ => out.push_str(&rest[pos..pos + 1 + len]),
+        }
+        rest = &rest[pos + 1 + len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Parses the TOML subset described in the module docs.
+pub fn parse(text: &str, path: &Path) -> Result<Table, ConfigError> {
+    let mut table = Table::new();
+    let mut section = String::new();
+    table.insert(section.clone(), BTreeMap::new());
+
+    for (index, raw_line) in text.lines().enumerate() {
+        let error = |message: String| ConfigError {
+            path: path.to_path_buf(),
+            line: index + 1,
+            message,
+        };
+        let line = strip_comment(raw_line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let name = header
+                .strip_suffix(']')
+                .ok_or_else(|| error("unclosed section header".to_string()))?
+                .trim();
+            if name.is_empty() {
+                return Err(error("empty section name".to_string()));
+            }
+            section = unquote_key(name);
+            table.entry(section.clone()).or_default();
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected 'key = value'".to_string()))?;
+        let key = unquote_key(key.trim());
+        if key.is_empty() {
+            return Err(error("empty key".to_string()));
+        }
+        let mut chars = value.trim().chars().peekable();
+        let value = parse_value(&mut chars).map_err(&error)?;
+        if chars.any(|c| !c.is_whitespace()) {
+            return Err(error("unexpected text after value".to_string()));
+        }
+        table.get_mut(&section).unwrap().insert(key, value);
+    }
+    Ok(table)
+}
+
+/// Removes a trailing `# comment`, ignoring `#` inside quoted strings.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' && q == '"' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+fn unquote_key(key: &str) -> String {
+    key.trim_matches(|c| c == '"' || c == '\'').to_string()
+}
+
+fn parse_value(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<Value, String> {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+    match chars.peek() {
+        Some('"') => {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => return Ok(Value::String(s)),
+                    Some('\\') => match chars.next() {
+                        Some('n') => s.push('\n'),
+                        Some('t') => s.push('\t'),
+                        Some('r') => s.push('\r'),
+                        Some('"') => s.push('"'),
+                        Some('\\') => s.push('\\'),
+                        Some(c) => return Err(format!("unknown escape '\\{}'", c)),
+                        None => return Err("unterminated string".to_string()),
+                    },
+                    Some(c) => s.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+        }
+        Some('\'') => {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some('\'') => return Ok(Value::String(s)),
+                    Some(c) => s.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+        }
+        Some('[') => {
+            chars.next();
+            let mut items = Vec::new();
+            loop {
+                while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                    chars.next();
+                }
+                match chars.peek() {
+                    Some(']') => {
+                        chars.next();
+                        return Ok(Value::Array(items));
+                    }
+                    None => return Err("unterminated array".to_string()),
+                    Some(_) => {}
+                }
+                items.push(parse_value(chars)?);
+                while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                    chars.next();
+                }
+                match chars.next() {
+                    Some(',') => {}
+                    Some(']') => return Ok(Value::Array(items)),
+                    _ => return Err("expected ',' or ']' in array".to_string()),
+                }
+            }
+        }
+        Some(_) => {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c == ',' || c == ']' || c.is_whitespace() {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            match word.as_str() {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                _ => {
+                    let number = word.replace('_', "");
+                    if let Ok(i) = number.parse() {
+                        Ok(Value::Integer(i))
+                    } else if let Ok(f) = number.parse() {
+                        Ok(Value::Float(f))
+                    } else {
+                        Err(format!("invalid value '{}'", word))
+                    }
+                }
+            }
+        }
+        None => Err("missing value".to_string()),
+    }
+}
@@ -0,0 +1,61 @@
+//! `/diff`: which messages differ between two checkpoints of the conversation.
+
+use crate::{Message, format_id};
+
+/// How far each message is previewed in a diff.
+const PREVIEW_CHARS: usize = 70;
+
+/// Renders the difference between the conversations `old` and `new` in unified-diff style:
+/// one line per message, `-` for messages only in `old`, `+` for those only in `new` and a
+/// space for those in both. Messages are the same if role and content match.
+pub fn render(old_name: &str, old: &[Message], new_name: &str, new: &[Message]) -> String {
+    let same = |a: &Message, b: &Message| a.role == b.role && a.content == b.content;
+
+    // Longest common subsequence, filled from the end so it can be walked forwards.
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if same(&old[i], &new[j]) {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && same(&old[i], &new[j]) {
+            out.push_str(&line(' ', &new[j]));
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || common[i][j + 1] >= common[i + 1][j]) {
+            out.push_str(&line('+', &new[j]));
+            j += 1;
+        } else {
+            out.push_str(&line('-', &old[i]));
+            i += 1;
+        }
+    }
+    out
+}
+
+fn line(marker: char, message: &Message) -> String {
+    let content = message.display_content();
+    let first = content.lines().next().unwrap_or_default();
+    let preview: String = first.chars().take(PREVIEW_CHARS).collect();
+    let ellipsis = if preview.len() < content.trim_end().len() {
+        "…"
+    } else {
+        ""
+    };
+    format!(
+        "{} {}{}: {}{}\n",
+        marker,
+        format_id(message),
+        message.role,
+        preview,
+        ellipsis
+    )
+}
@@ -0,0 +1,46 @@
+//! Per-message parameter overrides written at the start of a message, e.g.
+//! `!temperature=0.9 write me a poem`. They apply to that one request and are removed from
+//! the text before it is sent or stored.
+
+use crate::api::RequestOptions;
+
+/// Strips leading `!name=value` directives from `input`, applying them to `options`.
+/// Returns the rest of the message.
+pub fn strip<'a>(input: &'a str, options: &mut RequestOptions) -> Result<&'a str, String> {
+    let mut rest = input.trim_start();
+    while let Some(directive) = rest.strip_prefix('!') {
+        let end = directive
+            .find(char::is_whitespace)
+            .unwrap_or(directive.len());
+        let Some((name, value)) = directive[..end].split_once('=') else {
+            break;
+        };
+        apply(name, value, options)?;
+        rest = directive[end..].trim_start();
+    }
+    Ok(rest)
+}
+
+/// Sets one parameter by name, as used by directives and `/temp`.
+pub fn apply(name: &str, value: &str, options: &mut RequestOptions) -> Result<(), String> {
+    let number = |max: f32| -> Result<f32, String> {
+        match value.parse::<f32>() {
+            Ok(n) if (0.0..=max).contains(&n) => Ok(n),
+            _ => Err(format!(
+                "{} must be a number between 0 and {}, not '{}'",
+                name, max, value
+            )),
+        }
+    };
+    match name {
+        "temperature" | "temp" => options.temperature = Some(number(2.0)?),
+        "top_p" => options.top_p = Some(number(1.0)?),
+        _ => {
+            return Err(format!(
+                "unknown directive '!{}' (expected temperature or top_p)",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
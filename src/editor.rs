@@ -0,0 +1,39 @@
+//! Editing text in the user's `$VISUAL` / `$EDITOR`.
+
+use std::error::Error;
+
+use crate::hooks;
+
+/// Editor used when neither `VISUAL` nor `EDITOR` is set.
+const DEFAULT_EDITOR: &str = if cfg!(windows) { "notepad" } else { "vi" };
+
+/// Opens `text` in the editor and returns the saved result. `name` ends up in the temporary
+/// file's name, so editors can tell what they are editing.
+pub async fn edit(name: &str, text: &str) -> Result<String, Box<dyn Error>> {
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_EDITOR.to_string());
+    let path = std::env::temp_dir().join(format!("gpt_{}_{}.txt", name, std::process::id()));
+    std::fs::write(&path, text)?;
+
+    let command = if cfg!(windows) {
+        format!("{} \"{}\"", editor, path.display())
+    } else {
+        format!(
+            "{} '{}'",
+            editor,
+            path.display().to_string().replace('\'', "'\\''")
+        )
+    };
+    let status = hooks::shell(&command).status().await;
+    let edited = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+
+    let status = status.map_err(|e| format!("could not start editor '{}': {}", editor, e))?;
+    if !status.success() {
+        return Err(format!("editor '{}' exited with {}", editor, status).into());
+    }
+    Ok(edited?)
+}
@@ -0,0 +1,370 @@
+//! `/fetch <URL>`: downloads a web page and stages its readable text for the next message,
+//! the same way `/context-add` stages a file, so a page can be asked about inline.
+//!
+//! Only text is read: HTML loses its markup, scripts and styles, plain text and JSON are
+//! kept as they are, and anything else (images, PDFs, archives) is refused.
+
+use std::time::Duration;
+
+/// How long a fetch may take in all, redirects included.
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How much of the body is downloaded; the rest of a bigger page is left off.
+const MAX_BYTES: usize = 2_000_000;
+
+/// How much readable text is staged, so one page can't crowd out the conversation.
+pub const MAX_CHARS: usize = 40_000;
+
+/// Elements whose contents are never readable text.
+const SKIPPED: &[&str] = &[
+    "script", "style", "noscript", "template", "svg", "head", "iframe", "canvas",
+];
+
+/// Elements that start a new line of text.
+const BLOCKS: &[&str] = &[
+    "p",
+    "div",
+    "br",
+    "tr",
+    "section",
+    "article",
+    "header",
+    "footer",
+    "nav",
+    "aside",
+    "main",
+    "ul",
+    "ol",
+    "table",
+    "blockquote",
+    "pre",
+    "hr",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "dt",
+    "dd",
+    "figcaption",
+];
+
+/// A fetched page, reduced to text.
+pub struct Page {
+    pub title: Option<String>,
+    pub text: String,
+    pub content_type: String,
+    /// Whether the page was cut at [`MAX_BYTES`] or [`MAX_CHARS`].
+    pub truncated: bool,
+}
+
+/// Downloads `url` and extracts its text. A URL without a scheme is fetched over HTTPS.
+pub async fn fetch(url: &str) -> Result<Page, String> {
+    let url = if url.contains("://") {
+        url.to_string()
+    } else {
+        format!("https://{}", url)
+    };
+    let url =
+        reqwest::Url::parse(&url).map_err(|e| format!("{} is not a valid URL: {}", url, e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!(
+            "only http and https URLs can be fetched, not {}",
+            url
+        ));
+    }
+    let client = reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .user_agent(concat!("rust_gpt_cli/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let fail = |e: reqwest::Error| {
+        if e.is_timeout() {
+            format!("{} took longer than {}s", url, TIMEOUT.as_secs())
+        } else {
+            format!("could not fetch {}: {}", url, e)
+        }
+    };
+
+    let mut response = client.get(url.clone()).send().await.map_err(fail)?;
+    if !response.status().is_success() {
+        return Err(format!("{} answered {}", url, response.status()));
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_lowercase()
+        })
+        .unwrap_or_default();
+    let html = match content_type.as_str() {
+        "text/html" | "application/xhtml+xml" => true,
+        "" => false,
+        kind if kind.starts_with("text/") || kind.ends_with("json") || kind.ends_with("xml") => {
+            false
+        }
+        kind => {
+            return Err(format!(
+                "{} is {}, not a web page or text, so there is nothing to read",
+                url, kind
+            ));
+        }
+    };
+
+    let mut body = Vec::new();
+    let mut truncated = false;
+    while let Some(chunk) = response.chunk().await.map_err(fail)? {
+        body.extend_from_slice(&chunk);
+        if body.len() >= MAX_BYTES {
+            body.truncate(MAX_BYTES);
+            truncated = true;
+            break;
+        }
+    }
+    // Without a content type, the body has to say what it is.
+    if content_type.is_empty() && body.contains(&0) {
+        return Err(format!("{} doesn't look like text", url));
+    }
+    let body = String::from_utf8_lossy(&body);
+    let html = html || (content_type.is_empty() && body.trim_start().starts_with('<'));
+
+    let (title, text) = if html {
+        (title(&body), readable(&body))
+    } else {
+        (None, body.trim().to_string())
+    };
+    if text.is_empty() {
+        return Err(format!("{} has no readable text", url));
+    }
+    let (text, cut) = cut(text);
+    truncated |= cut;
+    Ok(Page {
+        title,
+        text,
+        content_type: if content_type.is_empty() {
+            "unknown type".to_string()
+        } else {
+            content_type
+        },
+        truncated,
+    })
+}
+
+/// `text` cut to [`MAX_CHARS`] characters, and whether anything was left off.
+fn cut(text: String) -> (String, bool) {
+    match text.char_indices().nth(MAX_CHARS) {
+        Some((end, _)) => (text[..end].to_string(), true),
+        None => (text, false),
+    }
+}
+
+/// The contents of the page's `<title>`, if it has one.
+fn title(html: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let start = lower.find("<title")?;
+    let start = start + lower[start..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+    let title = collapse(&entities(&html[start..end]));
+    (!title.is_empty()).then_some(title)
+}
+
+/// The text of an HTML document: tags, comments and non-text elements are removed, block
+/// elements start new lines and entities are decoded.
+fn readable(html: &str) -> String {
+    let lower = html.to_ascii_lowercase();
+    let mut out = String::new();
+    let mut i = 0;
+    while let Some(offset) = lower[i..].find('<') {
+        let tag_start = i + offset;
+        out.push_str(&entities(&html[i..tag_start]));
+        if lower[tag_start..].starts_with("<!--") {
+            i = lower[tag_start..]
+                .find("-->")
+                .map_or(lower.len(), |end| tag_start + end + 3);
+            continue;
+        }
+        let Some(tag_end) = lower[tag_start..].find('>').map(|end| tag_start + end + 1) else {
+            break;
+        };
+        let tag = &lower[tag_start + 1..tag_end - 1];
+        let closing = tag.starts_with('/');
+        let self_closing = tag.ends_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect();
+        i = tag_end;
+        if !closing && !self_closing && SKIPPED.contains(&name.as_str()) {
+            // Skip to the matching close tag, or to the end if there is none.
+            let close = format!("</{}", name);
+            i = lower[i..].find(&close).map_or(lower.len(), |end| {
+                let end = i + end;
+                lower[end..]
+                    .find('>')
+                    .map_or(lower.len(), |gt| end + gt + 1)
+            });
+        } else if name == "li" && !closing {
+            out.push_str("\n- ");
+        } else if BLOCKS.contains(&name.as_str()) {
+            out.push('\n');
+        } else if matches!(name.as_str(), "td" | "th") && !closing {
+            out.push(' ');
+        }
+    }
+    if i < html.len() && !lower[i..].contains('<') {
+        out.push_str(&entities(&html[i..]));
+    }
+
+    let mut text = String::new();
+    let mut blank = false;
+    for line in out.lines().map(collapse) {
+        if line.is_empty() || line == "-" {
+            blank = !text.is_empty();
+            continue;
+        }
+        if blank {
+            text.push('\n');
+            blank = false;
+        }
+        text.push_str(&line);
+        text.push('\n');
+    }
+    text.trim_end().to_string()
+}
+
+/// `text` with every run of whitespace turned into one space.
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Decodes the common named entities and numeric character references in `text`.
+fn entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| {
+                let name = &rest[1..end + 1];
+                let c = match name {
+                    "amp" => Some('&'),
+                    "lt" => Some('<'),
+                    "gt" => Some('>'),
+                    "quot" => Some('"'),
+                    "apos" => Some('\''),
+                    "nbsp" => Some(' '),
+                    "mdash" => Some('—'),
+                    "ndash" => Some('–'),
+                    "hellip" => Some('…'),
+                    "copy" => Some('©'),
+                    _ => name
+                        .strip_prefix("#x")
+                        .or_else(|| name.strip_prefix("#X"))
+                        .map(|hex| u32::from_str_radix(hex, 16))
+                        .or_else(|| name.strip_prefix('#').map(str::parse))
+                        .and_then(Result::ok)
+                        .and_then(char::from_u32),
+                };
+                c.map(|c| (c, end + 2))
+            });
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_scripts_and_styles() {
+        let html = "<p>before</p><script>var x = '<p>no</p>';</script><STYLE>p { color: red }</STYLE><p>after</p>";
+        assert_eq!(readable(html), "before\n\nafter");
+    }
+
+    #[test]
+    fn skips_comments() {
+        assert_eq!(readable("one <!-- <p>hidden</p> --> two"), "one two");
+        assert_eq!(readable("one <!-- never closed <p>two</p>"), "one");
+    }
+
+    #[test]
+    fn block_elements_start_new_lines() {
+        let html = "<h1>Title</h1><div>first<br>second</div><ul><li>a</li><li>b</li></ul><span>inline</span> text";
+        assert_eq!(
+            readable(html),
+            "Title\n\nfirst\nsecond\n\n- a\n- b\ninline text"
+        );
+    }
+
+    #[test]
+    fn blank_lines_are_collapsed() {
+        assert_eq!(
+            readable("<p>one</p>\n\n\n<p></p><p>  two   words </p>"),
+            "one\n\ntwo words"
+        );
+    }
+
+    #[test]
+    fn decodes_entities() {
+        assert_eq!(
+            entities("a &amp; b &lt;c&gt; &quot;d&quot;"),
+            "a & b <c> \"d\""
+        );
+        assert_eq!(entities("&#65;&#x42;&#X43; &mdash; &hellip;"), "ABC — …");
+        assert_eq!(
+            entities("&bogus; & &#xZZ; &#1114112;"),
+            "&bogus; & &#xZZ; &#1114112;"
+        );
+        assert_eq!(
+            entities("fish &chips and more text; later"),
+            "fish &chips and more text; later"
+        );
+    }
+
+    #[test]
+    fn unterminated_tag_ends_the_text() {
+        assert_eq!(readable("<p>kept</p><a href=\"x"), "kept");
+        assert_eq!(readable("plain text only"), "plain text only");
+    }
+
+    #[test]
+    fn reads_the_title() {
+        let html =
+            "<html><head><TITLE lang=en> A &amp;\n B </TITLE></head><body>body</body></html>";
+        assert_eq!(title(html).as_deref(), Some("A & B"));
+        assert_eq!(readable(html), "body");
+        assert_eq!(title("<title> </title>"), None);
+        assert_eq!(title("<p>no title</p>"), None);
+    }
+
+    #[test]
+    fn cuts_long_text() {
+        let short = "é".repeat(MAX_CHARS);
+        assert_eq!(cut(short.clone()), (short, false));
+        let (text, truncated) = cut("é".repeat(MAX_CHARS + 5));
+        assert!(truncated);
+        assert_eq!(text.chars().count(), MAX_CHARS);
+    }
+}
@@ -0,0 +1,541 @@
+//! Just enough gzip for session files: a compressor using LZ77 with the fixed Huffman
+//! codes, and a full decompressor so files written by the `gzip` tool load too.
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which code length code lengths are stored in a dynamic block header.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// How many earlier positions with the same hash are tried when looking for a match.
+const MAX_CHAIN: usize = 64;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut c = i as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+        *entry = c;
+    }
+    !data.iter().fold(!0u32, |crc, &b| {
+        table[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Whether `data` starts with the gzip magic number.
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&[0x1F, 0x8B])
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are packed starting from their most significant bit.
+    fn write_code(&mut self, code: u32, bits: u32) {
+        self.write(code.reverse_bits() >> (32 - bits), bits);
+    }
+
+    fn literal(&mut self, symbol: u32) {
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+/// Compresses `data` into a gzip file.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter {
+        out: vec![0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, 0xFF],
+        buffer: 0,
+        count: 0,
+    };
+    // A single final block with the fixed codes.
+    writer.write(1, 1);
+    writer.write(1, 2);
+
+    let hash = |i: usize| {
+        ((data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize) & 0x7FFF
+    };
+    let mut head = vec![usize::MAX; 0x8000];
+    let mut prev = vec![usize::MAX; WINDOW];
+    let insert = |i: usize, head: &mut [usize], prev: &mut [usize]| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(i);
+            prev[i % WINDOW] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash(i)];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW && chain < MAX_CHAIN {
+                let max = MAX_MATCH.min(data.len() - i);
+                let len = (0..max)
+                    .take_while(|&k| data[candidate + k] == data[i + k])
+                    .count();
+                if len > best.0 {
+                    best = (len, i - candidate);
+                    if len == max {
+                        break;
+                    }
+                }
+                let next = prev[candidate % WINDOW];
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        let (len, dist) = best;
+        if len >= MIN_MATCH {
+            let code = LENGTH_BASE
+                .iter()
+                .rposition(|&b| b as usize <= len)
+                .unwrap();
+            writer.literal(257 + code as u32);
+            writer.write(
+                (len - LENGTH_BASE[code] as usize) as u32,
+                LENGTH_EXTRA[code] as u32,
+            );
+            let code = DIST_BASE.iter().rposition(|&b| b as usize <= dist).unwrap();
+            writer.write_code(code as u32, 5);
+            writer.write(
+                (dist - DIST_BASE[code] as usize) as u32,
+                DIST_EXTRA[code] as u32,
+            );
+            for j in i..i + len {
+                insert(j, &mut head, &mut prev);
+            }
+            i += len;
+        } else {
+            writer.literal(data[i] as u32);
+            insert(i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+    writer.literal(256);
+
+    let mut out = writer.finish();
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> Result<u32, String> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self.data.get(self.pos).ok_or("unexpected end of data")?;
+            value |= (((byte >> self.bit) & 1) as u32) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit > 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+/// A canonical Huffman code: how many codes have each length, and the symbols in
+/// code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = Vec::with_capacity(lengths.len());
+        for len in 1..16 {
+            for (symbol, &l) in lengths.iter().enumerate() {
+                if l as usize == len {
+                    symbols.push(symbol as u16);
+                }
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literals = reader.bits(5)? as usize + 257;
+    let distances = reader.bits(5)? as usize + 1;
+    let code_lengths = reader.bits(4)? as usize + 4;
+    let mut lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[i] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&lengths);
+
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (value, repeat) = match code_length_code.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths.last().ok_or("repeat with no previous length")?;
+                (previous, 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat));
+    }
+    if lengths.len() > literals + distances {
+        return Err("code lengths overflow the header".to_string());
+    }
+    Ok((
+        Huffman::new(&lengths[..literals]),
+        Huffman::new(&lengths[literals..]),
+    ))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let code = symbol - 257;
+                let (&base, &extra) = LENGTH_BASE
+                    .get(code)
+                    .zip(LENGTH_EXTRA.get(code))
+                    .ok_or("invalid length code")?;
+                let len = base as usize + reader.bits(extra as u32)? as usize;
+                let code = distances.decode(reader)? as usize;
+                let (&base, &extra) = DIST_BASE
+                    .get(code)
+                    .zip(DIST_EXTRA.get(code))
+                    .ok_or("invalid distance code")?;
+                let dist = base as usize + reader.bits(extra as u32)? as usize;
+                if dist > out.len() {
+                    return Err("distance reaches before the start of the data".to_string());
+                }
+                let start = out.len() - dist;
+                for i in 0..len {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+}
+
+/// Decompresses a gzip file.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 18 || !is_gzip(data) || data[2] != 8 {
+        return Err("not a gzip file".to_string());
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & 4 != 0 {
+        let extra = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
+        pos += 2 + extra;
+    }
+    for flag in [8, 16] {
+        if flags & flag != 0 {
+            let end = data
+                .get(pos..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+                .ok_or("bad header")?;
+            pos += end + 1;
+        }
+    }
+    if flags & 2 != 0 {
+        pos += 2;
+    }
+
+    let mut reader = BitReader {
+        data: data.get(pos..).ok_or("bad header")?,
+        pos: 0,
+        bit: 0,
+    };
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header = reader
+                    .data
+                    .get(reader.pos..reader.pos + 4)
+                    .ok_or("unexpected end of data")?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                reader.pos += 4;
+                let stored = reader
+                    .data
+                    .get(reader.pos..reader.pos + len)
+                    .ok_or("unexpected end of data")?;
+                out.extend_from_slice(stored);
+                reader.pos += len;
+            }
+            1 => {
+                let (literals, distances) = fixed_codes();
+                inflate_block(&mut reader, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &literals, &distances)?;
+            }
+            _ => return Err("invalid block type".to_string()),
+        }
+        if last {
+            break;
+        }
+    }
+
+    reader.align();
+    let trailer = reader
+        .data
+        .get(reader.pos..reader.pos + 8)
+        .ok_or("missing gzip trailer")?;
+    let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    if crc != crc32(&out) {
+        return Err("checksum mismatch; the file is corrupt".to_string());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `gzip -0` output for "stored block": a single stored block.
+    const STORED: [u8; 35] = [
+        0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x03, 0x01, 0x0C, 0x00, 0xF3, 0xFF,
+        0x73, 0x74, 0x6F, 0x72, 0x65, 0x64, 0x20, 0x62, 0x6C, 0x6F, 0x63, 0x6B, 0x94, 0xA3, 0x24,
+        0x3D, 0x0C, 0x00, 0x00, 0x00,
+    ];
+
+    const DYNAMIC_TEXT: &str = "Sessions are saved as JSON. A session holds the model, the summary, how many messages were summarized, and the conversation itself. Compressed sessions are gzip files; the summary and the conversation are saved the same way.";
+
+    /// `gzip -9` output for [`DYNAMIC_TEXT`]: a single block with dynamic Huffman codes.
+    const DYNAMIC: [u8; 154] = [
+        0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x6D, 0x8E, 0x4D, 0x0E, 0x82,
+        0x30, 0x14, 0x84, 0xAF, 0x32, 0x07, 0x20, 0x5C, 0xC0, 0x95, 0x71, 0xE7, 0x42, 0x17, 0x9C,
+        0xE0, 0xC5, 0x3E, 0xA0, 0x49, 0x5F, 0x4B, 0x3A, 0x08, 0x81, 0xD3, 0x5B, 0x6B, 0x8C, 0x2C,
+        0xDC, 0x4D, 0xF2, 0xCD, 0x5F, 0xA7, 0xA4, 0x4F, 0x91, 0x90, 0xAC, 0xA0, 0x2C, 0xEA, 0x20,
+        0xC4, 0xB5, 0xBB, 0xDF, 0x5A, 0x9C, 0xC1, 0x0F, 0xC5, 0x98, 0x82, 0x23, 0xE6, 0x51, 0x61,
+        0xC9, 0x69, 0x68, 0xAA, 0xE4, 0xD3, 0x4C, 0xF2, 0xD6, 0x14, 0xBA, 0xC2, 0x24, 0x6E, 0xB0,
+        0x62, 0x97, 0x41, 0x89, 0x55, 0xF3, 0x97, 0xFB, 0x5D, 0x5D, 0x03, 0x89, 0xAE, 0x66, 0x1E,
+        0x29, 0x2E, 0x9A, 0x29, 0xF3, 0xBB, 0xD5, 0xCF, 0xD4, 0xD0, 0xB7, 0xB8, 0x24, 0x9B, 0x72,
+        0x89, 0x96, 0x6D, 0x1E, 0xEF, 0x0C, 0xBB, 0x9F, 0xD0, 0xFB, 0xA0, 0x3C, 0x1D, 0x07, 0xFF,
+        0x97, 0xFD, 0xFE, 0x57, 0xAB, 0x98, 0x62, 0x95, 0xAD, 0x7D, 0x01, 0xEA, 0x86, 0x76, 0xE8,
+        0xE0, 0x00, 0x00, 0x00,
+    ];
+
+    fn round_trip(data: &[u8]) {
+        let compressed = compress(data);
+        assert!(is_gzip(&compressed));
+        assert_eq!(decompress(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn round_trips_empty_input() {
+        round_trip(b"");
+    }
+
+    #[test]
+    fn round_trips_text() {
+        round_trip(b"a");
+        round_trip(DYNAMIC_TEXT.as_bytes());
+        round_trip("caf\u{e9} \u{1F600} \u{4F60}\u{597D}".as_bytes());
+    }
+
+    #[test]
+    fn round_trips_long_matches() {
+        round_trip(&vec![b'x'; 100_000]);
+        round_trip(&b"abc".repeat(10_000));
+    }
+
+    #[test]
+    fn round_trips_data_larger_than_the_window() {
+        // A simple LCG: little to match, and matches at every distance.
+        let mut state = 1u32;
+        let data: Vec<u8> = (0..3 * WINDOW)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                b"abcdefgh"[(state >> 16) as usize % 8]
+            })
+            .collect();
+        round_trip(&data);
+    }
+
+    #[test]
+    fn fixed_huffman_block() {
+        let compressed = compress(b"fixed codes");
+        assert_eq!(
+            compressed[10] & 0b111,
+            0b011,
+            "final block with the fixed codes"
+        );
+        assert_eq!(decompress(&compressed).unwrap(), b"fixed codes");
+    }
+
+    #[test]
+    fn stored_block() {
+        assert_eq!(decompress(&STORED).unwrap(), b"stored block");
+    }
+
+    #[test]
+    fn dynamic_huffman_block() {
+        assert_eq!(DYNAMIC[10] >> 1 & 0b11, 2);
+        assert_eq!(decompress(&DYNAMIC).unwrap(), DYNAMIC_TEXT.as_bytes());
+    }
+
+    #[test]
+    fn skips_the_file_name() {
+        let mut named = STORED.to_vec();
+        named[3] = 8;
+        named.splice(10..10, b"session.json\0".iter().copied());
+        assert_eq!(decompress(&named).unwrap(), b"stored block");
+    }
+
+    #[test]
+    fn rejects_corrupt_headers() {
+        assert!(decompress(b"{\"version\": 2}").is_err());
+        assert!(decompress(&STORED[..12]).is_err());
+
+        let mut method = STORED;
+        method[2] = 7;
+        assert_eq!(decompress(&method).unwrap_err(), "not a gzip file");
+
+        // An extra field longer than the file, followed by a file name.
+        let mut extra = STORED;
+        extra[3] = 4 | 8;
+        extra[10..12].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert!(decompress(&extra).is_err());
+
+        // A file name that is never terminated.
+        let mut name = STORED.to_vec();
+        name[3] = 8;
+        name.truncate(10);
+        name.extend_from_slice(&[b'x'; 20]);
+        assert!(decompress(&name).is_err());
+    }
+
+    #[test]
+    fn rejects_a_bad_crc() {
+        let mut compressed = compress(b"checksummed");
+        let crc = compressed.len() - 8;
+        compressed[crc] ^= 1;
+        assert_eq!(
+            decompress(&compressed).unwrap_err(),
+            "checksum mismatch; the file is corrupt"
+        );
+    }
+
+    #[test]
+    fn corrupt_data_is_an_error_not_a_panic() {
+        for (i, bit) in (10..DYNAMIC.len() - 8).zip((0..8).cycle()) {
+            let mut corrupt = DYNAMIC;
+            corrupt[i] ^= 1 << bit;
+            // A flip in the padding after the last block changes nothing.
+            if let Ok(out) = decompress(&corrupt) {
+                assert_eq!(out, DYNAMIC_TEXT.as_bytes(), "byte {} bit {}", i, bit);
+            }
+        }
+        let compressed = compress(DYNAMIC_TEXT.as_bytes());
+        assert!(decompress(&compressed[..compressed.len() - 9]).is_err());
+    }
+}
@@ -0,0 +1,54 @@
+//! External `--pre-hook` / `--post-hook` commands run around each turn.
+
+use std::error::Error;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// A command that runs `command` through the platform shell.
+pub fn shell(command: &str) -> Command {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+    cmd.arg(command);
+    cmd
+}
+
+/// Runs `command` through the shell with `input` on stdin and returns its stdout. A
+/// non-zero exit status is an error carrying whatever the hook wrote to stderr.
+pub async fn run(name: &str, command: &str, input: &str) -> Result<String, Box<dyn Error>> {
+    let mut child = shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not start {} '{}': {}", name, command, e))?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_string();
+    // Feed stdin concurrently so a hook that writes before it finishes reading can't
+    // deadlock against us.
+    let writer = tokio::spawn(async move {
+        let _ = stdin.write_all(input.as_bytes()).await;
+    });
+    let output = child.wait_with_output().await?;
+    let _ = writer.await;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = stderr.trim();
+        return Err(if stderr.is_empty() {
+            format!("{} failed ({})", name, output.status)
+        } else {
+            format!("{} failed ({}): {}", name, output.status, stderr)
+        }
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,142 @@
+//! `--import FORMAT PATH`: turns a conversation exported from another tool into a session,
+//! so it can be continued here. Each format has its own parser; add new ones to [`import`].
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+use crate::Message;
+use crate::session::Session;
+
+/// The formats `--import` understands.
+pub const FORMATS: &[&str] = &["chatgpt"];
+
+/// Parses `text`, written by the tool named by `format`, into a new session for `model`.
+pub fn import(format: &str, text: &str, model: &str) -> Result<Session, String> {
+    let mut session = Session::new(model);
+    match format {
+        "chatgpt" => chatgpt(text, &mut session)?,
+        _ => {
+            return Err(format!(
+                "unknown import format '{}' (expected one of: {})",
+                format,
+                FORMATS.join(", ")
+            ));
+        }
+    }
+    if session.conversation.is_empty() {
+        return Err("the export has no messages".to_string());
+    }
+    Ok(session)
+}
+
+/// ChatGPT's data export: `conversations.json` holds an array of conversations, or a single
+/// one. Each conversation is a tree of messages (edits and regenerations branch it) under
+/// `mapping`, and `current_node` is the last message of the branch that was on screen, so
+/// that branch is followed back to the root. From an array, the most recently updated
+/// conversation is taken.
+fn chatgpt(text: &str, session: &mut Session) -> Result<(), String> {
+    let json: Value = serde_json::from_str(text).map_err(|e| format!("not valid JSON: {}", e))?;
+    let conversation = match &json {
+        Value::Array(conversations) => {
+            let latest = conversations
+                .iter()
+                .max_by(|a, b| time(a, "update_time").total_cmp(&time(b, "update_time")))
+                .ok_or("the export has no conversations")?;
+            if conversations.len() > 1 {
+                eprintln!(
+                    "The export has {} conversations; importing the most recent one.",
+                    conversations.len()
+                );
+            }
+            latest
+        }
+        single => single,
+    };
+    let mapping = conversation
+        .get("mapping")
+        .and_then(Value::as_object)
+        .ok_or("not a ChatGPT export (no 'mapping')")?;
+
+    let mut branch = Vec::new();
+    let mut node = conversation.get("current_node").and_then(Value::as_str);
+    while let Some(id) = node {
+        let entry = mapping
+            .get(id)
+            .ok_or_else(|| format!("message '{}' is missing", id))?;
+        branch.push(entry);
+        node = entry.get("parent").and_then(Value::as_str);
+        if branch.len() > mapping.len() {
+            return Err("the message tree has a cycle".to_string());
+        }
+    }
+    branch.reverse();
+
+    session.title = conversation
+        .get("title")
+        .and_then(Value::as_str)
+        .map(str::to_string);
+    for entry in branch {
+        let Some(message) = entry.get("message").filter(|m| !m.is_null()) else {
+            continue;
+        };
+        let role = message
+            .pointer("/author/role")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let hidden = message
+            .pointer("/metadata/is_visually_hidden_from_conversation")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        // Tool calls and their results only make sense to ChatGPT itself.
+        if hidden || !matches!(role, "user" | "assistant" | "system") {
+            continue;
+        }
+        let content = parts(message);
+        if content.trim().is_empty() {
+            continue;
+        }
+        if role == "system" && session.conversation.is_empty() && session.system.is_none() {
+            session.system = Some(content);
+            continue;
+        }
+        let timestamp = message
+            .get("create_time")
+            .and_then(Value::as_f64)
+            .and_then(|secs| DateTime::<Utc>::from_timestamp_millis((secs * 1000.0) as i64));
+        let model = message
+            .pointer("/metadata/model_slug")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        session.push(Message {
+            timestamp: timestamp.or(Some(Utc::now())),
+            model: model.filter(|_| role == "assistant"),
+            ..Message::new(role, content)
+        });
+    }
+    Ok(())
+}
+
+/// The text parts of a ChatGPT message joined together. Images, files and other parts
+/// that aren't plain strings are left out.
+fn parts(message: &Value) -> String {
+    let content = message.get("content");
+    if let Some(text) = content.and_then(|c| c.get("text")).and_then(Value::as_str) {
+        return text.to_string();
+    }
+    content
+        .and_then(|c| c.get("parts"))
+        .and_then(Value::as_array)
+        .map(|parts| {
+            parts
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join("\n\n")
+        })
+        .unwrap_or_default()
+}
+
+/// A Unix timestamp field in seconds, as ChatGPT writes them; 0 when missing.
+fn time(value: &Value, field: &str) -> f64 {
+    value.get(field).and_then(Value::as_f64).unwrap_or_default()
+}
@@ -0,0 +1,92 @@
+//! Reading messages from stdin. Lines are read as bytes and decoded here, so input that
+//! isn't valid UTF-8, such as a latin-1 log or a binary file, is handled as
+//! `--on-invalid-utf8` says instead of ending the program with an opaque error.
+
+use serde::Serialize;
+use tokio::io::{self, AsyncBufReadExt, BufReader, Stdin};
+
+/// What to do with input that isn't valid UTF-8.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum InvalidUtf8 {
+    /// Replace the invalid bytes with U+FFFD and warn once.
+    #[default]
+    Lossy,
+    /// Stop with an error naming the line and the offending byte.
+    Error,
+}
+
+/// The lines of stdin, decoded according to an [`InvalidUtf8`] policy.
+pub struct Lines {
+    reader: BufReader<Stdin>,
+    policy: InvalidUtf8,
+    // Kept between calls so a read cancelled by `select!` loses nothing.
+    buf: Vec<u8>,
+    line: usize,
+    warned: bool,
+}
+
+impl Lines {
+    pub fn new(policy: InvalidUtf8) -> Self {
+        Lines {
+            reader: BufReader::new(io::stdin()),
+            policy,
+            buf: Vec::new(),
+            line: 0,
+            warned: false,
+        }
+    }
+
+    /// The next line without its line ending, or `None` at the end of input. Invalid
+    /// UTF-8 under [`InvalidUtf8::Error`] is an `InvalidData` error.
+    pub async fn next_line(&mut self) -> io::Result<Option<String>> {
+        if self.reader.read_until(b'\n', &mut self.buf).await? == 0 {
+            return Ok(None);
+        }
+        let mut bytes = std::mem::take(&mut self.buf);
+        if bytes.ends_with(b"\n") {
+            bytes.pop();
+            if bytes.ends_with(b"\r") {
+                bytes.pop();
+            }
+        }
+        self.line += 1;
+        decode(bytes, self.policy, "stdin", self.line, &mut self.warned)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Decodes `bytes` read from `source`, whose first line is line `first_line`. Lossy
+/// decoding warns on stderr unless `warned` says it already has.
+pub fn decode(
+    bytes: Vec<u8>,
+    policy: InvalidUtf8,
+    source: &str,
+    first_line: usize,
+    warned: &mut bool,
+) -> Result<String, String> {
+    let e = match String::from_utf8(bytes) {
+        Ok(text) => return Ok(text),
+        Err(e) => e,
+    };
+    let valid = e.utf8_error().valid_up_to();
+    let bytes = e.as_bytes();
+    let line = first_line + bytes[..valid].iter().filter(|&&b| b == b'\n').count();
+    match policy {
+        InvalidUtf8::Lossy => {
+            if !*warned {
+                eprintln!(
+                    "Warning: line {} of {} is not valid UTF-8; invalid bytes are replaced with U+FFFD.",
+                    line, source
+                );
+                *warned = true;
+            }
+            Ok(String::from_utf8_lossy(bytes).into_owned())
+        }
+        InvalidUtf8::Error => Err(format!(
+            "line {} of {} is not valid UTF-8 (byte 0x{:02x}); pass --on-invalid-utf8 lossy to replace invalid bytes",
+            line, source, bytes[valid]
+        )),
+    }
+}
@@ -0,0 +1,43 @@
+//! The API keys requests are sent with: usually just one, but a config file can list several
+//! in `api_keys` to take turns, and give particular models their own key in `[keys]`.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug, Default)]
+pub struct Keyring {
+    /// Keys used in turn for models without a key of their own.
+    rotation: Vec<String>,
+    /// Model id -> key.
+    by_model: BTreeMap<String, String>,
+    next: AtomicUsize,
+}
+
+impl Keyring {
+    pub fn new(rotation: Vec<String>, by_model: BTreeMap<String, String>) -> Self {
+        Keyring {
+            rotation,
+            by_model,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// True if there is no key at all.
+    pub fn is_empty(&self) -> bool {
+        self.rotation.is_empty() && self.by_model.is_empty()
+    }
+
+    /// The key for the next request to `model`: the model's own key if it has one, else the
+    /// next key in the rotation. Requests that aren't for a model (listing models, uploads)
+    /// just take the next key. Empty when there are no keys, as when replaying.
+    pub fn key_for(&self, model: Option<&str>) -> &str {
+        if let Some(key) = model.and_then(|model| self.by_model.get(model)) {
+            return key;
+        }
+        if self.rotation.is_empty() {
+            return "";
+        }
+        let turn = self.next.fetch_add(1, Ordering::Relaxed);
+        &self.rotation[turn % self.rotation.len()]
+    }
+}
@@ -1,57 +1,609 @@
-use clap::Parser;
-use chrono::Utc;
-use reqwest::header::{CONTENT_TYPE, AUTHORIZATION};
+use clap::{CommandFactory, FromArgMatches, Parser};
+use clap::parser::ValueSource;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use api::{ApiClient, ApiMessage, ChatChoice, ChatRequest, RequestOptions};
+use commands::Command;
+use session::Session;
+use usage::UsageTotals;
 use std::error::Error;
-use std::io::Write;
-use tokio::io::{self, AsyncBufReadExt, BufReader};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::io;
 
-#[derive(Parser, Debug)]
+// First, so its `print!` and `println!` replace the standard ones everywhere below.
+#[macro_use]
+mod stdout;
+mod api;
+#[cfg(feature = "audio")]
+mod audio;
+mod batch;
+mod benchmark;
+mod cassette;
+mod codeblocks;
+mod codefmt;
+mod color;
+mod commands;
+mod completions;
+mod config;
+mod diff;
+mod directives;
+mod editor;
+mod fetch;
+mod gzip;
+mod hooks;
+mod import;
+mod input;
+mod keyring;
+mod manifest;
+mod markdown;
+mod mock;
+mod models;
+mod plain;
+mod prompt;
+mod redact;
+mod regex;
+mod session;
+mod sink;
+#[cfg(feature = "serve")]
+mod serve;
+mod setup;
+mod template;
+mod tokens;
+mod transcript;
+mod tts;
+mod tui;
+mod typewriter;
+mod usage;
+mod wrap;
+
+#[derive(Parser, Debug, Serialize)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// The model to use for the conversation (default: gpt-4o).
+    /// The model to use for the conversation (default: the --provider's, gpt-4o for OpenAI).
     #[arg(short, long, default_value = "gpt-4o")]
     model: String,
+    /// The service requests go to, which picks the model and --base-url when they aren't set.
+    #[arg(long, value_enum, value_name = "PROVIDER", default_value = "openai")]
+    provider: Provider,
     /// Enable debug mode to generate additional files for testing.
     #[arg(short, long, action)]
     debug: bool,
+    /// Comma-separated roles to write to the --debug transcript, e.g. user,assistant to leave
+    /// out the system prompt and other scaffolding (default: all of them).
+    #[arg(long, value_name = "ROLES", value_delimiter = ',', requires = "debug", value_parser = ["system", "developer", "user", "assistant", "tool"])]
+    transcript_include: Vec<String>,
+    /// List the chat-capable models available to your API key and exit.
+    #[arg(long, action)]
+    list_models: bool,
+    /// List the sessions saved under the sessions directory, newest first, and exit. With
+    /// --tag, only those carrying every given tag.
+    #[arg(long, action)]
+    list_sessions: bool,
+    /// Before a new session is first saved, ask the summary model for a short title, which
+    /// is saved with it and put in its file name. Costs one extra request.
+    #[arg(long, action)]
+    auto_title: bool,
+    /// Start from a conversation exported by another tool, e.g. `--import chatgpt
+    /// conversations.json`. It is saved as a new session, or to --session.
+    #[arg(long, num_args = 2, value_names = ["FORMAT", "PATH"], conflicts_with_all = ["resume", "stateless"])]
+    import: Vec<String>,
+    /// Label the session so --list-sessions can find it (repeatable).
+    #[arg(long = "tag", value_name = "TAG")]
+    tags: Vec<String>,
+    /// Show the time each reply was received.
+    #[arg(long, action)]
+    timestamps: bool,
+    /// Seconds to wait for a summary update before giving up and keeping the previous summary.
+    #[arg(long, default_value_t = 30)]
+    summary_timeout: u64,
+    /// Print only the reply content, without the model prefix or the blank line after it.
+    #[arg(long, action)]
+    raw: bool,
+    /// Trim leading/trailing whitespace from replies before printing (default: on, off with --raw).
+    /// The untrimmed reply is always what's kept in the conversation.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    trim_replies: Option<bool>,
+    /// Write a JSON manifest describing the session (models, timing, usage, settings) on exit.
+    #[arg(long, value_name = "PATH")]
+    manifest: Option<String>,
+    /// Replace matches of this regex with [REDACTED] in everything you send: messages, staged
+    /// context, text attachments, pins and system prompts (repeatable).
+    #[arg(long = "redact-pattern", value_name = "REGEX")]
+    redact_patterns: Vec<String>,
+    /// Save and exit after this many seconds without input (disabled by default).
+    #[arg(long, value_name = "SECONDS")]
+    idle_timeout: Option<u64>,
+    /// Once a reply has taken this many seconds, show --thinking-text and the time elapsed
+    /// until it arrives. Handy for reasoning models that think for a long time.
+    #[arg(long, value_name = "SECONDS")]
+    thinking_delay: Option<f64>,
+    /// Ask before sending input with a line longer than this many characters, to catch
+    /// giant accidental pastes. 0 turns the check off; piped input is never checked.
+    #[arg(long, value_name = "CHARS", default_value_t = 10_000)]
+    max_line_length: usize,
+    /// What to do when a reply comes back empty: ask whether to retry (at a terminal),
+    /// retry once, or drop it. The empty reply is never kept.
+    #[arg(long, value_enum, value_name = "ACTION", default_value = "ask")]
+    on_empty_reply: EmptyReply,
+    /// For a message too big for the model's context window on its own: which part to keep
+    /// when truncating it to fit. At a terminal you are asked first.
+    #[arg(long, value_enum, value_name = "MODE", default_value = "both")]
+    truncate_mode: tokens::TruncateMode,
+    /// Ask before sending a message identical to the previous one, in case it was sent
+    /// twice by accident.
+    #[arg(long, action)]
+    confirm_duplicates: bool,
+    /// Ask once, before the first message to a model whose output price is at least
+    /// --expensive-threshold, whether to go ahead. Only at a terminal.
+    #[arg(long, action)]
+    confirm_expensive: bool,
+    /// Output price, in USD per million tokens, from which --confirm-expensive asks.
+    #[arg(long, value_name = "USD", default_value_t = 20.0, requires = "confirm_expensive")]
+    expensive_threshold: f64,
+    /// Placeholder shown by --thinking-delay.
+    #[arg(long, value_name = "TEXT", default_value = "Thinking…")]
+    thinking_text: String,
+    /// Ring the terminal bell (and show a desktop notification where available) when a slow
+    /// reply completes.
+    #[arg(long, action)]
+    notify: bool,
+    /// Extra HTTP header to send with every request, as "Name: Value" (repeatable).
+    #[arg(long = "header", value_name = "NAME: VALUE")]
+    #[serde(skip)]
+    headers: Vec<String>,
+    /// Retry transient API failures (rate limits, 5xx, network errors) this many times.
+    #[arg(long, default_value_t = 0)]
+    max_retries: u32,
+    /// HTTP status codes that --max-retries retries, replacing the default list, e.g.
+    /// 429,500,502,503,504,520 for gateways with their own codes.
+    #[arg(long, value_name = "CODES", value_delimiter = ',', value_parser = parse_status, default_values_t = [429, 500, 502, 503, 504])]
+    retry_on: Vec<u16>,
+    /// Fraction of each retry backoff that is randomized, between 0.0 and 1.0.
+    #[arg(long, default_value_t = 0.5)]
+    retry_jitter: f64,
+    /// Pause requests after this many consecutive failures (0 disables the circuit breaker).
+    #[arg(long, default_value_t = 5)]
+    circuit_breaker_threshold: u32,
+    /// Seconds to pause requests once the circuit breaker trips.
+    #[arg(long, default_value_t = 30)]
+    circuit_breaker_cooldown: u64,
+    /// Text printed before each input when reading from a terminal, e.g. "> ".
+    #[arg(long, value_name = "TEXT")]
+    prompt_indicator: Option<String>,
+    /// Print a shell completion script and exit.
+    #[arg(long, value_name = "SHELL", hide = true)]
+    #[serde(skip)]
+    generate_completions: Option<completions::Shell>,
+    /// Compress the rolling summary once it grows past roughly this many tokens. A summary
+    /// that is still too long after compressing is cut down to it.
+    #[arg(long, default_value_t = 800)]
+    summary_max_tokens: usize,
+    /// Read settings from this file instead of ~/.config/rust_gpt_cli/config.toml.
+    #[arg(long, value_name = "PATH")]
+    config: Option<String>,
+    /// System prompt sent at the start of every request.
+    #[arg(long, value_name = "TEXT")]
+    system: Option<String>,
+    /// Read the system prompt from this file.
+    #[arg(long, value_name = "PATH", conflicts_with = "system")]
+    system_file: Option<String>,
+    /// Ask the model, in the system prompt, to keep replies to about this many words. Unlike
+    /// a hard token limit, nothing is cut off mid-sentence.
+    #[arg(long, value_name = "WORDS")]
+    target_length: Option<u32>,
+    /// Start the system prompt with the current date and time on every request, so the
+    /// model can answer questions about today.
+    #[arg(long, action)]
+    inject_datetime: bool,
+    /// How --inject-datetime writes the time, as a strftime format.
+    #[arg(long, value_name = "FORMAT", requires = "inject_datetime", default_value = session::DATETIME_FORMAT)]
+    datetime_format: String,
+    /// Send each message on its own, with only the system prompt, and keep nothing: no
+    /// history, no summary, no files written.
+    #[arg(long, action, conflicts_with_all = ["debug", "manifest", "session", "resume"])]
+    stateless: bool,
+    /// Also append each reply's content to this file.
+    #[arg(long, value_name = "PATH")]
+    output: Option<String>,
+    /// Also write each reply, as a line of JSON, to this FIFO or Unix socket, for editors
+    /// and other front ends. If nothing is listening, a warning is printed and the session
+    /// carries on.
+    #[arg(long, value_name = "PATH")]
+    reply_sink: Option<String>,
+    /// Write replies only to the --output file, not the terminal.
+    #[arg(long, action, requires = "output")]
+    output_only: bool,
+    /// Load the session from this file if it exists, and save it there on exit. Without it,
+    /// sessions are saved under ~/.config/rust_gpt_cli/sessions.
+    #[arg(long, value_name = "PATH")]
+    session: Option<String>,
+    /// Resume the most recently saved session (ignored when --session is given).
+    #[arg(long = "continue", action)]
+    #[serde(rename = "continue")]
+    resume: bool,
+    /// Save new sessions gzip-compressed (.json.gz). A --session path ending in .gz is
+    /// always compressed.
+    #[arg(long, action)]
+    compress_session: bool,
+    /// Shell command that receives each message on stdin; its stdout is sent instead.
+    #[arg(long, value_name = "CMD")]
+    pre_hook: Option<String>,
+    /// Shell command that receives each reply on stdin, for logging or other side effects.
+    #[arg(long, value_name = "CMD")]
+    post_hook: Option<String>,
+    /// Read each reply aloud. Press Ctrl+C to stop a reply that's being spoken.
+    #[arg(long, action)]
+    speak: bool,
+    /// Command that speaks the text it reads on stdin (default: say on macOS, espeak on Linux).
+    #[arg(long, value_name = "CMD")]
+    tts_command: Option<String>,
+    /// Ask the model itself for spoken replies and play them. Only the transcript is kept
+    /// in the conversation.
+    #[cfg(feature = "audio")]
+    #[arg(long, action, conflicts_with_all = ["speak", "stream", "tui"])]
+    audio_output: bool,
+    /// Voice for --audio-output, e.g. alloy, echo or shimmer.
+    #[cfg(feature = "audio")]
+    #[arg(long, value_name = "VOICE", default_value = "alloy", requires = "audio_output")]
+    audio_voice: String,
+    /// Save each spoken reply as a WAV file in DIR instead of playing it.
+    #[cfg(feature = "audio")]
+    #[arg(long, value_name = "DIR", requires = "audio_output")]
+    audio_save: Option<String>,
+    /// Command that plays the WAV file whose path follows it (default: afplay on macOS, aplay
+    /// on Linux).
+    #[cfg(feature = "audio")]
+    #[arg(long, value_name = "CMD", requires = "audio_output")]
+    audio_player: Option<String>,
+    /// Show the model's reasoning, when it returns any, above each answer. Only the answer
+    /// is kept in the conversation.
+    #[arg(long, action)]
+    think_aloud: bool,
+    /// Combine adjacent messages with the same role when building each request. The saved
+    /// conversation is left as it is; use /merge to change it.
+    #[arg(long, action)]
+    merge_consecutive: bool,
+    /// Save every API request and response to FILE, for replaying later with --replay.
+    #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+    record: Option<String>,
+    /// Answer requests from a --record file instead of the network. No API key is needed.
+    #[arg(long, value_name = "FILE")]
+    replay: Option<String>,
+    /// Answer every request locally instead of calling the API, for trying out scripts
+    /// and sessions offline. Nothing is sent and no API key is needed.
+    #[arg(long, action, conflicts_with_all = ["record", "replay", "list_models", "wait_for_server"])]
+    mock: bool,
+    /// The --mock reply; {prompt} is replaced by the last user message and {model} by the
+    /// model. Defaults to echoing the prompt.
+    #[arg(long, value_name = "TEMPLATE", requires = "mock", default_value = mock::DEFAULT_TEMPLATE)]
+    mock_reply: String,
+    /// Make at most N API calls in all, counting summaries, titles and other background
+    /// requests as well as replies. Further requests are refused.
+    #[arg(long, value_name = "N")]
+    max_requests: Option<usize>,
+    /// Model to try when the main one is unavailable (not found, overloaded or rate
+    /// limited). Repeat to try several in order.
+    #[arg(long = "fallback-model", value_name = "MODEL")]
+    fallback_models: Vec<String>,
+    /// Instead of chatting here, serve POST /chat on ADDR (e.g. 127.0.0.1:8080) and answer
+    /// with server-sent events. Each session id in the request gets its own conversation.
+    #[cfg(feature = "serve")]
+    #[arg(long, value_name = "ADDR", conflicts_with_all = ["stateless", "session", "resume", "compare", "stream", "tui"])]
+    serve: Option<String>,
+    /// A web page origin, e.g. http://localhost:3000, allowed to call --serve from a
+    /// browser. Repeat for several; requests from any other page are refused.
+    #[cfg(feature = "serve")]
+    #[arg(long = "serve-origin", value_name = "ORIGIN", requires = "serve")]
+    serve_origins: Vec<String>,
+    /// Print replies with no newline after them, e.g. to capture a reply byte for byte
+    /// with --raw.
+    #[arg(long, action)]
+    no_trailing_newline: bool,
+    /// Type replies out a character at a time at a natural, uneven pace, for demos. Only
+    /// on terminals; the reply itself is kept as it arrived.
+    #[arg(long, action)]
+    typewriter_natural: bool,
+    /// Print replies as they arrive instead of all at once. Streamed replies aren't
+    /// wrapped to the terminal width.
+    #[arg(
+        long,
+        action,
+        conflicts_with_all = [
+            "compare", "batch", "benchmark", "extract", "format_code", "strip_markdown",
+            "typewriter_natural", "output_only", "think_aloud", "show_logprobs",
+        ]
+    )]
+    stream: bool,
+    /// With --stream, give up on a reply when no data has arrived for this many seconds,
+    /// however long the reply has been streaming. What arrived is kept for /continue.
+    #[arg(long, value_name = "SECONDS", requires = "stream")]
+    stream_idle_timeout: Option<u64>,
+    /// Chat in a full-screen view instead: the history in a scrollable pane above an input
+    /// line. PgUp/PgDn and the arrows scroll, Ctrl+L clears the pane, Ctrl+N switches to
+    /// the next of the --favorite-models (or types /model), Esc gives up on a reply and
+    /// Ctrl+C quits. Flags that only change the prompt loop's own output or questions
+    /// can't be combined with it.
+    #[arg(
+        long,
+        action,
+        conflicts_with_all = [
+            "stream", "stateless", "compare", "batch", "estimate_batch", "benchmark", "raw", "output_only",
+            "confirm_duplicates", "confirm_expensive", "echo_input", "extract", "format_code", "think_aloud",
+            "show_logprobs", "typewriter_natural", "speak", "auto_continue", "on_empty_reply", "idle_timeout",
+            "thinking_delay", "thinking_text", "timestamps", "max_line_length", "no_wrap", "strip_markdown",
+            "assistant_name", "no_trailing_newline", "prompt_indicator",
+        ]
+    )]
+    tui: bool,
+    /// Print how long each reply took to arrive, e.g. `[2.4s]`, on stderr.
+    #[arg(long, action)]
+    show_latency: bool,
+    /// Don't wrap replies to the terminal width.
+    #[arg(long, action)]
+    no_wrap: bool,
+    /// Show replies as plain text, without Markdown headings, emphasis, fences or link
+    /// syntax. The conversation keeps the reply as it came.
+    #[arg(long, action)]
+    strip_markdown: bool,
+    /// Send each non-empty line of FILE (or - for stdin) as a separate prompt with no
+    /// history, print the replies and exit.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["session", "resume", "compare", "benchmark"])]
+    batch: Option<String>,
+    /// Estimate the input tokens and cost of each prompt in a --batch FILE (or - for stdin)
+    /// for --model, then exit without sending anything.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["batch", "session", "resume", "compare", "benchmark"])]
+    estimate_batch: Option<String>,
+    /// What to do with input that isn't valid UTF-8: replace the bad bytes and warn, or
+    /// stop with an error.
+    #[arg(long, value_enum, value_name = "MODE", default_value = "lossy")]
+    on_invalid_utf8: input::InvalidUtf8,
+    /// In --batch mode, carry on after a failed prompt instead of stopping.
+    #[arg(long, action, requires = "batch")]
+    continue_on_error: bool,
+    /// min_p sampling (0-1) for OpenAI-compatible servers that support it, such as
+    /// llama.cpp. OpenAI itself rejects it, so it is only sent when given.
+    #[arg(long, value_name = "P", value_parser = parse_probability)]
+    min_p: Option<f32>,
+    /// Ask for every reply as a JSON object. OpenAI requires the word "JSON" to appear in
+    /// the conversation, e.g. in the system prompt.
+    #[arg(long, action)]
+    json_response: bool,
+    /// JSON Schema file the replies must follow (structured outputs).
+    #[arg(long, value_name = "PATH", requires = "json_response")]
+    json_schema: Option<String>,
+    /// Show only this field of each JSON reply; a dotted path like result.answer reaches
+    /// into nested objects and arrays.
+    #[arg(long, value_name = "FIELD", requires = "json_response")]
+    extract: Option<String>,
+    /// Mark the system prompt, pins and summary as a cacheable prefix for models that take
+    /// explicit cache-control hints (Claude models, directly or through OpenRouter). OpenAI
+    /// caches prefixes by itself, so nothing is added for its models.
+    #[arg(long, action)]
+    cache_prefix: bool,
+    /// Warn when the API answers with a different model than the one requested, other than
+    /// a dated snapshot of it.
+    #[arg(long, action)]
+    warn_model_mismatch: bool,
+    /// Cut each reply off after this many tokens.
+    #[arg(long, value_name = "N")]
+    max_tokens: Option<u32>,
+    /// When a reply is cut off by the token limit, ask for the rest without asking first.
+    #[arg(long, action)]
+    auto_continue: bool,
+    /// Refuse to send conversations that fail validation (empty messages, unknown roles,
+    /// ending on a reply) instead of just warning about them.
+    #[arg(long, action)]
+    strict: bool,
+    /// Show costs in this currency, given as a code (EUR) or a symbol. Prices are in USD,
+    /// so pair it with --currency-rate.
+    #[arg(long, value_name = "CURRENCY")]
+    currency: Option<String>,
+    /// How many units of --currency one US dollar buys.
+    #[arg(long, value_name = "RATE", default_value_t = 1.0, requires = "currency")]
+    currency_rate: f64,
+    /// Run code blocks in replies through a formatter (rustfmt for Rust, gofmt for Go, black
+    /// for Python, or the config's [formatters]) before showing them. The stored reply is
+    /// left as the model wrote it.
+    #[arg(long, action)]
+    format_code: bool,
+    /// Print diagnostic response headers (request id, model, processing time, rate limits)
+    /// to stderr after each API call.
+    #[arg(long, action)]
+    show_headers: bool,
+    /// Request token log probabilities and print each token's probability after the reply.
+    #[arg(long, action)]
+    show_logprobs: bool,
+    /// Also show the N most likely alternatives for each token (0-20).
+    #[arg(long, value_name = "N", requires = "show_logprobs", value_parser = clap::value_parser!(u32).range(0..=20))]
+    top_logprobs: Option<u32>,
+    /// Only write the last N context messages to debug_context.txt. The transcript is
+    /// always complete.
+    #[arg(long, value_name = "N")]
+    debug_max_messages: Option<usize>,
+    /// Cut each message in debug_context.txt to N characters. The full text is still sent.
+    #[arg(long, value_name = "N")]
+    context_preview_chars: Option<usize>,
+    /// Send this prompt to each of the --benchmark-models and compare latency, usage and cost,
+    /// then exit.
+    #[arg(long, value_name = "PROMPT")]
+    benchmark: Option<String>,
+    /// Comma-separated models to benchmark (default: the --model).
+    #[arg(long, value_name = "MODELS", value_delimiter = ',')]
+    benchmark_models: Vec<String>,
+    /// Send each message to all of these comma-separated models at once, show the replies
+    /// side by side and choose which one to keep.
+    #[arg(long, value_name = "MODELS", value_delimiter = ',')]
+    compare: Vec<String>,
+    /// Comma-separated models (or aliases) that /next-model cycles through.
+    #[arg(long, value_name = "MODELS", value_delimiter = ',')]
+    favorite_models: Vec<String>,
+    /// A fact that is always sent with the context and kept by the summarizer (repeatable).
+    #[arg(long = "pin", value_name = "TEXT")]
+    pins: Vec<String>,
+    /// Processing tier to request, e.g. auto, default or flex (cheaper but slower).
+    #[arg(long, value_name = "TIER")]
+    service_tier: Option<String>,
+    /// Disable the /run command.
+    #[arg(long, action)]
+    no_run: bool,
+    /// Never send more than this many messages in one request, counting the system prompt,
+    /// pins and summary. Older messages are dropped first.
+    #[arg(long, value_name = "N")]
+    max_context_messages: Option<usize>,
+    /// Label replies with this name instead of the model id.
+    #[arg(long, value_name = "NAME")]
+    assistant_name: Option<String>,
+    /// Print each message as it is sent, prefixed with its role, so output from piped input
+    /// reads as a full transcript.
+    #[arg(long, action)]
+    echo_input: bool,
+    /// Base URL of the API, for proxies and compatible servers (default: the --provider's).
+    #[arg(long, value_name = "URL", default_value = api::DEFAULT_BASE_URL)]
+    base_url: String,
+    /// Before starting, wait up to this many seconds for the API at --base-url to answer,
+    /// e.g. for a local model server that is still loading.
+    #[arg(long, value_name = "SECONDS", conflicts_with = "replay")]
+    wait_for_server: Option<u64>,
+    /// Send the conversation as chat messages, or flattened into one prompt for
+    /// completion-style backends.
+    #[arg(long, value_enum, default_value = "chat")]
+    format: api::Format,
+    /// How each message is written into the prompt with --format prompt.
+    #[arg(long, value_name = "TEMPLATE", default_value = prompt::DEFAULT_TEMPLATE, hide_default_value = true)]
+    prompt_template: String,
+    /// After each summary update, ask the model to check it for omissions and fill them in.
+    #[arg(long, action)]
+    verify_summary: bool,
+    /// How many verification rounds to run at most with --verify-summary.
+    #[arg(long, default_value_t = 1, value_name = "N")]
+    verify_summary_passes: u32,
+    /// Keep the text of only the N most recently summarized messages in memory; older ones
+    /// keep just their id, role and time. Dropped text is gone from the saved session and
+    /// /export too, though the --debug transcript has already written it.
+    #[arg(long, value_name = "N")]
+    keep_summarized: Option<usize>,
+    /// When the session ends, write the code blocks from the replies to files in DIR, named
+    /// after the files they are for. Existing files are left alone.
+    #[arg(long, value_name = "DIR")]
+    extract_code: Option<String>,
+    /// Print the settings in effect, after the config file and flags are combined, and exit.
+    #[arg(long, action)]
+    #[serde(skip)]
+    dump_config: bool,
+    /// Role label colors from the config file's `[colors]` section.
+    #[arg(skip)]
+    #[serde(skip)]
+    colors: std::collections::BTreeMap<String, String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct Message {
+    /// Stable id assigned when the message joins the conversation. Never sent to the API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
     role: String,
     content: String,
+    /// The model's explanation when it declined to answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    refusal: Option<String>,
+    /// When the message was added to the conversation. Never sent to the API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<DateTime<Utc>>,
+    /// The model that wrote this reply. Never sent to the API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    /// The content was dropped by `--keep-summarized` after being summarized.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    dropped: bool,
+    /// Files uploaded with `/attach` and sent along with this message.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<Attachment>,
 }
 
-#[derive(Serialize)]
-struct ChatRequest {
-    model: String,
-    messages: Vec<Message>,
+/// A file uploaded to the provider, referenced from a message by its id.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Attachment {
+    file_id: String,
+    name: String,
 }
 
-#[derive(Deserialize, Debug)]
-struct ChatChoice {
-    message: Message,
-}
+impl Message {
+    fn new(role: &str, content: impl Into<String>) -> Self {
+        Message {
+            id: None,
+            role: role.to_string(),
+            content: content.into(),
+            refusal: None,
+            timestamp: Some(Utc::now()),
+            model: None,
+            dropped: false,
+            attachments: Vec::new(),
+        }
+    }
+
+    /// An assistant message holding a reply from the API. Spoken replies keep only their
+    /// transcript.
+    fn reply(api: &ApiMessage) -> Self {
+        let content = match &api.audio {
+            Some(audio) if api.content.is_empty() => audio.transcript.as_str(),
+            _ => api.content.as_str(),
+        };
+        Message {
+            refusal: api.refusal.clone(),
+            ..Message::new("assistant", content)
+        }
+    }
+
+    /// The content as shown to the user, with refusals marked as such.
+    fn display_content(&self) -> String {
+        match &self.refusal {
+            _ if self.dropped => "[dropped after summarizing]".to_string(),
+            Some(refusal) => format!("[refused: {}]", refusal.trim()),
+            None => self.content.clone(),
+        }
+    }
 
-#[derive(Deserialize, Debug)]
-struct ChatResponse {
-    choices: Vec<ChatChoice>,
+    /// The message as it is sent to `model`, without any local metadata and with the role
+    /// translated to what that model expects.
+    fn to_api(&self, model: &str) -> ApiMessage {
+        ApiMessage {
+            role: models::wire_role(model, &self.role).to_string(),
+            content: self.content.clone(),
+            refusal: self.refusal.clone(),
+            reasoning_content: None,
+            audio: None,
+            file_ids: self.attachments.iter().map(|a| a.file_id.clone()).collect(),
+            cache_breakpoint: false,
+        }
+    }
 }
 
+/// Asks `model`, the --provider's summary model, to fold `messages` into the summary.
 async fn update_summary(
-    client: &reqwest::Client,
-    api_key: &str,
+    client: &ApiClient,
+    model: &str,
     current_summary: Option<&str>,
+    pinned: &[String],
     messages: &[Message],
+    options: &RequestOptions,
+    usage: &mut UsageTotals,
 ) -> Result<String, Box<dyn Error>> {
-    let system_msg = Message {
-        role: "system".to_string(),
-        content: "You are a helpful assistant tasked with summarizing a conversation. Try to keep the summary short, but make sure to include each relevant bullet point. I would rather you make the summary longer than forget things.:w
-.".to_string(),
-    };
+    let system_msg = Message::new(
+        "system",
+        "You are a helpful assistant tasked with summarizing a conversation. Try to keep the summary short, but make sure to include each relevant bullet point. I would rather you make the summary longer than forget things.:w
+.",
+    );
 
     let mut user_content = String::new();
+    if !pinned.is_empty() {
+        user_content.push_str("These facts must be retained in the summary, word for word:\n");
+        for fact in pinned {
+            user_content.push_str(&format!("- {}\n", fact));
+        }
+        user_content.push('\n');
+    }
     if let Some(summary) = current_summary {
         user_content.push_str("Current summary:\n");
         user_content.push_str(summary);
@@ -60,183 +612,3015 @@ async fn update_summary(
         user_content.push_str("Summarize the following conversation in under 200 words:\n");
     }
     for msg in messages {
-        user_content.push_str(&format!("{}: {}\n", msg.role, msg.content));
+        user_content.push_str(&format!("{}: {}\n", msg.role, msg.display_content()));
     }
     user_content.push_str("\nPlease provide an updated summary.");
 
-    let request_body = ChatRequest {
-        model: "gpt-4o".to_string(),
-        messages: vec![
-            system_msg,
-            Message {
-                role: "user".to_string(),
-                content: user_content,
-            },
-        ],
-    };
-
-    let url = "https://api.openai.com/v1/chat/completions";
-    let res = client
-        .post(url)
-        .header(CONTENT_TYPE, "application/json")
-        .header(AUTHORIZATION, format!("Bearer {}", api_key))
-        .json(&request_body)
-        .send()
-        .await?;
-
-    if res.status().is_success() {
-        let chat_response: ChatResponse = res.json().await?;
-        if let Some(choice) = chat_response.choices.first() {
-            Ok(choice.message.content.clone())
-        } else {
-            Err("No summary returned by GPT-4o".into())
-        }
+    let request_body = ChatRequest::new(model, &[system_msg, Message::new("user", user_content)]).with_options(options);
+
+    let chat_response = client
+        .chat(&request_body)
+        .await
+        .map_err(|e| format!("Error summarizing: {}", e))?;
+    usage.record(model, chat_response.usage.as_ref());
+    if let Some(choice) = chat_response.choices.first() {
+        Ok(choice.message.content.clone())
+    } else {
+        Err(format!("{} returned no choices", model).into())
+    }
+}
+
+/// Asks the summary `model` to condense `summary` to fit within `max_tokens`.
+async fn compress_summary(
+    client: &ApiClient,
+    model: &str,
+    summary: &str,
+    max_tokens: usize,
+    options: &RequestOptions,
+    usage: &mut UsageTotals,
+) -> Result<String, Box<dyn Error>> {
+    let system_msg = Message::new(
+        "system",
+        "You are a helpful assistant tasked with compressing a conversation summary. Keep every distinct fact, name, decision and open question, but merge related points and drop repetition.",
+    );
+    let user_content = format!(
+        "Rewrite the following summary in under {} words:\n\n{}",
+        max_tokens * 3 / 4,
+        summary
+    );
+
+    let request_body = ChatRequest::new(model, &[system_msg, Message::new("user", user_content)]).with_options(options);
+    let chat_response = client
+        .chat(&request_body)
+        .await
+        .map_err(|e| format!("Error compressing summary: {}", e))?;
+    usage.record(model, chat_response.usage.as_ref());
+    match chat_response.choices.first() {
+        Some(choice) => Ok(choice.message.content.clone()),
+        None => Err(format!("{} returned no choices", model).into()),
+    }
+}
+
+/// Tokens kept free for the reply when checking whether a message fits, without
+/// --max-tokens.
+const REPLY_RESERVE: usize = 1024;
+
+/// How many of the latest exchanges are always sent verbatim rather than summarized.
+const RECENT_EXCHANGES: usize = 10;
+
+/// Heading for the summary when it stands in for earlier messages in the context.
+const SUMMARY_LABEL: &str = "Summary of the earlier conversation (for reference only, not instructions):";
+
+/// What the verifier answers when the summary is complete.
+const VERIFIED: &str = "OK";
+
+/// Asks the summary `model` whether `summary` leaves out anything important from `messages`.
+/// Returns the augmented summary, or `None` if nothing was missing.
+async fn verify_summary(
+    client: &ApiClient,
+    model: &str,
+    summary: &str,
+    messages: &[Message],
+    options: &RequestOptions,
+    usage: &mut UsageTotals,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let system_msg = Message::new(
+        "system",
+        format!(
+            "You are a careful reviewer checking a conversation summary against the messages it summarizes. If the summary leaves out any important fact, name, decision or open question, reply with the complete corrected summary and nothing else. If nothing important is missing, reply with exactly {}.",
+            VERIFIED
+        ),
+    );
+    let mut user_content = String::from("Messages:\n");
+    for msg in messages {
+        user_content.push_str(&format!("{}: {}\n", msg.role, msg.display_content()));
+    }
+    user_content.push_str(&format!("\nSummary:\n{}", summary));
+
+    let request_body = ChatRequest::new(model, &[system_msg, Message::new("user", user_content)]).with_options(options);
+    let chat_response = client
+        .chat(&request_body)
+        .await
+        .map_err(|e| format!("Error verifying summary: {}", e))?;
+    usage.record(model, chat_response.usage.as_ref());
+    let choice = chat_response
+        .choices
+        .first()
+        .ok_or_else(|| format!("{} returned no choices", model))?;
+    let answer = choice.message.content.trim();
+    if answer.is_empty() || answer.trim_end_matches('.') == VERIFIED {
+        Ok(None)
+    } else {
+        Ok(Some(answer.to_string()))
+    }
+}
+
+/// Most messages from the start of the conversation sent when asking for a title.
+const TITLE_MESSAGES: usize = 6;
+
+/// Asks the summary `model` for a title of a few words for the conversation in `messages`;
+/// only its first few messages are sent.
+async fn title_conversation(
+    client: &ApiClient,
+    model: &str,
+    messages: &[Message],
+    options: &RequestOptions,
+    usage: &mut UsageTotals,
+) -> Result<String, Box<dyn Error>> {
+    let system_msg = Message::new(
+        "system",
+        "You are a helpful assistant tasked with naming a conversation. Reply with a descriptive title of at most six words, without quotes or a full stop.",
+    );
+    let mut user_content = String::from("Conversation:\n");
+    for msg in messages.iter().take(TITLE_MESSAGES) {
+        user_content.push_str(&format!("{}: {}\n", msg.role, msg.display_content()));
+    }
+
+    let request_body = ChatRequest::new(model, &[system_msg, Message::new("user", user_content)]).with_options(options);
+    let chat_response = client
+        .chat(&request_body)
+        .await
+        .map_err(|e| format!("Error naming the session: {}", e))?;
+    usage.record(model, chat_response.usage.as_ref());
+    let title = chat_response
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.trim().lines().next())
+        .map(|line| line.trim_matches(['"', '\'', '.']).trim().to_string())
+        .unwrap_or_default();
+    if title.is_empty() {
+        return Err(format!("{} returned no title", model).into());
+    }
+    Ok(title)
+}
+
+/// Builds the messages sent for the next request. With `max_messages`, older messages are
+/// dropped until the whole context fits, but the latest message is always kept.
+/// Context staged with `/context-add` goes just ahead of the conversation.
+fn build_context(session: &Session, max_messages: Option<usize>) -> Vec<Message> {
+    let conversation = &session.conversation;
+    // Everything already folded into the summary is replaced by it, and past 10 exchanges
+    // only the last 10 are sent verbatim. Rehydrated sessions send whatever text is left.
+    let mut start_index = if session.rehydrated {
+        session.first_kept()
     } else {
-        let error_text = res.text().await?;
-        Err(format!("Error summarizing: {}", error_text).into())
+        session
+            .summarized
+            .min(conversation.len())
+            .max(session.recent_start(RECENT_EXCHANGES))
+    };
+
+    let mut context: Vec<Message> = session.system_message().into_iter().collect();
+    context.extend(session.pinned_message());
+    if start_index > 0
+        && let Some(sum) = &session.summary
+    {
+        // Labelled so the model reads it as background rather than as instructions.
+        context.push(Message::new("system", format!("{}\n{}", SUMMARY_LABEL, sum)));
+    }
+    context.extend(session.staged_message());
+    if let Some(max) = max_messages {
+        let room = max.saturating_sub(context.len()).max(1);
+        start_index = start_index.max(conversation.len().saturating_sub(room));
     }
+    context.extend_from_slice(&conversation[start_index..]);
+    context
 }
 
-fn build_context(conversation: &[Message], summary: &Option<String>) -> Vec<Message> {
-    let total_exchanges = conversation.len() / 2;
-    if total_exchanges <= 10 {
-        conversation.to_vec()
+/// Checks the messages about to be sent for sequences the API is likely to reject or
+/// misread, describing every problem found.
+fn validate_conversation(messages: &[Message]) -> Result<(), String> {
+    let mut problems = Vec::new();
+    for (i, message) in messages.iter().enumerate() {
+        if !matches!(message.role.as_str(), "system" | "developer" | "user" | "assistant" | "tool") {
+            problems.push(format!("message {} has unknown role '{}'", i + 1, message.role));
+        }
+        if message.content.trim().is_empty() && message.refusal.is_none() {
+            let id = message.id.map(|id| format!(" (#{}, remove it with /delete {})", id, id)).unwrap_or_default();
+            problems.push(format!("message {} ({}) is empty{}", i + 1, message.role, id));
+        }
+    }
+    match messages.last() {
+        None => problems.push("there are no messages to send".to_string()),
+        Some(last) if last.role == "assistant" => problems.push(
+            "the conversation ends on an assistant message, so there is nothing to reply to".to_string(),
+        ),
+        Some(_) => {}
+    }
+    if problems.is_empty() {
+        Ok(())
     } else {
-        let start_index = conversation.len() - 20;
-        let mut context = Vec::new();
-        if let Some(sum) = summary {
-            context.push(Message {
-                role: "system".to_string(),
-                content: sum.clone(),
-            });
+        Err(problems.join("; "))
+    }
+}
+
+/// How many times an oversized summary is re-compressed before we give up and keep it.
+const MAX_COMPRESSION_PASSES: usize = 2;
+
+/// Folds `session.conversation[session.summarized..end]` into the summary. On failure or
+/// timeout the previous summary is kept, so the same messages are retried next time.
+/// Returns whether the summary was updated.
+async fn fold_into_summary(
+    client: &ApiClient,
+    session: &mut Session,
+    end: usize,
+    usage: &mut UsageTotals,
+    args: &Args,
+) -> bool {
+    let timeout_secs = args.summary_timeout;
+    let options = summary_options(args);
+    let pending = &session.conversation[session.summarized..end];
+    let update = update_summary(client, args.provider.summary_model(), session.summary.as_deref(), &session.pinned, pending, &options, usage);
+    match tokio::time::timeout(Duration::from_secs(timeout_secs), update).await {
+        Ok(Ok(mut updated)) => {
+            if args.verify_summary {
+                for pass in 0..args.verify_summary_passes {
+                    let verify = verify_summary(client, args.provider.summary_model(), &updated, pending, &options, usage);
+                    match tokio::time::timeout(Duration::from_secs(timeout_secs), verify).await {
+                        Ok(Ok(Some(augmented))) => updated = augmented,
+                        Ok(Ok(None)) => break,
+                        Ok(Err(e)) => {
+                            eprintln!("{}", e);
+                            break;
+                        }
+                        Err(_) => {
+                            eprintln!("Summary verification pass {} timed out after {}s.", pass + 1, timeout_secs);
+                            break;
+                        }
+                    }
+                }
+            }
+            // Feeding the summary back into itself lets it grow without bound over a long
+            // session; squeeze it back under the budget when it gets too big.
+            let mut passes = 0;
+            while tokens::estimate(&updated) > args.summary_max_tokens && passes < MAX_COMPRESSION_PASSES {
+                passes += 1;
+                let compress = compress_summary(client, args.provider.summary_model(), &updated, args.summary_max_tokens, &options, usage);
+                match tokio::time::timeout(Duration::from_secs(timeout_secs), compress).await {
+                    Ok(Ok(compressed)) => updated = compressed,
+                    Ok(Err(e)) => {
+                        eprintln!("{}", e);
+                        break;
+                    }
+                    Err(_) => {
+                        eprintln!("Summary compression timed out after {}s.", timeout_secs);
+                        break;
+                    }
+                }
+            }
+            // The model doesn't always manage, and the cap has to hold regardless.
+            if tokens::estimate(&updated) > args.summary_max_tokens {
+                let (cut, dropped) = tokens::truncate(&updated, args.summary_max_tokens, tokens::TruncateMode::Both);
+                eprintln!("The summary is still over --summary-max-tokens, so {} characters were cut from its middle.", dropped);
+                updated = cut;
+            }
+            session.summary = Some(updated);
+            session.summarized = end;
+            if let Some(keep) = args.keep_summarized {
+                session.drop_summarized(keep);
+            }
+            true
+        }
+        Ok(Err(e)) => {
+            eprintln!("Summary update failed, keeping the previous summary: {}", e);
+            false
+        }
+        Err(_) => {
+            eprintln!(
+                "Summary update timed out after {}s, keeping the previous summary.",
+                timeout_secs
+            );
+            false
         }
-        context.extend_from_slice(&conversation[start_index..]);
-        context
+    }
+}
+
+/// The favorite after `current`, wrapping around; the first one when `current` isn't a
+/// favorite at all.
+fn next_favorite<'a>(favorites: &'a [String], current: &str) -> &'a str {
+    let next = match favorites.iter().position(|m| m == current) {
+        Some(index) => (index + 1) % favorites.len(),
+        None => 0,
+    };
+    &favorites[next]
+}
+
+/// Share of the model's context window a rehydrated conversation may fill, leaving room
+/// for the reply.
+const REHYDRATE_SHARE: f64 = 0.75;
+
+/// Estimated tokens of the whole conversation as `/rehydrate` sends it, and whether that
+/// fits the session model's context window. Models with an unknown window always fit.
+fn rehydrated_size(session: &Session) -> (usize, Option<u32>, bool) {
+    let start = session.first_kept();
+    let summary = session.summary.as_deref().filter(|_| start > 0);
+    let tokens = [session.current_system(), summary]
+        .into_iter()
+        .flatten()
+        .chain(session.pinned.iter().map(String::as_str))
+        .chain(session.conversation[start..].iter().map(|m| m.content.as_str()))
+        .map(tokens::estimate)
+        .sum();
+    let window = models::lookup(&session.model).map(|info| info.context_window);
+    let fits = window.is_none_or(|window| tokens as f64 <= window as f64 * REHYDRATE_SHARE);
+    (tokens, window, fits)
+}
+
+/// Goes back from `/rehydrate` to sending the summary and the recent messages, catching the
+/// summary up on everything said while it was set aside.
+async fn end_rehydration(client: &ApiClient, session: &mut Session, usage: &mut UsageTotals, args: &Args) {
+    session.rehydrated = false;
+    println!("Back to sending the summary and the recent messages.\n");
+    let recent_start = session.recent_start(RECENT_EXCHANGES);
+    if session.summarized < recent_start {
+        fold_into_summary(client, session, recent_start, usage, args).await;
     }
 }
 
 /// Debug function: writes a fiile to track the current context 
 /// - "debug_context.txt" contains the context prompt (summary and the last few messages).
-fn save_debug_files(conversation: &[Message], summary: &Option<String>) -> Result<(), Box<dyn Error>> {
-    let context = build_context(conversation, summary);
+fn save_debug_files(session: &Session, args: &Args) -> Result<(), Box<dyn Error>> {
+    let context = build_context(session, args.max_context_messages);
     let mut ctx_file = std::fs::File::create("debug_context.txt")?;
     writeln!(ctx_file, "Context Prompt:")?;
-    for msg in &context {
-        writeln!(ctx_file, "{}: {}", msg.role, msg.content)?;
+    let omitted = args.debug_max_messages.map_or(0, |max| context.len().saturating_sub(max));
+    if omitted > 0 {
+        writeln!(ctx_file, "… ({} earlier messages omitted)", omitted)?;
+    }
+    for msg in &context[omitted..] {
+        let content = match args.context_preview_chars {
+            Some(max) if msg.content.chars().count() > max => {
+                let kept: String = msg.content.chars().take(max).collect();
+                format!("{}… ({} more characters)", kept, msg.content.chars().count() - max)
+            }
+            _ => msg.content.clone(),
+        };
+        writeln!(ctx_file, "{}: {}", msg.role, content)?;
     }
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let args = Args::parse();
-    let api_key = std::env::var("OPENAI_API_KEY")
-        .expect("OPENAI_API_KEY environment variable not set");
+/// Formats a message's timestamp in local time, e.g. `[2025-03-05 14:03:12]`.
+fn format_timestamp(msg: &Message) -> String {
+    match msg.timestamp {
+        Some(ts) => format!("[{}]", ts.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S")),
+        None => "[--]".to_string(),
+    }
+}
 
-    let client = reqwest::Client::new();
-    let url = "https://api.openai.com/v1/chat/completions";
+/// Formats a message's id for cross-referencing, e.g. `#14 `. Empty for messages without one.
+fn format_id(msg: &Message) -> String {
+    msg.id.map(|id| format!("#{} ", id)).unwrap_or_default()
+}
 
-    let stdin = BufReader::new(io::stdin());
-    let mut lines = stdin.lines();
+/// `--provider`: the service behind the API, and the defaults that go with it. Each is
+/// reached through its OpenAI-compatible API; another service is added as a variant here.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum Provider {
+    /// OpenAI's own API.
+    #[value(name = "openai")]
+    OpenAi,
+    /// Anthropic's Claude models, through their OpenAI-compatible endpoint.
+    Anthropic,
+}
 
-    let mut conversation: Vec<Message> = Vec::new();
-    let mut summary: Option<String> = None;
+impl Provider {
+    /// The model used when neither --model nor the config file names one.
+    fn default_model(self) -> &'static str {
+        match self {
+            Provider::OpenAi => "gpt-4o",
+            Provider::Anthropic => "claude-sonnet-4-5",
+        }
+    }
 
-    println!(
-        "Interactive Chat Session (model: {}). Type your message below. Press Ctrl+C to exit.\n",
-        args.model
-    );
+    /// The model that keeps the rolling summary and names sessions.
+    fn summary_model(self) -> &'static str {
+        match self {
+            Provider::OpenAi => "gpt-4o",
+            Provider::Anthropic => "claude-haiku-4-5",
+        }
+    }
+
+    fn base_url(self) -> &'static str {
+        match self {
+            Provider::OpenAi => api::DEFAULT_BASE_URL,
+            Provider::Anthropic => "https://api.anthropic.com/v1",
+        }
+    }
+
+    /// The environment variable holding the API key.
+    fn key_variable(self) -> &'static str {
+        match self {
+            Provider::OpenAi => "OPENAI_API_KEY",
+            Provider::Anthropic => "ANTHROPIC_API_KEY",
+        }
+    }
+}
+
+/// Replaces the --model and --base-url defaults with the --provider's, unless they were
+/// given. clap's own defaults can't depend on another flag.
+fn apply_provider_defaults(args: &mut Args, matches: &clap::ArgMatches) {
+    if matches.value_source("model") == Some(ValueSource::DefaultValue) {
+        args.model = args.provider.default_model().to_string();
+    }
+    if matches.value_source("base_url") == Some(ValueSource::DefaultValue) {
+        args.base_url = args.provider.base_url().to_string();
+    }
+}
+
+/// `--on-empty-reply`: how a successful reply with no content is handled.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum EmptyReply {
+    /// Ask whether to send the request again; piped input drops the reply.
+    Ask,
+    /// Send the request again once without asking.
+    Retry,
+    /// Drop the reply, and the message it answers, with a warning.
+    Drop,
+}
+
+/// Whether a reply has nothing to show: no text, no transcript and no refusal. Models
+/// sometimes answer this way, e.g. when they run out of tokens while reasoning.
+fn is_empty_reply(choice: &ChatChoice) -> bool {
+    let message = Message::reply(&choice.message);
+    message.content.trim().is_empty() && message.refusal.is_none()
+}
+
+/// The reply content as printed, trimmed unless the display flags say otherwise.
+fn reply_text(args: &Args, message: &Message) -> String {
+    let content = message.display_content();
+    if args.trim_replies.unwrap_or(!args.raw) {
+        content.trim().to_string()
+    } else {
+        content
+    }
+}
+
+/// `label` in the color configured for `role`, when printing to a terminal.
+fn colored_label(args: &Args, role: &str, label: &str) -> String {
+    match args.colors.get(role) {
+        Some(sgr) if std::io::stdout().is_terminal() => color::paint(label, sgr),
+        _ => label.to_string(),
+    }
+}
+
+/// Prints an assistant reply from `model` according to the display flags.
+fn display_reply(args: &Args, model: &str, message: &Message) {
+    let mut content = reply_text(args, message);
+    if args.strip_markdown {
+        content = plain::strip(&content);
+    }
+    let label = args.assistant_name.as_deref().unwrap_or(model);
+
+    if args.raw {
+        if args.no_trailing_newline {
+            print!("{}", content);
+            let _ = std::io::stdout().flush();
+        } else {
+            println!("{}", content);
+        }
+        return;
+    }
+    let text = if args.timestamps {
+        format!("{} {}: {}", format_timestamp(message), label, content)
+    } else {
+        format!("{}: {}", label, content)
+    };
+    let text = match wrap::terminal_width().filter(|_| !args.no_wrap) {
+        Some(width) => wrap::wrap(&text, width),
+        None => text,
+    };
+    // Colored after wrapping, which would count the escape codes as text.
+    let text = text.replacen(&format!("{}:", label), &colored_label(args, "assistant", &format!("{}:", label)), 1);
+    if args.typewriter_natural && std::io::stdout().is_terminal() {
+        typewriter::type_out(&text);
+        if !args.no_trailing_newline {
+            println!("\n");
+        }
+    } else if args.no_trailing_newline {
+        print!("{}", text);
+        let _ = std::io::stdout().flush();
+    } else {
+        println!("{}\n", text);
+    }
+}
+
+/// Prints a message the user sent, for `--echo-input`.
+fn display_input(args: &Args, message: &Message) {
+    let label = if args.raw {
+        format!("{}:", message.role)
+    } else {
+        colored_label(args, &message.role, &format!("{}:", message.role))
+    };
+    let text = if args.timestamps {
+        format!("{} {} {}", format_timestamp(message), label, message.content)
+    } else {
+        format!("{} {}", label, message.content)
+    };
+    if args.raw {
+        println!("{}", text);
+    } else {
+        println!("{}\n", text);
+    }
+}
+
+/// Prints the reasoning behind a reply under a `[reasoning]` header, dimmed on terminals
+/// so it stands apart from the answer.
+fn display_reasoning(reasoning: &str) {
+    let reasoning = reasoning.trim();
+    if reasoning.is_empty() {
+        return;
+    }
+    if std::io::stdout().is_terminal() {
+        println!("\x1b[2m[reasoning]\n{}\x1b[0m\n", reasoning);
+    } else {
+        println!("[reasoning]\n{}\n", reasoning);
+    }
+}
+
+/// Prints each token of a reply with its probability, and the alternatives if requested.
+fn display_logprobs(tokens: &[api::TokenLogprob]) {
+    let percent = |logprob: f64| logprob.exp() * 100.0;
+    println!("[logprobs]");
+    for token in tokens {
+        let mut line = format!("{:>7.2}%  {:?}", percent(token.logprob), token.token);
+        let alternatives: Vec<String> = token
+            .top_logprobs
+            .iter()
+            .filter(|alt| alt.token != token.token)
+            .map(|alt| format!("{:?} {:.2}%", alt.token, percent(alt.logprob)))
+            .collect();
+        if !alternatives.is_empty() {
+            line.push_str(&format!("  (also: {})", alternatives.join(", ")));
+        }
+        println!("{}", line);
+    }
+    println!();
+}
+
+/// Prints the effective settings as JSON for `--dump-config`. Secrets are redacted.
+fn print_config(args: &Args, config: &config::Config) -> Result<(), Box<dyn Error>> {
+    let headers: Vec<String> = args
+        .headers
+        .iter()
+        .map(|h| match h.split_once(':') {
+            Some((name, _)) => format!("{}: [REDACTED]", name.trim()),
+            None => h.clone(),
+        })
+        .collect();
+    let api_key = (std::env::var_os(args.provider.key_variable()).is_some() || config.api_key.is_some())
+        .then_some("[REDACTED]");
+    let model_keys: std::collections::BTreeMap<&String, &str> = config.model_keys.keys().map(|model| (model, "[REDACTED]")).collect();
+    let dump = serde_json::json!({
+        "config_file": args.config.clone().or_else(|| {
+            config::default_path().map(|path| path.display().to_string())
+        }),
+        "api_key": api_key,
+        "api_keys": vec!["[REDACTED]"; config.api_keys.len()],
+        "model_keys": model_keys,
+        "headers": headers,
+        "settings": args,
+        "aliases": config.aliases,
+        "templates": config.templates,
+        "formatters": config.formatters,
+        "colors": config.colors,
+    });
+    println!("{}", serde_json::to_string_pretty(&dump)?);
+    Ok(())
+}
+
+/// Catches flag combinations that clap's per-flag rules can't express: out-of-range values
+/// and flags that would silently do nothing next to another.
+fn validate_args(args: &Args) -> Result<(), String> {
+    if !(0.0..=1.0).contains(&args.retry_jitter) {
+        return Err("--retry-jitter must be between 0.0 and 1.0".to_string());
+    }
+    if !(args.currency_rate.is_finite() && args.currency_rate > 0.0) {
+        return Err("--currency-rate must be a positive number".to_string());
+    }
+    if !(args.expensive_threshold.is_finite() && args.expensive_threshold >= 0.0) {
+        return Err("--expensive-threshold must be a price in USD, 0 or more".to_string());
+    }
+    if args.thinking_delay.is_some_and(|secs| !(secs.is_finite() && secs >= 0.0)) {
+        return Err("--thinking-delay must be a number of seconds, 0 or more".to_string());
+    }
+    if args.max_context_messages == Some(0) {
+        return Err("--max-context-messages must be at least 1".to_string());
+    }
+    if !session::valid_datetime_format(&args.datetime_format) {
+        return Err(format!("--datetime-format '{}' is not a valid strftime format", args.datetime_format));
+    }
+    if args.target_length == Some(0) {
+        return Err("--target-length must be at least 1 word".to_string());
+    }
+    if args.summary_max_tokens == 0 {
+        return Err("--summary-max-tokens must be at least 1".to_string());
+    }
+    if args.stream_idle_timeout == Some(0) {
+        return Err("--stream-idle-timeout must be at least 1 second".to_string());
+    }
+    if args.verify_summary && args.verify_summary_passes == 0 {
+        return Err("--verify-summary-passes 0 turns --verify-summary off; drop one of them".to_string());
+    }
+
+    let ignored_by = |flag: &str, by: &str| Err(format!("{} has no effect with {}", flag, by));
+    if args.stateless {
+        if args.compress_session {
+            return ignored_by("--compress-session", "--stateless (nothing is saved)");
+        }
+        if args.keep_summarized.is_some() {
+            return ignored_by("--keep-summarized", "--stateless (nothing is summarized)");
+        }
+        if args.auto_title {
+            return ignored_by("--auto-title", "--stateless (nothing is saved)");
+        }
+        if args.extract_code.is_some() {
+            return ignored_by("--extract-code", "--stateless (nothing is kept)");
+        }
+        if !args.tags.is_empty() && !args.list_sessions {
+            return ignored_by("--tag", "--stateless (nothing is saved)");
+        }
+    }
+    if args.raw {
+        if args.timestamps {
+            return ignored_by("--timestamps", "--raw");
+        }
+        if args.assistant_name.is_some() {
+            return ignored_by("--assistant-name", "--raw");
+        }
+        if args.thinking_delay.is_some() {
+            return ignored_by("--thinking-delay", "--raw");
+        }
+        if args.typewriter_natural {
+            return ignored_by("--typewriter-natural", "--raw");
+        }
+    }
+    if args.stream {
+        if args.timestamps {
+            return ignored_by("--timestamps", "--stream");
+        }
+        if args.thinking_delay.is_some() {
+            return ignored_by("--thinking-delay", "--stream (the reply shows as it arrives)");
+        }
+    }
+    if !args.compare.is_empty() && !args.fallback_models.is_empty() {
+        return ignored_by("--fallback-model", "--compare");
+    }
+    if !args.compare.is_empty() && args.show_latency {
+        return ignored_by("--show-latency", "--compare");
+    }
+    if args.show_logprobs && args.format == api::Format::Prompt {
+        return ignored_by("--show-logprobs", "--format prompt");
+    }
+    #[cfg(feature = "audio")]
+    if args.audio_save.is_some() && args.audio_player.is_some() {
+        return ignored_by("--audio-player", "--audio-save (replies are saved, not played)");
+    }
+    Ok(())
+}
+
+fn parse_probability(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(p) if (0.0..=1.0).contains(&p) => Ok(p),
+        _ => Err(format!("'{}' is not a number between 0 and 1", value)),
+    }
+}
+
+fn parse_status(value: &str) -> Result<u16, String> {
+    match value.trim().parse::<u16>() {
+        Ok(code) if (100..=599).contains(&code) => Ok(code),
+        _ => Err(format!("'{}' is not an HTTP status code", value)),
+    }
+}
 
+/// The request parameters chosen on the command line.
+fn request_options(args: &Args) -> RequestOptions {
+    RequestOptions {
+        service_tier: args.service_tier.clone(),
+        temperature: None,
+        top_p: None,
+        min_p: args.min_p,
+        max_tokens: args.max_tokens,
+        response_format: args.json_response.then(|| serde_json::json!({"type": "json_object"})),
+        logprobs: args.show_logprobs.then_some(true),
+        top_logprobs: args.top_logprobs,
+        #[cfg(feature = "audio")]
+        modalities: args.audio_output.then(|| vec!["text".to_string(), "audio".to_string()]),
+        #[cfg(not(feature = "audio"))]
+        modalities: None,
+        #[cfg(feature = "audio")]
+        audio: args.audio_output.then(|| api::AudioOptions {
+            voice: args.audio_voice.clone(),
+            format: audio::FORMAT.to_string(),
+        }),
+        #[cfg(not(feature = "audio"))]
+        audio: None,
+        cache_prefix: args.cache_prefix,
+        warn_model_mismatch: args.warn_model_mismatch,
+        format: args.format,
+        prompt_template: prompt::unescape(&args.prompt_template),
+    }
+}
+
+/// Options for summary requests: the user's settings minus those that only make sense for
+/// replies, such as the token limit, JSON mode and audio.
+fn summary_options(args: &Args) -> RequestOptions {
+    RequestOptions {
+        max_tokens: None,
+        response_format: None,
+        logprobs: None,
+        top_logprobs: None,
+        modalities: None,
+        audio: None,
+        ..request_options(args)
+    }
+}
+
+/// Reads `--json-schema` into a structured-outputs `response_format`, named after the file.
+fn schema_format(path: &str) -> Result<serde_json::Value, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let schema: serde_json::Value = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+    let name: String = Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect())
+        .unwrap_or_else(|| "schema".to_string());
+    Ok(serde_json::json!({
+        "type": "json_schema",
+        "json_schema": {"name": name, "schema": schema, "strict": true},
+    }))
+}
+
+/// The `--extract` field of a JSON reply. Strings are shown as they are, anything else as
+/// pretty-printed JSON.
+fn extract_field(content: &str, field: &str) -> Result<String, String> {
+    let json: serde_json::Value =
+        serde_json::from_str(content.trim()).map_err(|e| format!("the reply isn't valid JSON ({}), so --extract {} can't be applied", e, field))?;
+    let mut value = &json;
+    for key in field.split('.') {
+        let next = match value {
+            serde_json::Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => value.get(key),
+        };
+        value = next.ok_or_else(|| format!("the reply has no field '{}'", field))?;
+    }
+    Ok(match value {
+        serde_json::Value::String(text) => text.clone(),
+        other => serde_json::to_string_pretty(other).unwrap_or_default(),
+    })
+}
+
+/// Awaits `request`, showing the `--thinking-delay` placeholder with the elapsed time on
+/// terminals while it is slow to finish, and clearing it afterwards.
+async fn show_thinking<T>(args: &Args, request: impl Future<Output = T>) -> T {
+    let delay = match args.thinking_delay {
+        Some(secs) if secs >= 0.0 && !args.raw && std::io::stdout().is_terminal() => Duration::from_secs_f64(secs),
+        _ => return request.await,
+    };
+    tokio::pin!(request);
+    let started = tokio::time::Instant::now();
+    let mut ticks = tokio::time::interval_at(started + delay, Duration::from_secs(1));
+    let mut shown = false;
     loop {
         tokio::select! {
-            maybe_line = lines.next_line() => {
-                match maybe_line? {
-                    Some(input) => {
-                        let prompt = input.trim();
-                        if prompt.is_empty() {
-                            continue;
-                        }
-                        conversation.push(Message {
-                            role: "user".to_string(),
-                            content: prompt.to_string(),
-                        });
+            result = &mut request => {
+                if shown {
+                    print!("\r\x1b[2K");
+                    let _ = std::io::stdout().flush();
+                }
+                return result;
+            }
+            _ = ticks.tick() => {
+                shown = true;
+                print!("\r\x1b[2K{} {}s", args.thinking_text, started.elapsed().as_secs());
+                let _ = std::io::stdout().flush();
+            }
+        }
+    }
+}
 
-                        let context_messages = build_context(&conversation, &summary);
+/// Polls `/models` until the server answers at all, printing progress. Any HTTP response
+/// counts: a server that rejects the key is still up. Ctrl+C gives up early.
+async fn wait_for_server(client: &ApiClient, base_url: &str, timeout: Duration) -> Result<(), String> {
+    let started = Instant::now();
+    let mut announced = false;
+    loop {
+        let attempt = client.get("/models").timeout(Duration::from_secs(5)).send();
+        let error = tokio::select! {
+            result = attempt => match result {
+                Ok(_) => {
+                    if announced {
+                        println!("Server is up after {}s.", started.elapsed().as_secs());
+                    }
+                    return Ok(());
+                }
+                Err(e) => e,
+            },
+            _ = tokio::signal::ctrl_c() => return Err("stopped waiting for the server".to_string()),
+        };
+        if started.elapsed() >= timeout {
+            return Err(format!("{} did not respond within {}s: {}", base_url, timeout.as_secs(), error));
+        }
+        let waited = started.elapsed().as_secs();
+        if !announced {
+            println!("Waiting for {} to respond (up to {}s; Ctrl+C to give up)...", base_url, timeout.as_secs());
+            announced = true;
+        } else if waited > 0 && waited.is_multiple_of(5) {
+            println!("Still waiting ({}s)...", waited);
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+            _ = tokio::signal::ctrl_c() => return Err("stopped waiting for the server".to_string()),
+        }
+    }
+}
 
-                        let request_body = ChatRequest {
-                            model: args.model.clone(),
-                            messages: context_messages,
-                        };
+/// Marks a reply stopped with Ctrl+C while it streamed in, where it is stored.
+const TRUNCATED_BY_USER: &str = "[truncated by user]";
 
-                        let res = client.post(url)
-                            .header(CONTENT_TYPE, "application/json")
-                            .header(AUTHORIZATION, format!("Bearer {}", api_key))
-                            .json(&request_body)
-                            .send()
-                            .await?;
-
-                        if res.status().is_success() {
-                            let chat_response: ChatResponse = res.json().await?;
-                            if let Some(choice) = chat_response.choices.first() {
-                                let reply = &choice.message.content;
-                                println!("{}: {}\n", args.model, reply);
-                                conversation.push(Message {
-                                    role: "assistant".to_string(),
-                                    content: reply.to_string(),
-                                });
-                            } else {
-                                eprintln!("No response returned by the API.");
-                            }
-                        } else {
-                            let error_text = res.text().await?;
-                            eprintln!("Error: {}", error_text);
-                        }
+/// A streamed reply stopped with Ctrl+C, and the text that had arrived by then.
+#[derive(Debug)]
+struct Cancelled {
+    partial: String,
+}
 
-                        if conversation.len() / 2 > 10 {
-                            if summary.is_none() {
-                                // Create the initial summary from all messages before the last 5 exchanges.
-                                let summary_source = &conversation[..conversation.len()-20];
-                                summary = Some(update_summary(&client, &api_key, None, summary_source).await?);
-                            } else {
-                                // Update the summary with the latest exchange (last two messages).
-                                let new_exchange = &conversation[conversation.len()-22..conversation.len()-20];
-                                summary = Some(update_summary(&client, &api_key, summary.as_deref(), new_exchange).await?);
-                            }
-                        }
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the reply was stopped with Ctrl+C")
+    }
+}
 
-                        if args.debug {
-                            if let Err(e) = save_debug_files(&conversation, &summary) {
-                                eprintln!("Debug file error: {}", e);
-                            }
-                        }
-                    },
-                    _ => break,
+impl Error for Cancelled {}
+
+/// Sends `context` to `model` and returns the first choice, if there is one. With
+/// `stream`, the reply is printed as it arrives (see [`StreamedText`]), and Ctrl+C stops it
+/// with a [`Cancelled`] error.
+async fn request_reply(
+    client: &ApiClient,
+    model: &str,
+    context: &[Message],
+    options: &RequestOptions,
+    usage: &mut UsageTotals,
+    stream: Option<&Args>,
+) -> Result<Option<(String, ChatChoice)>, Box<dyn Error>> {
+    let request = ChatRequest::new(model, context).with_options(options);
+    let response = match stream {
+        Some(args) => {
+            let request = request.streamed();
+            let printer = std::cell::RefCell::new(StreamedText::new(args, model));
+            tokio::select! {
+                response = client.chat_stream(&request, |delta| printer.borrow_mut().push(delta)) => response?,
+                _ = tokio::signal::ctrl_c() => {
+                    return Err(Cancelled { partial: printer.borrow().received.clone() }.into());
                 }
-            },
-            _ = tokio::signal::ctrl_c() => {
-                println!("\nTermination signal received.");
-                break;
             }
         }
-    }
+        None => client.chat(&request).await?,
+    };
+    response.check_service_tier(options);
+    usage.record(model, response.usage.as_ref());
+    Ok(response.choices.into_iter().next().map(|c| (model.to_string(), c)))
+}
 
-    if args.debug {
-        let timestamp = Utc::now().format("%Y%m%dT%H%M%SZ");
-        let transcript_file = format!("chat_transcript_{}.txt", timestamp);
-        let mut file = std::fs::File::create(transcript_file)?;
-        writeln!(file, "Conversation Transcript:")?;
-        for msg in conversation.iter() {
-            writeln!(file, "{}: {}", msg.role, msg.content)?;
+/// Prints a reply for `--stream` as it arrives. The label goes out with the first text,
+/// so an empty reply prints nothing. Unless replies are untrimmed, leading whitespace is
+/// skipped and trailing whitespace is held back until more text follows it.
+struct StreamedText<'a> {
+    args: &'a Args,
+    model: &'a str,
+    started: bool,
+    held: String,
+    /// Everything pushed so far, shown or held back.
+    received: String,
+}
+
+impl<'a> StreamedText<'a> {
+    fn new(args: &'a Args, model: &'a str) -> Self {
+        StreamedText {
+            args,
+            model,
+            started: false,
+            held: String::new(),
+            received: String::new(),
         }
+    }
 
-        if let Err(e) = save_debug_files(&conversation, &summary) {
-            eprintln!("Final debug file error: {}", e);
-        } else {
-            println!("Debug files 'chat_transcription.txt' and generated.");
+    fn push(&mut self, delta: &str) {
+        self.received.push_str(delta);
+        if !self.args.trim_replies.unwrap_or(!self.args.raw) {
+            self.print(delta);
+            return;
         }
+        self.held.push_str(delta);
+        let text = self.held.trim_end();
+        let text = if self.started { text } else { text.trim_start() };
+        if text.is_empty() {
+            return;
+        }
+        let text = text.to_string();
+        self.held = self.held[self.held.trim_end().len()..].to_string();
+        self.print(&text);
     }
 
-    println!("Session ended. Press enter to exit.");
+    fn print(&mut self, text: &str) {
+        if !self.started && !self.args.raw {
+            let label = self.args.assistant_name.as_deref().unwrap_or(self.model);
+            print!("{} ", colored_label(self.args, "assistant", &format!("{}:", label)));
+        }
+        self.started = true;
+        print!("{}", text);
+        let _ = std::io::stdout().flush();
+    }
+}
 
-    Ok(())
+/// Finishes a reply printed by [`StreamedText`]: prints whatever `content` adds to the
+/// `streamed` text, which a continuation of a cut-off reply does, then the usual ending.
+fn finish_streamed(args: &Args, streamed: &str, content: &str) {
+    let trim = args.trim_replies.unwrap_or(!args.raw);
+    let shown = if trim { streamed.trim_end().len() } else { streamed.len() };
+    let rest = content.get(shown..).unwrap_or_default();
+    print!("{}", if trim { rest.trim_end() } else { rest });
+    if args.no_trailing_newline {
+        let _ = std::io::stdout().flush();
+    } else if args.raw {
+        println!();
+    } else {
+        println!("\n");
+    }
+}
+
+/// Most follow-up requests made to finish one reply cut off by the token limit.
+const MAX_CONTINUATIONS: usize = 3;
+
+const CONTINUE_PROMPT: &str =
+    "Your last reply was cut off. Continue exactly where it stopped, without repeating anything or adding a preamble.";
+
+/// Asks `model` for the rest of a reply cut off by the token limit, always with
+/// --auto-continue and otherwise if the user agrees, and joins the pieces into one reply.
+/// A failed continuation keeps what arrived so far.
+#[allow(clippy::too_many_arguments)]
+async fn continue_truncated(
+    client: &ApiClient,
+    model: &str,
+    context: &[Message],
+    mut choice: ChatChoice,
+    options: &RequestOptions,
+    usage: &mut UsageTotals,
+    args: &Args,
+    lines: &mut input::Lines,
+) -> ChatChoice {
+    for _ in 0..MAX_CONTINUATIONS {
+        if !choice.truncated() {
+            break;
+        }
+        let wanted = if args.auto_continue {
+            true
+        } else if std::io::stdin().is_terminal() {
+            confirm(lines, "The reply was cut off by the token limit. Ask for the rest?").await.unwrap_or(false)
+        } else {
+            eprintln!("Note: the reply was cut off by the token limit; --auto-continue asks for the rest.");
+            false
+        };
+        if !wanted {
+            break;
+        }
+        let mut request = context.to_vec();
+        request.push(Message::new("assistant", choice.message.content.clone()));
+        request.push(Message::new("user", CONTINUE_PROMPT));
+        match show_thinking(args, request_reply(client, model, &request, options, usage, None)).await {
+            Ok(Some((_, next))) => {
+                choice.message.content = join_continuation(&choice.message.content, &next.message.content);
+                choice.finish_reason = next.finish_reason;
+                if let Some(tokens) = choice.logprobs.as_mut().and_then(|l| l.content.as_mut()) {
+                    tokens.extend(next.logprobs.and_then(|l| l.content).unwrap_or_default());
+                }
+            }
+            Ok(None) => {
+                eprintln!("The continuation came back empty; keeping the reply so far.");
+                break;
+            }
+            Err(e) => {
+                eprintln!("Could not continue the reply ({}); keeping it so far.", e);
+                break;
+            }
+        }
+    }
+    choice
+}
+
+/// A streamed reply that broke off partway, kept so `/continue` can ask for the rest.
+struct BrokenOff {
+    model: String,
+    /// What was sent to get the reply.
+    context: Vec<Message>,
+    partial: String,
+    /// Whether the partial reply is the last message of the conversation.
+    kept: bool,
+}
+
+/// Asks for the rest of a broken-off reply, the partial reply going out as the assistant's
+/// so far, and joins the pieces. Returns what is left to continue if it breaks off again
+/// or fails.
+async fn continue_broken_off(
+    client: &ApiClient,
+    mut broken: BrokenOff,
+    options: &RequestOptions,
+    usage: &mut UsageTotals,
+    args: &Args,
+    session: &mut Session,
+) -> Option<BrokenOff> {
+    let mut request = broken.context.clone();
+    request.push(Message::new("assistant", broken.partial.clone()));
+    request.push(Message::new("user", CONTINUE_PROMPT));
+    let (joined, left) = match request_reply(client, &broken.model, &request, options, usage, Some(args)).await {
+        Ok(Some((_, choice))) => {
+            finish_streamed(args, &choice.message.content, &choice.message.content);
+            (join_continuation(&broken.partial, &choice.message.content), false)
+        }
+        Ok(None) => {
+            eprintln!("The continuation came back empty; keeping the reply so far.");
+            return Some(broken);
+        }
+        Err(e) => match api::partial_reply(e.as_ref()) {
+            Some(partial) => {
+                println!("\n");
+                eprintln!("Error: {}", e);
+                eprintln!("The continuation broke off too; /continue asks for the rest.");
+                (join_continuation(&broken.partial, partial), true)
+            }
+            None => {
+                eprintln!("Could not continue the reply ({}); /continue tries again.", e);
+                return Some(broken);
+            }
+        },
+    };
+    if broken.kept
+        && let Some(last) = session.conversation.last_mut()
+        && last.role == "assistant"
+    {
+        last.content = joined.clone();
+    }
+    broken.partial = joined;
+    left.then_some(broken)
+}
+
+/// Joins a continuation onto the reply it continues. Models often start again from the
+/// beginning of the sentence they were cut off in, so text at the start of `tail` that
+/// repeats the end of `head` is kept only once.
+fn join_continuation(head: &str, tail: &str) -> String {
+    // Shorter matches are too likely to be coincidence.
+    const MIN_OVERLAP: usize = 12;
+    let trimmed = tail.trim_start();
+    let overlap = (MIN_OVERLAP..=trimmed.len().min(head.len()))
+        .rev()
+        .filter(|&n| trimmed.is_char_boundary(n))
+        .find(|&n| head.ends_with(&trimmed[..n]));
+    match overlap {
+        Some(n) => format!("{}{}", head, &trimmed[n..]),
+        None => format!("{}{}", head, tail),
+    }
+}
+
+/// Like [`request_reply`], but while the model turns out to be unavailable, tries each of
+/// `fallbacks` in turn.
+async fn request_with_fallback(
+    client: &ApiClient,
+    model: &str,
+    fallbacks: &[String],
+    context: &[Message],
+    options: &RequestOptions,
+    usage: &mut UsageTotals,
+    stream: Option<&Args>,
+) -> Result<Option<(String, ChatChoice)>, Box<dyn Error>> {
+    let mut result = request_reply(client, model, context, options, usage, stream).await;
+    let mut current = model;
+    for fallback in fallbacks {
+        let status = match &result {
+            Err(e) => match e.downcast_ref::<api::Error>().and_then(api::Error::api_error) {
+                Some(e) if e.model_unavailable() => e.status,
+                _ => break,
+            },
+            Ok(_) => break,
+        };
+        eprintln!("{} is unavailable ({}); trying {}.", current, status, fallback);
+        result = request_reply(client, fallback, context, options, usage, stream).await;
+        current = fallback;
+    }
+    result
+}
+
+/// Sends `context` to every model in `models` concurrently, prints the replies and asks
+/// which one to keep. Fails only if every request failed.
+async fn compare_replies(
+    client: &ApiClient,
+    models: &[String],
+    context: &[Message],
+    options: &RequestOptions,
+    usage: &mut UsageTotals,
+    lines: &mut input::Lines,
+) -> Result<Option<(String, ChatChoice)>, Box<dyn Error>> {
+    let requests: Vec<ChatRequest> = models
+        .iter()
+        .map(|m| ChatRequest::new(m, context).with_options(options))
+        .collect();
+    let responses = futures_util::future::join_all(requests.iter().map(|r| client.chat(r))).await;
+
+    let mut candidates = Vec::new();
+    let mut last_error = None;
+    for (model, response) in models.iter().zip(responses) {
+        match response {
+            Ok(response) => {
+                response.check_service_tier(options);
+                usage.record(model, response.usage.as_ref());
+                match response.choices.into_iter().next() {
+                    Some(choice) => candidates.push((model.clone(), choice)),
+                    None => eprintln!("{} returned no choices.", model),
+                }
+            }
+            Err(e) => {
+                eprintln!("{} failed: {}", model, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    if candidates.is_empty() {
+        return match last_error {
+            Some(e) => Err(e.into()),
+            None => Ok(None),
+        };
+    }
+
+    for (i, (model, choice)) in candidates.iter().enumerate() {
+        let reply = Message::reply(&choice.message);
+        println!("[{}] {}: {}\n", i + 1, model, reply.display_content().trim());
+    }
+    if candidates.len() == 1 {
+        return Ok(candidates.pop());
+    }
+    let choice = loop {
+        print!("Keep which reply? [1-{}, default 1] ", candidates.len());
+        stdout::flush();
+        let answer = lines.next_line().await?.unwrap_or_default();
+        let answer = answer.trim();
+        if answer.is_empty() {
+            break 0;
+        }
+        match answer.parse::<usize>() {
+            Ok(n) if (1..=candidates.len()).contains(&n) => break n - 1,
+            _ => println!("Please enter a number between 1 and {}.", candidates.len()),
+        }
+    };
+    println!();
+    Ok(Some(candidates.swap_remove(choice)))
+}
+
+/// Command output beyond this many characters is cut off before it is sent.
+const RUN_OUTPUT_LIMIT: usize = 16_000;
+
+/// Runs `command` through the shell for `/run`, prints what it wrote and returns a message
+/// describing the result.
+async fn run_shell_command(command: &str) -> Result<String, Box<dyn Error>> {
+    let output = hooks::shell(command).stdin(std::process::Stdio::null()).output().await?;
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    if text.chars().count() > RUN_OUTPUT_LIMIT {
+        text = text.chars().take(RUN_OUTPUT_LIMIT).collect();
+        text.push_str("\n[output truncated]");
+    }
+    let text = text.trim_end();
+    println!("{}\n({})\n", text, output.status);
+
+    Ok(format!(
+        "I ran `{}` ({}). Output:\n```\n{}\n```",
+        command, output.status, text
+    ))
+}
+
+/// `text` with the `--redact-pattern` matches replaced, noting on stderr how many were
+/// found in `what`.
+fn redact(redactor: &redact::Redactor, text: &str, what: &str) -> Result<String, regex::RegexError> {
+    let (text, count) = redactor.apply(text)?;
+    if count > 0 {
+        eprintln!("Redacted {} match(es) from {} before sending.", count, what);
+    }
+    Ok(text)
+}
+
+/// What every turn passes through besides the request itself, whichever front end took
+/// the prompt: the prompt loop in [`run`] or `--tui`.
+struct Pipeline {
+    redactor: redact::Redactor,
+    /// The --output file, which every reply is appended to.
+    output: Option<std::fs::File>,
+    reply_sink: Option<sink::ReplySink>,
+    /// The --debug transcript.
+    transcript: Option<transcript::Transcript>,
+}
+
+impl Pipeline {
+    /// `prompt` as it is sent: through the --pre-hook, then redacted. An error says why
+    /// nothing should be sent.
+    async fn outgoing(&self, args: &Args, prompt: &str) -> Result<String, String> {
+        let prompt = match &args.pre_hook {
+            Some(hook) => {
+                let output = hooks::run("pre-hook", hook, prompt).await.map_err(|e| e.to_string())?;
+                if output.trim().is_empty() {
+                    return Err("the pre-hook produced an empty message".to_string());
+                }
+                output.trim_end_matches(['\r', '\n']).to_string()
+            }
+            None => prompt.to_string(),
+        };
+        redact(&self.redactor, &prompt, "your message").map_err(|e| e.to_string())
+    }
+
+    /// Hands a reply to --output, --reply-sink and the --post-hook. `shown` is the reply as
+    /// it is displayed, which --extract may have cut down.
+    async fn deliver(&mut self, args: &Args, model: &str, message: &Message, shown: &Message) {
+        if let Some(file) = &mut self.output
+            && let Err(e) = writeln!(file, "{}", reply_text(args, shown))
+        {
+            eprintln!("Could not write to {}: {}", args.output.as_deref().unwrap_or_default(), e);
+        }
+        if let Some(sink) = &mut self.reply_sink {
+            sink.send(model, &reply_text(args, shown)).await;
+        }
+        if let Some(hook) = &args.post_hook
+            && let Err(e) = hooks::run("post-hook", hook, &format!("{}\n", reply_text(args, message))).await
+        {
+            eprintln!("Error: {}", e);
+        }
+    }
+
+    /// What follows every turn: rehydration ends once the conversation outgrows the
+    /// context window, older exchanges are folded into the summary, and the --debug files
+    /// and transcript are brought up to date.
+    async fn after_turn(&mut self, client: &ApiClient, session: &mut Session, usage: &mut UsageTotals, args: &Args) {
+        if session.rehydrated && !rehydrated_size(session).2 {
+            println!("The full conversation has outgrown {}'s context window.", session.model);
+            end_rehydration(client, session, usage, args).await;
+        }
+        let recent_start = session.recent_start(RECENT_EXCHANGES);
+        if !session.rehydrated && session.summarized < recent_start {
+            // Fold everything before the last 10 exchanges that isn't summarized yet
+            // into the summary.
+            fold_into_summary(client, session, recent_start, usage, args).await;
+        }
+
+        if args.debug
+            && let Err(e) = save_debug_files(session, args)
+        {
+            eprintln!("Debug file error: {}", e);
+        }
+        if let Some(transcript) = &mut self.transcript
+            && let Err(e) = transcript.append(session)
+        {
+            eprintln!("Transcript error: {}", e);
+        }
+    }
+}
+
+/// Checks a request's messages before they are sent. A problem stops the request with
+/// --strict; otherwise it is returned as a warning to show.
+fn check_context(args: &Args, context: &[Message]) -> Result<Option<String>, String> {
+    match validate_conversation(context) {
+        Err(problem) if args.strict => Err(problem),
+        Err(problem) => Ok(Some(problem)),
+        Ok(()) => Ok(None),
+    }
+}
+
+/// The messages sent with the latest one in the conversation, consecutive messages from
+/// one role merged with --merge-consecutive.
+fn conversation_context(session: &Session, args: &Args) -> Vec<Message> {
+    let mut context = build_context(session, args.max_context_messages);
+    if args.merge_consecutive {
+        session::merge_consecutive(&mut context, 0);
+    }
+    context
+}
+
+/// Asks a yes/no question on stdout and reads the answer from the input stream.
+async fn confirm(lines: &mut input::Lines, question: &str) -> io::Result<bool> {
+    print!("{} [y/N] ", question);
+    stdout::flush();
+    let answer = lines.next_line().await?.unwrap_or_default();
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Re-runs every user message of `source`, in order, against `model` and returns the
+/// result as a new session with fresh assistant replies. Older messages are folded into
+/// the summary as they are in the main loop, so each request sees what it would have.
+async fn replay_conversation(
+    client: &ApiClient,
+    model: &str,
+    source: &Session,
+    options: &RequestOptions,
+    usage: &mut UsageTotals,
+    args: &Args,
+) -> Result<Session, Box<dyn Error>> {
+    let conversation = &source.conversation;
+    let total = conversation.iter().filter(|m| m.role == "user").count();
+    let mut replayed = Session::new(model);
+    replayed.system = source.system.clone();
+    replayed.system_stack = source.system_stack.clone();
+    replayed.pinned = source.pinned.clone();
+    let mut turn = 0;
+    for msg in conversation.iter().filter(|m| m.role != "assistant") {
+        replayed.push(msg.clone());
+        if msg.role != "user" {
+            continue;
+        }
+        turn += 1;
+
+        let context = build_context(&replayed, args.max_context_messages);
+        let response = client.chat(&ChatRequest::new(model, &context).with_options(options)).await?;
+        usage.record(model, response.usage.as_ref());
+        let choice = response
+            .choices
+            .first()
+            .ok_or_else(|| format!("the API returned no choices for message {} of {}", turn, total))?;
+        let reply = Message::reply(&choice.message);
+        println!("[{}/{}] {}: {}\n", turn, total, model, reply.display_content().trim());
+        replayed.push(reply);
+
+        let recent_start = replayed.recent_start(RECENT_EXCHANGES);
+        if replayed.summarized < recent_start {
+            fold_into_summary(client, &mut replayed, recent_start, usage, args).await;
+        }
+    }
+    Ok(replayed)
+}
+
+/// Picks the session to start with: the `--session` file, the latest saved session for
+/// `--continue`, or a new one. Also returns where the session is saved on exit.
+fn open_session(args: &Args) -> Result<(Session, Option<PathBuf>), Box<dyn Error>> {
+    if args.stateless {
+        return Ok((Session::new(&args.model), None));
+    }
+    if let [format, path] = args.import.as_slice() {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+        let session =
+            import::import(format, &text, &args.model).map_err(|e| format!("could not import {}: {}", path, e))?;
+        let save_to = match &args.session {
+            Some(target) if Path::new(target).exists() => {
+                return Err(format!("{} already exists; choose a new --session path for the import", target).into());
+            }
+            Some(target) => Some(PathBuf::from(target)),
+            None => session::new_session_path(args.compress_session),
+        };
+        println!("Imported {} messages from {}.", session.conversation.len(), path);
+        return Ok((session, save_to));
+    }
+    let path = match &args.session {
+        Some(path) => Some(PathBuf::from(path)),
+        None if args.resume => {
+            let latest = session::latest_session();
+            if latest.is_none() {
+                println!("No previous session found, starting a new one.");
+            }
+            latest
+        }
+        None => None,
+    };
+    match path {
+        Some(path) if path.exists() => {
+            let session = Session::load(&path, &args.model)
+                .map_err(|e| format!("could not load session {}: {}", path.display(), e))?;
+            println!(
+                "Resuming session {} ({} messages).",
+                path.display(),
+                session.conversation.len()
+            );
+            Ok((session, Some(path)))
+        }
+        Some(path) => Ok((Session::new(&args.model), Some(path))),
+        None => Ok((Session::new(&args.model), session::new_session_path(args.compress_session))),
+    }
+}
+
+/// Prints the saved sessions that carry every one of `tags`: file name, when it was last
+/// saved, its tags and its title or how it began.
+fn list_sessions(tags: &[String], default_model: &str) {
+    let mut shown = 0;
+    for (modified, path) in session::saved_sessions() {
+        let session = match Session::load(&path, default_model) {
+            Ok(session) => session,
+            Err(e) => {
+                eprintln!("Skipping {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        if !tags.iter().all(|tag| session.tags.contains(tag)) {
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let saved = DateTime::<Utc>::from(modified).with_timezone(&chrono::Local);
+        let tag_list = if session.tags.is_empty() { String::new() } else { format!("  [{}]", session.tags.join(", ")) };
+        println!("{}  {}  {} message(s){}", name, saved.format("%Y-%m-%d %H:%M"), session.conversation.len(), tag_list);
+        if let Some(title) = &session.title {
+            println!("    {}", title);
+        } else if let Some(first) = session.conversation.iter().find(|m| m.role == "user") {
+            let line = first.content.lines().next().unwrap_or_default();
+            let preview: String = line.chars().take(SESSION_PREVIEW_CHARS).collect();
+            let ellipsis = if preview.len() < first.content.trim_end().len() { "…" } else { "" };
+            println!("    {}{}", preview, ellipsis);
+        }
+        shown += 1;
+    }
+    if shown == 0 {
+        match tags {
+            [] => println!("No saved sessions."),
+            _ => println!("No saved sessions are tagged {}.", tags.join(", ")),
+        }
+    }
+}
+
+/// How much of a session's first message `--list-sessions` shows.
+const SESSION_PREVIEW_CHARS: usize = 70;
+
+/// Runs once the session is over, however it ended: saves the session and writes the
+/// debug transcript and the session manifest when they were requested.
+fn finalize_session(
+    args: &Args,
+    session: &Session,
+    session_path: Option<&Path>,
+    transcript: Option<&mut transcript::Transcript>,
+    usage: &UsageTotals,
+    started_at: DateTime<Utc>,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(path) = session_path
+        && !session.conversation.is_empty()
+    {
+        match session.save(path) {
+            Ok(()) => println!("Session saved to {}", path.display()),
+            Err(e) => eprintln!("Could not save session to {}: {}", path.display(), e),
+        }
+    }
+
+    if let Some(dir) = &args.extract_code {
+        let mut extracted = codeblocks::extract(&session.conversation, Path::new(dir));
+        let existing = extracted.existing().len();
+        if existing > 0 {
+            eprintln!("Left {} existing file(s) in {} alone; /extract-code can overwrite them.", existing, dir);
+            extracted.files.retain(|path, _| !path.exists());
+        }
+        if let Err(e) = extracted.write() {
+            eprintln!("Could not write the code to {}: {}", dir, e);
+        }
+    }
+
+    let mut transcript_file = None;
+    if let Some(transcript) = transcript {
+        transcript.finish(session)?;
+        transcript_file = Some(transcript.path.clone());
+
+        if let Err(e) = save_debug_files(session, args) {
+            eprintln!("Final debug file error: {}", e);
+        } else {
+            println!("Debug files 'chat_transcription.txt' and generated.");
+        }
+    }
+
+    if let Some(path) = &args.manifest {
+        let turns = session.conversation.iter().filter(|m| m.role == "assistant").count();
+        let manifest = manifest::Manifest::new(started_at, turns, usage, args, session, transcript_file.as_deref());
+        if let Err(e) = manifest.write(path) {
+            eprintln!("Manifest error: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// A line holding only this starts or ends a multi-line message.
+const MULTILINE_DELIMITER: &str = "\"\"\"";
+
+/// Replies that take longer than this trigger `--notify`.
+const NOTIFY_AFTER: Duration = Duration::from_secs(5);
+
+/// Lets the user know a slow reply has arrived. Does nothing when stdout isn't a terminal.
+fn notify_reply_ready(model: &str) {
+    let mut stdout = std::io::stdout();
+    if !stdout.is_terminal() {
+        return;
+    }
+    let _ = write!(stdout, "\x07");
+    let _ = stdout.flush();
+
+    let body = format!("{} finished replying", model);
+    let command = if cfg!(target_os = "macos") {
+        Some(("osascript", vec![
+            "-e".to_string(),
+            format!("display notification \"{}\" with title \"gpt\"", body),
+        ]))
+    } else if cfg!(target_os = "linux") {
+        Some(("notify-send", vec!["gpt".to_string(), body]))
+    } else {
+        None
+    };
+    if let Some((program, args)) = command {
+        // Best effort: a missing notifier shouldn't disturb the session.
+        let _ = tokio::process::Command::new(program)
+            .args(args)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn();
+    }
+}
+
+/// SIGTERM, which process managers and containers send to ask for a clean shutdown.
+#[cfg(unix)]
+type TermSignal = Option<tokio::signal::unix::Signal>;
+#[cfg(not(unix))]
+type TermSignal = ();
+
+#[cfg(unix)]
+fn term_signal() -> TermSignal {
+    tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).ok()
+}
+
+#[cfg(not(unix))]
+fn term_signal() -> TermSignal {}
+
+/// Resolves once SIGTERM arrives; never on platforms without it.
+#[cfg(unix)]
+async fn terminated(signal: &mut TermSignal) {
+    match signal {
+        Some(signal) => {
+            signal.recv().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(not(unix))]
+async fn terminated(_signal: &mut TermSignal) {
+    std::future::pending().await
+}
+
+fn is_broken_pipe(e: &(dyn Error + 'static)) -> bool {
+    e.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe)
+}
+
+/// The exit status a shell reports for a process stopped by SIGPIPE.
+const BROKEN_PIPE_STATUS: i32 = 128 + 13;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let result = run().await;
+    // Like other Unix tools, a closed stdout (`gpt --list-models | head -1`) ends the run
+    // quietly, though not successfully.
+    if stdout::closed() || result.as_ref().is_err_and(|e| is_broken_pipe(e.as_ref())) {
+        std::process::exit(BROKEN_PIPE_STATUS);
+    }
+    result
+}
+
+async fn run() -> Result<(), Box<dyn Error>> {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    apply_provider_defaults(&mut args, &matches);
+    if let Err(e) = validate_args(&args) {
+        eprintln!("Error: {}", e);
+        std::process::exit(2);
+    }
+
+    if let Some(shell) = args.generate_completions {
+        print!("{}", completions::generate(shell, &Args::command()));
+        return Ok(());
+    }
+
+    // Paths may refer to the environment, e.g. --system-file $HOME/prompts/coder.txt.
+    for path in [
+        &mut args.config,
+        &mut args.system_file,
+        &mut args.session,
+        &mut args.output,
+        &mut args.reply_sink,
+        &mut args.manifest,
+        &mut args.record,
+        &mut args.replay,
+        &mut args.batch,
+        &mut args.estimate_batch,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        *path = config::expand_env(path);
+    }
+    if let Some(path) = &args.system_file {
+        match std::fs::read_to_string(path) {
+            Ok(text) => args.system = Some(text.trim_end().to_string()),
+            Err(e) => {
+                eprintln!("Error: could not read {}: {}", path, e);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let mut config = match config::Config::load(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    let mut api_key = std::env::var(args.provider.key_variable()).ok().or_else(|| config.api_key.clone());
+    let have_keys = !config.api_keys.is_empty() || !config.model_keys.is_empty();
+    if api_key.is_none() && !have_keys && args.replay.is_none() && !args.mock && !args.dump_config && args.estimate_batch.is_none() && std::io::stdin().is_terminal() {
+        let path = match &args.config {
+            Some(path) => Some(PathBuf::from(path)),
+            None => config::default_path(),
+        };
+        if let Some(path) = path {
+            match setup::run(&path, &mut config) {
+                Ok(key) => api_key = key,
+                Err(e) => eprintln!("Error: setup failed: {}", e),
+            }
+        }
+    }
+    if let Some(model) = &config.model
+        && matches.value_source("model") != Some(ValueSource::CommandLine)
+    {
+        args.model = model.clone();
+    }
+    if args.system.is_none() {
+        args.system = config.system.clone();
+    }
+    args.colors = config.colors.clone();
+    args.model = config.resolve_model(&args.model);
+
+    if args.dump_config {
+        print_config(&args, &config)?;
+        return Ok(());
+    }
+    if args.list_sessions {
+        list_sessions(&args.tags, &args.model);
+        return Ok(());
+    }
+
+    let currency = match &args.currency {
+        Some(name) => usage::Currency::new(name, args.currency_rate),
+        None => usage::Currency::default(),
+    };
+
+    if let Some(path) = &args.estimate_batch {
+        if let Err(e) = batch::estimate(path, &args, &currency) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // OPENAI_API_KEY (or the --provider's own variable) wins over the config file, and a
+    // single key over a rotation.
+    let rotation = match api_key {
+        Some(key) if std::env::var_os(args.provider.key_variable()).is_some() || config.api_keys.is_empty() => vec![key],
+        _ => config.api_keys.clone(),
+    };
+    let model_keys = config.model_keys.iter().map(|(model, key)| (config.resolve_model(model), key.clone())).collect();
+    let keys = keyring::Keyring::new(rotation, model_keys);
+    if keys.is_empty() && args.replay.is_none() && !args.mock {
+        eprintln!("Error: no API key; set {} or add api_key to the config file.", args.provider.key_variable());
+        std::process::exit(1);
+    }
+
+    let redactor = match redact::Redactor::new(&args.redact_patterns) {
+        Ok(redactor) => redactor,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    // Text given on the command line is redacted once, before anything can send it.
+    let redact_arg = |text: &str, what: &str| match redact(&redactor, text, what) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    if let Some(system) = &args.system {
+        args.system = Some(redact_arg(system, "the system prompt"));
+    }
+    if let Some(prompt) = &args.benchmark {
+        args.benchmark = Some(redact_arg(prompt, "the benchmark prompt"));
+    }
+    args.pins = args.pins.iter().map(|pin| redact_arg(pin, "the pin")).collect();
+
+    let headers = match api::parse_headers(&args.headers) {
+        Ok(headers) => headers,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    let retry = api::RetryPolicy {
+        max_retries: args.max_retries,
+        retry_on: args.retry_on.clone(),
+        jitter: args.retry_jitter,
+        breaker_threshold: args.circuit_breaker_threshold,
+        breaker_cooldown: Duration::from_secs(args.circuit_breaker_cooldown),
+    };
+    let mut client = ApiClient::new(&args.base_url, keys, headers, retry).with_show_headers(args.show_headers);
+    let cassette = match (&args.record, &args.replay) {
+        (Some(path), _) => Some(cassette::Cassette::record(path)),
+        (None, Some(path)) => Some(cassette::Cassette::replay(path)),
+        (None, None) => None,
+    };
+    match cassette {
+        Some(Ok(cassette)) => client = client.with_cassette(cassette),
+        Some(Err(e)) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+        None => {}
+    }
+    if args.mock {
+        client = client.with_mock(&args.mock_reply);
+    }
+    client = client
+        .with_max_requests(args.max_requests)
+        .with_stream_idle_timeout(args.stream_idle_timeout.map(Duration::from_secs));
+    if let Some(secs) = args.wait_for_server
+        && let Err(e) = wait_for_server(&client, &args.base_url, Duration::from_secs(secs)).await
+    {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    let mut options = request_options(&args);
+    if let Some(path) = &args.json_schema {
+        match schema_format(path) {
+            Ok(format) => options.response_format = Some(format),
+            Err(e) => {
+                eprintln!("Error: --json-schema {}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    if args.list_models {
+        return models::list_models(&client, &currency).await;
+    }
+
+    if let Some(prompt) = &args.benchmark {
+        let models: Vec<String> = if args.benchmark_models.is_empty() {
+            vec![args.model.clone()]
+        } else {
+            args.benchmark_models.iter().map(|m| config.resolve_model(m.trim())).collect()
+        };
+        let system = session::system_prompt(args.system.as_deref(), args.target_length, args.inject_datetime.then_some(args.datetime_format.as_str()));
+        return benchmark::run(&client, prompt, &models, system.as_deref(), &options, &currency).await;
+    }
+
+    let tts_command = match (&args.tts_command, tts::default_command()) {
+        (Some(command), _) => Some(command.clone()),
+        (None, Some(command)) => Some(command.to_string()),
+        (None, None) if args.speak => {
+            eprintln!("Error: no default text-to-speech command on this platform; use --tts-command");
+            std::process::exit(2);
+        }
+        (None, None) => None,
+    };
+
+    let compare_models: Vec<String> = args.compare.iter().map(|m| config.resolve_model(m.trim())).collect();
+    let fallback_models: Vec<String> = args.fallback_models.iter().map(|m| config.resolve_model(m)).collect();
+
+    let mut output = match &args.output {
+        Some(path) => match std::fs::OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                eprintln!("Error: could not open {}: {}", path, e);
+                std::process::exit(2);
+            }
+        },
+        None => None,
+    };
+    let mut reply_sink = args.reply_sink.as_deref().map(sink::ReplySink::new);
+
+    if let Some(path) = &args.batch {
+        if let Err(e) = batch::run(&client, path, &args, &options, &redactor, output.as_mut(), reply_sink.as_mut()).await {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let mut lines = input::Lines::new(args.on_invalid_utf8);
+
+    let (mut session, session_path) = match open_session(&args) {
+        Ok(opened) => opened,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    // A resumed session keeps its own model and system prompt unless they're given again.
+    if session.conversation.is_empty() || matches.value_source("model") == Some(ValueSource::CommandLine) {
+        session.model = args.model.clone();
+    }
+    let system_given = matches.value_source("system").is_some() || matches.value_source("system_file").is_some();
+    if args.system.is_some() && (session.conversation.is_empty() || system_given) {
+        session.system = args.system.clone();
+    }
+    session.target_length = args.target_length;
+    session.datetime_format = args.inject_datetime.then(|| args.datetime_format.clone());
+    for tag in &args.tags {
+        if !session.tags.contains(tag) {
+            session.tags.push(tag.clone());
+        }
+    }
+    for pin in &args.pins {
+        if !session.pinned.contains(pin) {
+            session.pinned.push(pin.clone());
+        }
+    }
+
+    #[cfg(feature = "serve")]
+    if let Some(addr) = args.serve.clone() {
+        return serve::run(&addr, client, args, options, redactor, session).await;
+    }
+
+    let mut usage = UsageTotals::default();
+    let started_at = Utc::now();
+
+    println!(
+        "Interactive Chat Session (model: {}{}). Type your message below. Press Ctrl+C to exit.\n",
+        session.model,
+        if args.mock { ", mocked: nothing is sent" } else { "" }
+    );
+
+    let mut transcript = None;
+    if args.debug {
+        let path = format!("chat_transcript_{}.txt", started_at.format("%Y%m%dT%H%M%SZ"));
+        match transcript::Transcript::create(path, &args.transcript_include, session.current_system()) {
+            Ok(created) => transcript = Some(created),
+            Err(e) => eprintln!("Transcript error: {}", e),
+        }
+    }
+    let mut pipeline = Pipeline { redactor, output, reply_sink, transcript };
+
+    let show_indicator = !args.raw && std::io::stdin().is_terminal();
+    // Registered up front so a SIGTERM sent mid-request is still seen at the next prompt.
+    let mut sigterm = term_signal();
+
+    // Lines typed so far inside a `"""` block.
+    let mut block: Option<Vec<String>> = None;
+    // Set when the account runs out of credits, which ends the session with an error.
+    let mut out_of_quota = false;
+    let mut bad_input = false;
+    // Whether anything but blank lines has been read, so empty piped input can be refused.
+    let mut any_input = false;
+    // Set once --confirm-expensive has been answered, since it only asks once.
+    let mut expensive_confirmed = false;
+    // The last streamed reply, if it broke off partway, for /continue.
+    let mut broken_off: Option<BrokenOff> = None;
+    // Named snapshots of the conversation from /checkpoint, kept for this run only.
+    let mut checkpoints: std::collections::BTreeMap<String, Vec<Message>> = std::collections::BTreeMap::new();
+
+    loop {
+        // Nobody is reading the replies any more; end here so the session is still saved.
+        if stdout::closed() {
+            break;
+        }
+        // --tui holds the whole conversation in its own full-screen view.
+        if args.tui {
+            if let Err(e) = tui::run(&client, &mut session, &args, &options, &config, &mut pipeline, &mut usage).await {
+                eprintln!("Error: {}", e);
+            }
+            break;
+        }
+        if show_indicator
+            && block.is_none()
+            && let Some(indicator) = &args.prompt_indicator
+        {
+            print!("{}", indicator);
+            stdout::flush();
+        }
+
+        let idle = async {
+            match args.idle_timeout {
+                Some(secs) => tokio::time::sleep(Duration::from_secs(secs)).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let maybe_line = tokio::select! {
+            maybe_line = lines.next_line() => match maybe_line {
+                Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                    eprintln!("Error: {}", e);
+                    bad_input = true;
+                    break;
+                }
+                maybe_line => maybe_line?,
+            },
+            _ = tokio::signal::ctrl_c() => {
+                // Inside a multi-line block, Ctrl+C only throws the block away.
+                if block.take().is_some() {
+                    println!("\nMulti-line input cancelled.\n");
+                    continue;
+                }
+                println!("\nTermination signal received.");
+                break;
+            }
+            _ = terminated(&mut sigterm) => {
+                println!("\nTermination signal received.");
+                break;
+            }
+            _ = idle => {
+                println!("\nNo input for {}s, ending the session.", args.idle_timeout.unwrap_or_default());
+                break;
+            }
+        };
+        let Some(mut input) = maybe_line else {
+            if !any_input && !std::io::stdin().is_terminal() {
+                eprintln!("Error: there was no message on stdin; nothing was sent.");
+                std::process::exit(1);
+            }
+            break;
+        };
+
+        if input.trim() == MULTILINE_DELIMITER {
+            match block.take() {
+                Some(typed) => input = typed.join("\n"),
+                None => {
+                    block = Some(Vec::new());
+                    continue;
+                }
+            }
+        } else if let Some(typed) = &mut block {
+            typed.push(input);
+            continue;
+        }
+
+        let prompt = input.trim();
+        if prompt.is_empty() {
+            continue;
+        }
+        any_input = true;
+        if let Some(longest) = prompt.lines().map(|line| line.chars().count()).max()
+            && args.max_line_length > 0
+            && longest > args.max_line_length
+            && std::io::stdin().is_terminal()
+        {
+            let question = format!(
+                "That has a line of {} characters (over --max-line-length {}), likely an accidental paste. Send it anyway?",
+                longest, args.max_line_length
+            );
+            if !confirm(&mut lines, &question).await? {
+                println!("Not sent.\n");
+                continue;
+            }
+        }
+
+        let mut outgoing = None;
+        // A question asked with /ask, which is answered but not kept.
+        let mut aside = false;
+        // Parameters for this turn only, from /temp or !name=value directives.
+        let mut turn_options = options.clone();
+        if let Some(command) = commands::parse(prompt) {
+            match command {
+                Ok(Command::Replay(model)) => {
+                    let model = config.resolve_model(&model);
+                    match replay_conversation(&client, &model, &session, &options, &mut usage, &args).await {
+                        Ok(fork) => {
+                            let name = format!("session_fork_{}.json", Utc::now().format("%Y%m%dT%H%M%SZ"));
+                            let path = session::sessions_dir().map_or_else(|| PathBuf::from(&name), |dir| dir.join(&name));
+                            let question = format!("Save the replayed conversation as a forked session in {}?", path.display());
+                            if confirm(&mut lines, &question).await? {
+                                match fork.save(&path) {
+                                    Ok(()) => println!("Forked session saved to {}; resume it with --session {}\n", path.display(), path.display()),
+                                    Err(e) => eprintln!("Could not save forked session: {}", e),
+                                }
+                            }
+                        }
+                        Err(e) => eprintln!("Replay failed: {}", e),
+                    }
+                }
+                Ok(Command::Summarize) => {
+                    let len = session.conversation.len();
+                    if session.summarized >= len {
+                        println!("Nothing new to summarize.\n");
+                    } else if fold_into_summary(&client, &mut session, len, &mut usage, &args).await {
+                        println!("Summary updated.\n");
+                    }
+                    match &session.summary {
+                        Some(summary) => println!("Current summary:\n{}\n", summary),
+                        None => println!("There is no summary yet.\n"),
+                    }
+                }
+                Ok(Command::Summary { edit: false }) => match &session.summary {
+                    Some(summary) => println!("Current summary (covers {} messages):\n{}\n", session.summarized, summary),
+                    None => println!("There is no summary yet.\n"),
+                },
+                Ok(Command::Summary { edit: true }) => {
+                    let current = session.summary.clone().unwrap_or_default();
+                    match editor::edit("summary", &current).await {
+                        Ok(edited) if edited.trim() == current.trim() => println!("Summary unchanged.\n"),
+                        Ok(edited) if edited.trim().is_empty() => {
+                            session.summary = None;
+                            println!("Summary cleared; the {} summarized messages are no longer sent in any form.\n", session.summarized);
+                        }
+                        Ok(edited) => match redact(&pipeline.redactor, edited.trim(), "the summary") {
+                            Ok(edited) => {
+                                session.summary = Some(edited);
+                                println!("Summary saved.\n");
+                            }
+                            Err(e) => eprintln!("Error: {}; the summary is unchanged.", e),
+                        },
+                        Err(e) => eprintln!("Could not edit the summary: {}", e),
+                    }
+                }
+                Ok(Command::Resummarize(from, to)) => {
+                    let position = |id| session.conversation.iter().position(|m| m.id == Some(id));
+                    match (position(from), position(to)) {
+                        (Some(start), Some(end)) if session.conversation[start..=end].iter().any(|m| m.dropped) => {
+                            eprintln!("Some of those messages were dropped by --keep-summarized and can't be summarized again.");
+                        }
+                        (Some(start), Some(end)) if start <= end => {
+                            let previous = (session.summary.take(), session.summarized);
+                            session.summarized = start;
+                            if fold_into_summary(&client, &mut session, end + 1, &mut usage, &args).await {
+                                println!("Summary rebuilt from #{} to #{}:\n{}\n", from, to, session.summary.as_deref().unwrap_or_default());
+                                if start > 0 {
+                                    eprintln!("Note: the {} message(s) before #{} are no longer sent or summarized.", start, from);
+                                }
+                            } else {
+                                (session.summary, session.summarized) = previous;
+                            }
+                        }
+                        (Some(_), Some(_)) => eprintln!("#{} comes after #{}.", from, to),
+                        (None, _) => eprintln!("There is no message #{}.", from),
+                        (_, None) => eprintln!("There is no message #{}.", to),
+                    }
+                }
+                Ok(Command::RebuildSummary) if session.summarized == 0 => println!("There is no summary to rebuild yet.\n"),
+                Ok(Command::RebuildSummary) if session.first_kept() > 0 => {
+                    eprintln!("Some messages were dropped by --keep-summarized, so the summary can't be rebuilt from the full history.");
+                }
+                Ok(Command::RebuildSummary) => {
+                    // The incremental summary is thrown away and redone over the whole history.
+                    let previous = (session.summary.take(), session.summarized);
+                    let end = session.summarized;
+                    session.summarized = 0;
+                    if fold_into_summary(&client, &mut session, end, &mut usage, &args).await {
+                        println!("Summary rebuilt from {} message(s):\n{}\n", end, session.summary.as_deref().unwrap_or_default());
+                    } else {
+                        (session.summary, session.summarized) = previous;
+                    }
+                }
+                Ok(Command::Model(None)) => println!("Current model: {}\n", session.model),
+                Ok(Command::NextModel) if args.favorite_models.is_empty() => {
+                    eprintln!("There are no --favorite-models to switch between.");
+                }
+                Ok(command @ (Command::Model(Some(_)) | Command::NextModel)) => {
+                    let favorites: Vec<String> = args.favorite_models.iter().map(|m| config.resolve_model(m)).collect();
+                    session.model = match command {
+                        Command::Model(Some(model)) => config.resolve_model(&model),
+                        _ => next_favorite(&favorites, &session.model).to_string(),
+                    };
+                    match favorites.iter().position(|m| *m == session.model) {
+                        Some(index) => println!("Switched to model {} (favorite {} of {}).\n", session.model, index + 1, favorites.len()),
+                        None => println!("Switched to model {}.\n", session.model),
+                    }
+                    if session.rehydrated && !rehydrated_size(&session).2 {
+                        println!("The full conversation is too big for {}'s context window.", session.model);
+                        end_rehydration(&client, &mut session, &mut usage, &args).await;
+                    }
+                }
+                Ok(Command::Rehydrate(_)) if args.stateless => {
+                    eprintln!("Nothing is kept in --stateless mode, so there is no history to rehydrate.");
+                }
+                Ok(Command::Rehydrate(false)) if !session.rehydrated => println!("The session isn't rehydrated.\n"),
+                Ok(Command::Rehydrate(false)) => end_rehydration(&client, &mut session, &mut usage, &args).await,
+                Ok(Command::Rehydrate(true)) => {
+                    let (tokens, window, fits) = rehydrated_size(&session);
+                    let window_note = match window {
+                        Some(window) => format!("{}'s window is {} tokens", session.model, window),
+                        None => format!("{}'s window is unknown", session.model),
+                    };
+                    if fits {
+                        session.rehydrated = true;
+                        println!("Sending the full conversation (~{} tokens; {}) until it outgrows the window.", tokens, window_note);
+                        if session.first_kept() > 0 {
+                            println!("The {} message(s) dropped by --keep-summarized are still covered by the summary.", session.first_kept());
+                        }
+                        println!();
+                    } else {
+                        eprintln!("The full conversation (~{} tokens) is too big ({}); keeping the summary.", tokens, window_note);
+                    }
+                }
+                Ok(Command::Export(_)) if args.stateless => {
+                    eprintln!("Nothing is kept in --stateless mode, so there is nothing to export.");
+                }
+                Ok(Command::Export(path)) => match session.save(&path) {
+                    Ok(()) => println!("Conversation exported to {}\n", path),
+                    Err(e) => eprintln!("Could not export to {}: {}", path, e),
+                },
+                Ok(Command::ExtractCode(_)) if args.stateless => {
+                    eprintln!("Nothing is kept in --stateless mode, so there is no code to extract.");
+                }
+                Ok(Command::ExtractCode(dir)) => {
+                    let extracted = codeblocks::extract(&session.conversation, Path::new(&dir));
+                    let existing = extracted.existing();
+                    if extracted.files.is_empty() {
+                        println!("The replies have no code blocks.\n");
+                    } else if existing.is_empty()
+                        || confirm(&mut lines, &format!("Overwrite {} existing file(s) in {}?", existing.len(), dir)).await?
+                    {
+                        match extracted.write() {
+                            Ok(()) => println!(),
+                            Err(e) => eprintln!("Could not write the code to {}: {}", dir, e),
+                        }
+                    }
+                }
+                Ok(Command::Pin(Some(fact))) => {
+                    let fact = match redact(&pipeline.redactor, &fact, "the pin") {
+                        Ok(fact) => fact,
+                        Err(e) => {
+                            eprintln!("Error: {}; nothing was pinned.", e);
+                            continue;
+                        }
+                    };
+                    session.pinned.push(fact);
+                    println!("Pinned #{}.\n", session.pinned.len());
+                }
+                Ok(Command::PushSystem(text)) => {
+                    let text = match redact(&pipeline.redactor, &text, "the system prompt") {
+                        Ok(text) => text,
+                        Err(e) => {
+                            eprintln!("Error: {}; the system prompt is unchanged.", e);
+                            continue;
+                        }
+                    };
+                    session.system_stack.push(text);
+                    println!("System prompt pushed ({} deep); /pop-system goes back.\n", session.system_stack.len());
+                }
+                Ok(Command::PopSystem) => match session.system_stack.pop() {
+                    Some(_) => match session.current_system() {
+                        Some(system) => println!("Back to the system prompt: {}\n", system),
+                        None => println!("Back to no system prompt.\n"),
+                    },
+                    None => eprintln!("No pushed system prompt to pop."),
+                },
+                Ok(Command::Info) => {
+                    println!("Model: {}", session.model);
+                    match session.current_system() {
+                        Some(system) if !session.system_stack.is_empty() => {
+                            println!("System prompt: {} (pushed, {} deep)", system, session.system_stack.len())
+                        }
+                        Some(system) => println!("System prompt: {}", system),
+                        None => println!("System prompt: none"),
+                    }
+                    println!(
+                        "Messages: {} ({} summarized), pins: {}",
+                        session.conversation.len(),
+                        session.summarized,
+                        session.pinned.len()
+                    );
+                    println!();
+                }
+                Ok(Command::Pin(None)) if session.pinned.is_empty() => println!("Nothing is pinned.\n"),
+                Ok(Command::Pin(None)) => {
+                    for (i, fact) in session.pinned.iter().enumerate() {
+                        println!("{}. {}", i + 1, fact);
+                    }
+                    println!();
+                }
+                Ok(Command::Unpin(None)) => {
+                    println!("Removed {} pin(s).\n", session.pinned.len());
+                    session.pinned.clear();
+                }
+                Ok(Command::Unpin(Some(n))) if n <= session.pinned.len() => {
+                    let fact = session.pinned.remove(n - 1);
+                    println!("Unpinned: {}\n", fact);
+                }
+                Ok(Command::Unpin(Some(n))) => eprintln!("There is no pin #{}.", n),
+                Ok(Command::Run(_)) if args.no_run => eprintln!("/run is disabled by --no-run."),
+                Ok(Command::Run(command)) => {
+                    if confirm(&mut lines, &format!("Run `{}`?", command)).await? {
+                        match run_shell_command(&command).await {
+                            Ok(output) => outgoing = Some(output),
+                            Err(e) => eprintln!("Could not run `{}`: {}", command, e),
+                        }
+                    }
+                }
+                Ok(Command::Delete(id)) => match (session.summarized, session.delete(id)) {
+                    (summarized, Some((message, index))) => {
+                        println!("Deleted #{} ({}).", id, message.role);
+                        if index < summarized {
+                            eprintln!("Note: it was already folded into the summary, which still reflects it.");
+                        }
+                        let before = index.checked_sub(1).and_then(|i| session.conversation.get(i));
+                        if let (Some(before), Some(after)) = (before, session.conversation.get(index))
+                            && before.role == after.role
+                        {
+                            eprintln!("Note: this leaves two {} messages in a row.", before.role);
+                        }
+                        println!();
+                    }
+                    (_, None) => eprintln!("There is no message #{}.", id),
+                },
+                Ok(Command::ContextAdd(arg)) => {
+                    let path = config::expand_env(&arg);
+                    let (text, source) = if Path::new(&path).is_file() {
+                        match std::fs::read_to_string(&path) {
+                            Ok(text) => (text, path),
+                            Err(e) => {
+                                eprintln!("Error: could not read {}: {}", path, e);
+                                continue;
+                            }
+                        }
+                    } else {
+                        (arg, "text".to_string())
+                    };
+                    let text = match redact(&pipeline.redactor, &text, "the staged context") {
+                        Ok(text) => text,
+                        Err(e) => {
+                            eprintln!("Error: {}; nothing was staged.", e);
+                            continue;
+                        }
+                    };
+                    println!(
+                        "Staged {} characters of context ({}) for your next message; {} item(s) staged.\n",
+                        text.chars().count(),
+                        source,
+                        session.staged.len() + 1
+                    );
+                    session.staged.push(text.trim_end().to_string());
+                }
+                Ok(Command::Fetch(url)) => match fetch::fetch(&url).await {
+                    Ok(mut page) => {
+                        page.text = match redact(&pipeline.redactor, &page.text, "the page") {
+                            Ok(text) => text,
+                            Err(e) => {
+                                eprintln!("Error: {}; nothing was staged.", e);
+                                continue;
+                            }
+                        };
+                        println!(
+                            "Staged {} characters of {} from {} for your next message; {} item(s) staged.",
+                            page.text.chars().count(),
+                            page.content_type,
+                            page.title.as_deref().unwrap_or(&url),
+                            session.staged.len() + 1
+                        );
+                        if page.truncated {
+                            println!("The page was too long, so only the first {} characters were kept.", fetch::MAX_CHARS);
+                        }
+                        println!();
+                        let heading = match &page.title {
+                            Some(title) => format!("{} ({})", title, url),
+                            None => url,
+                        };
+                        session.staged.push(format!("Fetched from {}:\n\n{}", heading, page.text));
+                    }
+                    Err(e) => eprintln!("Error: {}", e),
+                },
+                Ok(Command::Attach(arg)) => {
+                    let path = config::expand_env(&arg);
+                    let data = match std::fs::read(&path) {
+                        Ok(data) => data,
+                        Err(e) => {
+                            eprintln!("Error: could not read {}: {}", path, e);
+                            continue;
+                        }
+                    };
+                    // Text files are redacted like messages; other files go up as they are.
+                    let data = match String::from_utf8(data) {
+                        Ok(text) => match redact(&pipeline.redactor, &text, &path) {
+                            Ok(text) => text.into_bytes(),
+                            Err(e) => {
+                                eprintln!("Error: {}; nothing was uploaded.", e);
+                                continue;
+                            }
+                        },
+                        Err(e) => e.into_bytes(),
+                    };
+                    let name = Path::new(&path).file_name().map_or(path.clone(), |n| n.to_string_lossy().into_owned());
+                    match client.upload_file(&name, &data).await {
+                        Ok(file_id) => {
+                            println!("Uploaded {} as {}; it will be attached to your next message.\n", name, file_id);
+                            session.attachments.push(Attachment { file_id, name });
+                        }
+                        Err(e) => eprintln!("Could not upload {}: {}", path, e),
+                    }
+                }
+                Ok(Command::Developer(_)) if args.stateless => {
+                    eprintln!("Nothing is kept in --stateless mode; use --system for standing instructions.");
+                }
+                Ok(Command::Developer(text)) => {
+                    if !models::uses_developer_role(&session.model) {
+                        eprintln!(
+                            "Note: {} doesn't take developer messages, so this will be sent as a system message.",
+                            session.model
+                        );
+                    }
+                    let text = match redact(&pipeline.redactor, &text, "the developer instruction") {
+                        Ok(text) => text,
+                        Err(e) => {
+                            eprintln!("Error: {}; nothing was added.", e);
+                            continue;
+                        }
+                    };
+                    session.push(Message::new("developer", text));
+                    println!("Developer instruction added for the rest of the conversation.\n");
+                }
+                Ok(Command::Compact(_)) if args.stateless => {
+                    eprintln!("Nothing is kept in --stateless mode, so there is nothing to compact.");
+                }
+                Ok(Command::Compact(count)) if count > session.conversation.len() => {
+                    eprintln!("There are only {} message(s) in the conversation.", session.conversation.len());
+                }
+                Ok(Command::Compact(count)) if session.conversation[..count].iter().any(|m| m.dropped) => {
+                    eprintln!("Some of those messages were dropped by --keep-summarized and can't be summarized again.");
+                }
+                Ok(Command::Compact(count)) => {
+                    let oldest = &session.conversation[..count];
+                    let id = |m: &Message| m.id.unwrap_or_default();
+                    let range = format!("#{} to #{}", id(&oldest[0]), id(&oldest[count - 1]));
+                    match update_summary(&client, args.provider.summary_model(), None, &session.pinned, oldest, &summary_options(&args), &mut usage).await {
+                        Ok(summary) => {
+                            let message = Message::new("system", format!("Summary of messages {}, compacted:\n{}", range, summary));
+                            session.compact(count, message);
+                            println!("Replaced messages {} with a summary (#{}).\n", range, session.last_id);
+                            if let Some(transcript) = &mut pipeline.transcript
+                                && let Err(e) = transcript.note(&format!("Compacted messages {} into #{}", range, session.last_id))
+                            {
+                                eprintln!("Transcript error: {}", e);
+                            }
+                        }
+                        Err(e) => eprintln!("Could not compact: {}", e),
+                    }
+                }
+                Ok(Command::Checkpoint(_) | Command::Diff(..)) if args.stateless => {
+                    eprintln!("Nothing is kept in --stateless mode, so there is nothing to checkpoint.");
+                }
+                Ok(Command::Checkpoint(None)) if checkpoints.is_empty() => println!("No checkpoints yet; /checkpoint NAME makes one.\n"),
+                Ok(Command::Checkpoint(None)) => {
+                    for (name, conversation) in &checkpoints {
+                        println!("{}: {} message(s)", name, conversation.len());
+                    }
+                    println!();
+                }
+                Ok(Command::Checkpoint(Some(name))) => {
+                    println!("Checkpoint '{}' saved ({} message(s)).\n", name, session.conversation.len());
+                    checkpoints.insert(name, session.conversation.clone());
+                }
+                Ok(Command::Diff(a, b)) => {
+                    let find = |name: &str| match name {
+                        commands::NOW => Some(session.conversation.as_slice()),
+                        _ => checkpoints.get(name).map(Vec::as_slice),
+                    };
+                    match (find(&a), find(&b)) {
+                        (Some(old), Some(new)) => println!("{}", diff::render(&a, old, &b, new)),
+                        (None, _) => eprintln!("There is no checkpoint '{}'.", a),
+                        (_, None) => eprintln!("There is no checkpoint '{}'.", b),
+                    }
+                }
+                Ok(Command::Merge) => match session.merge_consecutive() {
+                    0 => println!("No adjacent messages share a role.\n"),
+                    n => println!("Merged {} message(s) into the one before.\n", n),
+                },
+                Ok(Command::Template(arg)) if arg.is_empty() => {
+                    if config.templates.is_empty() {
+                        println!("No templates defined; add them to the [templates] section of the config file.\n");
+                    }
+                    for (name, text) in &config.templates {
+                        println!("{}: {}", name, text);
+                    }
+                    println!();
+                }
+                Ok(Command::Ask(question)) => {
+                    aside = true;
+                    outgoing = Some(question);
+                }
+                Ok(Command::Temp(value, message)) => match directives::apply("temperature", &value, &mut turn_options) {
+                    Ok(()) => outgoing = Some(message),
+                    Err(e) => eprintln!("{}", e),
+                },
+                Ok(Command::Continue) => match broken_off.take() {
+                    Some(broken) => broken_off = continue_broken_off(&client, broken, &turn_options, &mut usage, &args, &mut session).await,
+                    None => println!("There is no broken-off reply to continue.\n"),
+                },
+                Ok(Command::Template(arg)) => {
+                    let rendered = template::parse_args(&arg).and_then(|(name, vars)| {
+                        let text = config.templates.get(&name).ok_or_else(|| format!("no template named '{}'", name))?;
+                        template::render(text, &vars)
+                    });
+                    match rendered {
+                        Ok(text) => outgoing = Some(text),
+                        Err(e) => eprintln!("Template error: {}", e),
+                    }
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+            // Only /run, /temp, /template and /ask go on to send something.
+            if outgoing.is_none() {
+                continue;
+            }
+        }
+        let prompt = outgoing.as_deref().unwrap_or_else(|| commands::unescape(prompt));
+        let prompt = match directives::strip(prompt, &mut turn_options) {
+            Ok("") => {
+                eprintln!("Nothing to send after the directives.");
+                continue;
+            }
+            Ok(prompt) => prompt,
+            Err(e) => {
+                eprintln!("Error: {}; nothing was sent.", e);
+                continue;
+            }
+        };
+
+        let prompt = match pipeline.outgoing(&args, prompt).await {
+            Ok(prompt) => prompt,
+            Err(e) => {
+                eprintln!("Error: {}; nothing was sent.", e);
+                continue;
+            }
+        };
+        if args.confirm_duplicates
+            && std::io::stdin().is_terminal()
+            && session.conversation.iter().rev().find(|m| m.role == "user").is_some_and(|last| last.content == prompt)
+            && !confirm(&mut lines, "That is the same as your last message. Send it again?").await?
+        {
+            println!("Not sent.\n");
+            continue;
+        }
+        if args.confirm_expensive && !expensive_confirmed && std::io::stdin().is_terminal() {
+            let targets = if compare_models.is_empty() { std::slice::from_ref(&session.model) } else { compare_models.as_slice() };
+            let expensive = targets
+                .iter()
+                .find_map(|model| models::lookup(model).filter(|info| info.output_price >= args.expensive_threshold).map(|info| (model, info)));
+            if let Some((model, info)) = expensive {
+                let question = format!(
+                    "{} costs about {} per million input tokens and {} per million output tokens. Send to it anyway?",
+                    model,
+                    currency.format(info.input_price, 2),
+                    currency.format(info.output_price, 2)
+                );
+                if !confirm(&mut lines, &question).await? {
+                    println!("Not sent; /model switches to a cheaper model.\n");
+                    continue;
+                }
+                expensive_confirmed = true;
+            }
+        }
+        let window = if compare_models.is_empty() { std::slice::from_ref(&session.model) } else { compare_models.as_slice() }
+            .iter()
+            .filter_map(|model| models::lookup(model).map(|info| (model, info.context_window as usize)))
+            .min_by_key(|&(_, window)| window);
+        let prompt = match window {
+            Some((model, window)) => {
+                // Room for the message once the system prompt and the reply are set aside.
+                let fixed = session.current_system().map_or(0, tokens::estimate) + args.max_tokens.map_or(REPLY_RESERVE, |n| n as usize);
+                let budget = window.saturating_sub(fixed);
+                let size = tokens::estimate(&prompt);
+                // With no room at all, truncating can't help; let the API say so.
+                if size <= budget || budget == 0 {
+                    prompt
+                } else {
+                    let keeping = match args.truncate_mode {
+                        tokens::TruncateMode::Head => "the beginning",
+                        tokens::TruncateMode::Tail => "the end",
+                        tokens::TruncateMode::Both => "the beginning and the end",
+                    };
+                    let question = format!(
+                        "Your message is ~{} tokens, more than the ~{} that fit in {}'s {}-token context window. Truncate it to fit, keeping {}?",
+                        size, budget, model, window, keeping
+                    );
+                    if std::io::stdin().is_terminal() && !confirm(&mut lines, &question).await? {
+                        println!("Not sent.\n");
+                        continue;
+                    }
+                    let (truncated, dropped) = tokens::truncate(&prompt, budget, args.truncate_mode);
+                    eprintln!(
+                        "Truncated your message from ~{} to ~{} tokens for {}, dropping {} characters and keeping {}.",
+                        size,
+                        tokens::estimate(&truncated),
+                        model,
+                        dropped,
+                        keeping
+                    );
+                    truncated
+                }
+            }
+            None => prompt,
+        };
+        let user_message = Message {
+            attachments: session.attachments.clone(),
+            ..Message::new("user", prompt)
+        };
+        if args.echo_input {
+            display_input(&args, &user_message);
+        }
+        let context_messages = if args.stateless {
+            // Only this message goes out, and it is forgotten once answered.
+            let mut context: Vec<Message> = session.system_message().into_iter().collect();
+            context.extend(session.staged_message());
+            context.push(user_message);
+            context
+        } else {
+            session.push(user_message);
+            let context = conversation_context(&session, &args);
+            if aside {
+                session.pop_unanswered();
+            }
+            context
+        };
+
+        match check_context(&args, &context_messages) {
+            Ok(None) => {}
+            Ok(Some(problem)) => eprintln!("Warning: {}.", problem),
+            Err(problem) => {
+                eprintln!("Error: {}; nothing was sent.", problem);
+                if !args.stateless && !aside {
+                    session.pop_unanswered();
+                }
+                continue;
+            }
+        }
+
+        broken_off = None;
+        let mut request_started;
+        let mut retried = false;
+        let reply = loop {
+            request_started = Instant::now();
+            let reply = if args.stream {
+                request_with_fallback(&client, &session.model, &fallback_models, &context_messages, &turn_options, &mut usage, Some(&args)).await
+            } else if compare_models.is_empty() {
+                let request = request_with_fallback(&client, &session.model, &fallback_models, &context_messages, &turn_options, &mut usage, None);
+                show_thinking(&args, request).await
+            } else {
+                compare_replies(&client, &compare_models, &context_messages, &turn_options, &mut usage, &mut lines).await
+            };
+            let retry = match &reply {
+                Ok(Some((_, choice))) if compare_models.is_empty() && is_empty_reply(choice) => match args.on_empty_reply {
+                    EmptyReply::Retry => !retried,
+                    EmptyReply::Ask if std::io::stdin().is_terminal() => {
+                        confirm(&mut lines, "The model sent back an empty reply. Send the request again?").await?
+                    }
+                    _ => false,
+                },
+                _ => false,
+            };
+            if !retry {
+                break reply;
+            }
+            retried = true;
+        };
+        let latency = request_started.elapsed();
+        match reply {
+            Ok(Some((_, choice))) if is_empty_reply(&choice) => {
+                session.pop_unanswered();
+                eprintln!(
+                    "Warning: the model sent back an empty reply (finish reason: {}). It was not kept, and neither was your message; send it again to retry.",
+                    choice.finish_reason.as_deref().unwrap_or("none")
+                );
+            }
+            Ok(Some((model, choice))) => {
+                // What --stream has already printed; a refusal isn't streamed, so it is shown as usual.
+                let streamed = (args.stream && !choice.message.content.is_empty()).then(|| choice.message.content.clone());
+                if streamed.is_some() && choice.truncated() && !args.auto_continue {
+                    println!();
+                }
+                let ChatChoice { message: reply, logprobs, .. } = if choice.truncated() && compare_models.is_empty() {
+                    continue_truncated(&client, &model, &context_messages, choice, &turn_options, &mut usage, &args, &mut lines).await
+                } else {
+                    choice
+                };
+                let message = Message {
+                    model: Some(model.clone()),
+                    ..Message::reply(&reply)
+                };
+                // --extract only changes what is shown; the conversation keeps the whole reply.
+                let shown = match &args.extract {
+                    Some(field) => match extract_field(&message.content, field) {
+                        Ok(content) => Message { content, ..message.clone() },
+                        Err(e) => {
+                            eprintln!("Error: {}; showing the whole reply.", e);
+                            message.clone()
+                        }
+                    },
+                    None => message.clone(),
+                };
+                // Compared replies have already been shown side by side.
+                if let Some(streamed) = &streamed {
+                    finish_streamed(&args, streamed, &reply.content);
+                } else if !args.output_only && compare_models.is_empty() {
+                    if args.think_aloud && !args.raw
+                        && let Some(reasoning) = &reply.reasoning_content
+                    {
+                        display_reasoning(reasoning);
+                    }
+                    if args.format_code && !args.raw {
+                        let formatted = Message {
+                            content: codefmt::format_blocks(&shown.content, &config.formatters).await,
+                            ..shown.clone()
+                        };
+                        display_reply(&args, &model, &formatted);
+                    } else {
+                        display_reply(&args, &model, &shown);
+                    }
+                    if args.show_logprobs {
+                        match logprobs.as_ref().and_then(|l| l.content.as_deref()) {
+                            Some(tokens) => display_logprobs(tokens),
+                            None => eprintln!("No log probabilities were returned for this reply.\n"),
+                        }
+                    }
+                }
+                if args.show_latency {
+                    eprintln!("[{:.1}s]\n", latency.as_secs_f64());
+                }
+                if args.notify && request_started.elapsed() >= NOTIFY_AFTER {
+                    notify_reply_ready(&model);
+                }
+                #[cfg(feature = "audio")]
+                if let Some(spoken) = &reply.audio
+                    && let Err(e) = audio::output(&args, spoken).await
+                {
+                    eprintln!("Audio error: {}", e);
+                }
+                if args.speak
+                    && let Some(command) = &tts_command
+                    && let Err(e) = tts::speak(command, &tts::speech_text(&message.display_content())).await
+                {
+                    eprintln!("Speech error: {}", e);
+                }
+                pipeline.deliver(&args, &model, &message, &shown).await;
+                // Staged context and attachments are for one request only.
+                session.staged.clear();
+                session.attachments.clear();
+                if !args.stateless && !aside {
+                    session.push(message);
+                }
+            }
+            Ok(None) => {
+                session.pop_unanswered();
+                eprintln!("The API returned an empty reply (no choices). Your message was not kept; send it again to retry.");
+            }
+            Err(e) if api::partial_reply(e.as_ref()).is_some() => {
+                let partial = api::partial_reply(e.as_ref()).unwrap_or_default().to_string();
+                println!("\n");
+                eprintln!("Error: {}", e);
+                eprintln!("The reply broke off partway; the part that arrived was kept. /continue asks for the rest.");
+                session.staged.clear();
+                session.attachments.clear();
+                let kept = !args.stateless && !aside;
+                if kept {
+                    session.push(Message {
+                        model: Some(session.model.clone()),
+                        ..Message::new("assistant", partial.clone())
+                    });
+                }
+                broken_off = Some(BrokenOff {
+                    model: session.model.clone(),
+                    context: context_messages,
+                    partial,
+                    kept,
+                });
+            }
+            Err(e) if e.is::<Cancelled>() => {
+                let partial = e.downcast_ref::<Cancelled>().map(|c| c.partial.trim_end()).unwrap_or_default();
+                if partial.trim().is_empty() {
+                    session.pop_unanswered();
+                    println!("\nStopped before any of the reply arrived; your message was not kept.\n");
+                } else {
+                    println!("\n");
+                    println!("Stopped; the reply so far was kept, marked {}.\n", TRUNCATED_BY_USER);
+                    session.staged.clear();
+                    session.attachments.clear();
+                    if !args.stateless && !aside {
+                        session.push(Message {
+                            model: Some(session.model.clone()),
+                            ..Message::new("assistant", format!("{} {}", partial, TRUNCATED_BY_USER))
+                        });
+                    }
+                }
+            }
+            Err(e) if api::is_out_of_quota(e.as_ref()) => {
+                session.pop_unanswered();
+                eprintln!("Error: {}", api::OUT_OF_QUOTA);
+                out_of_quota = true;
+                break;
+            }
+            Err(e) => {
+                session.pop_unanswered();
+                eprintln!("Error: {}", e);
+                eprintln!("Your message was not kept; send it again to retry.");
+            }
+        }
+
+        pipeline.after_turn(&client, &mut session, &mut usage, &args).await;
+    }
+
+    let mut session_path = session_path;
+    if args.auto_title
+        && !out_of_quota
+        && session.title.is_none()
+        && !session.conversation.is_empty()
+        && let Some(path) = &session_path
+        && !path.exists()
+    {
+        match title_conversation(&client, args.provider.summary_model(), &session.conversation, &summary_options(&args), &mut usage).await {
+            Ok(title) => {
+                // Only names we picked ourselves are changed; a --session path is kept.
+                if args.session.is_none() {
+                    session_path = Some(session::titled_path(path, &title));
+                }
+                session.title = Some(title);
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    finalize_session(&args, &session, session_path.as_deref(), pipeline.transcript.as_mut(), &usage, started_at)?;
+    if out_of_quota || bad_input {
+        std::process::exit(1);
+    }
+
+    println!("Session ended. Press enter to exit.");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(flags: &[&str]) -> Args {
+        Args::try_parse_from(std::iter::once("gpt").chain(flags.iter().copied())).unwrap()
+    }
+
+    /// A client that answers every request locally with `template`, as `--mock` does.
+    fn mock_client(template: &str) -> ApiClient {
+        let retry = api::RetryPolicy {
+            max_retries: 0,
+            retry_on: Vec::new(),
+            jitter: 0.0,
+            breaker_threshold: 0,
+            breaker_cooldown: Duration::ZERO,
+        };
+        let keys = keyring::Keyring::new(vec!["test".to_string()], Default::default());
+        ApiClient::new("http://localhost", keys, reqwest::header::HeaderMap::new(), retry).with_mock(template)
+    }
+
+    #[tokio::test]
+    async fn summary_stays_under_its_cap() {
+        let args = args(&["--summary-max-tokens", "200"]);
+        // Echoing the prompt back makes every summary update as long as possible, and
+        // compression no help at all.
+        let client = mock_client(mock::DEFAULT_TEMPLATE);
+        let mut usage = UsageTotals::default();
+        let mut session = Session::new("gpt-4o");
+        for turn in 0..40 {
+            session.push(Message::new("user", format!("Question {}: {}", turn, "tell me more ".repeat(20))));
+            session.push(Message::new("assistant", format!("Answer {}: {}", turn, "here is more ".repeat(20))));
+            let recent_start = session.recent_start(RECENT_EXCHANGES);
+            if session.summarized < recent_start {
+                assert!(fold_into_summary(&client, &mut session, recent_start, &mut usage, &args).await);
+            }
+            if let Some(summary) = &session.summary {
+                assert!(tokens::estimate(summary) <= 200, "turn {}: {} tokens", turn, tokens::estimate(summary));
+            }
+        }
+        assert!(session.summarized > 0);
+    }
+
+    #[tokio::test]
+    async fn summaries_use_the_providers_model() {
+        let client = mock_client("{model}");
+        let mut usage = UsageTotals::default();
+        for (provider, model) in [("openai", "gpt-4o"), ("anthropic", "claude-haiku-4-5")] {
+            let args = args(&["--provider", provider, "--model", "my-model"]);
+            let mut session = Session::new(&args.model);
+            session.push(Message::new("user", "hi"));
+            session.push(Message::new("assistant", "hello"));
+            assert!(fold_into_summary(&client, &mut session, 2, &mut usage, &args).await);
+            assert_eq!(session.summary.as_deref(), Some(model));
+            let title = title_conversation(&client, args.provider.summary_model(), &session.conversation, &summary_options(&args), &mut usage).await;
+            assert_eq!(title.unwrap(), model);
+        }
+    }
+
+    /// A session of `exchanges` user/assistant pairs, with everything but the last
+    /// `RECENT_EXCHANGES` folded into `summary`.
+    fn summarized_session(exchanges: usize, summary: &str) -> Session {
+        let mut session = Session::new("gpt-4o");
+        for i in 0..exchanges {
+            session.push(Message::new("user", format!("question {}", i)));
+            session.push(Message::new("assistant", format!("answer {}", i)));
+        }
+        session.summary = Some(summary.to_string());
+        session.summarized = session.recent_start(RECENT_EXCHANGES);
+        session
+    }
+
+    #[test]
+    fn summary_in_the_context_is_labelled() {
+        let session = summarized_session(15, "The user asked about Rust lifetimes.");
+        let context = build_context(&session, None);
+        let summaries: Vec<&Message> = context.iter().filter(|m| m.content.contains("Rust lifetimes")).collect();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].role, "system");
+        assert!(summaries[0].content.starts_with("Summary of the earlier conversation"));
+        assert!(summaries[0].content.contains("not instructions"));
+        // The summarized messages themselves are not sent.
+        assert!(!context.iter().any(|m| m.content == "question 0"));
+    }
+
+    #[test]
+    fn no_summary_label_before_anything_is_summarized() {
+        let mut session = Session::new("gpt-4o");
+        session.push(Message::new("user", "hello"));
+        let context = build_context(&session, None);
+        assert!(!context.iter().any(|m| m.content.starts_with(SUMMARY_LABEL)));
+    }
+
+    #[test]
+    fn max_context_messages_trims_the_tail() {
+        let mut session = summarized_session(15, "Earlier talk.");
+        session.system = Some("Be brief.".to_string());
+        let uncapped = build_context(&session, None);
+        assert_eq!(uncapped.len(), 2 + RECENT_EXCHANGES * 2);
+
+        let context = build_context(&session, Some(6));
+        assert_eq!(context.len(), 6);
+        assert_eq!(context[0].content, "Be brief.");
+        assert!(context[1].content.starts_with(SUMMARY_LABEL));
+        let tail: Vec<&str> = context[2..].iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(tail, ["question 13", "answer 13", "question 14", "answer 14"]);
+    }
+
+    #[test]
+    fn max_context_messages_keeps_the_latest_message() {
+        let mut session = summarized_session(15, "Earlier talk.");
+        session.system = Some("Be brief.".to_string());
+        session.push(Message::new("user", "latest"));
+        let context = build_context(&session, Some(1));
+        assert_eq!(context.len(), 3);
+        assert_eq!(context.last().unwrap().content, "latest");
+    }
+
+    /// Asserts that `flags` are rejected at startup with an error mentioning `expected`.
+    fn rejected(flags: &[&str], expected: &str) {
+        match validate_args(&args(flags)) {
+            Ok(()) => panic!("{:?} was accepted", flags),
+            Err(e) => assert!(e.contains(expected), "{:?}: {}", flags, e),
+        }
+    }
+
+    /// Asserts that clap itself refuses `flags` as conflicting.
+    fn conflicting(flags: &[&str]) {
+        let error = Args::try_parse_from(std::iter::once("gpt").chain(flags.iter().copied())).err();
+        assert_eq!(error.map(|e| e.kind()), Some(clap::error::ErrorKind::ArgumentConflict), "{:?}", flags);
+    }
+
+    /// Parses `flags` as the command line would, provider defaults included.
+    fn resolved(flags: &[&str]) -> Args {
+        let matches = Args::command().try_get_matches_from(std::iter::once("gpt").chain(flags.iter().copied())).unwrap();
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        apply_provider_defaults(&mut args, &matches);
+        args
+    }
+
+    #[test]
+    fn provider_default_model_unless_one_is_given() {
+        let args = resolved(&[]);
+        assert_eq!((args.model.as_str(), args.base_url.as_str()), ("gpt-4o", api::DEFAULT_BASE_URL));
+        let args = resolved(&["--provider", "anthropic"]);
+        assert_eq!(args.model, "claude-sonnet-4-5");
+        assert_eq!(args.base_url, "https://api.anthropic.com/v1");
+        assert_eq!(args.provider.summary_model(), "claude-haiku-4-5");
+        let args = resolved(&["--provider", "anthropic", "--model", "claude-opus-4-1", "--base-url", "http://localhost:8080/v1"]);
+        assert_eq!(args.model, "claude-opus-4-1");
+        assert_eq!(args.base_url, "http://localhost:8080/v1");
+        // Giving the OpenAI default explicitly still counts as choosing it.
+        assert_eq!(resolved(&["--provider", "anthropic", "--model", "gpt-4o"]).model, "gpt-4o");
+    }
+
+    #[test]
+    fn plain_flags_are_accepted() {
+        assert_eq!(validate_args(&args(&[])), Ok(()));
+        assert_eq!(validate_args(&args(&["--raw", "--stateless", "--stream"])), Ok(()));
+    }
+
+    #[test]
+    fn compress_session_with_stateless() {
+        rejected(&["--stateless", "--compress-session"], "--compress-session");
+    }
+
+    #[test]
+    fn keep_summarized_with_stateless() {
+        rejected(&["--stateless", "--keep-summarized", "3"], "--keep-summarized");
+    }
+
+    #[test]
+    fn auto_title_with_stateless() {
+        rejected(&["--stateless", "--auto-title"], "--auto-title");
+    }
+
+    #[test]
+    fn extract_code_with_stateless() {
+        rejected(&["--stateless", "--extract-code", "out"], "--extract-code");
+    }
+
+    #[test]
+    fn tag_with_stateless() {
+        rejected(&["--stateless", "--tag", "work"], "--tag");
+        assert_eq!(validate_args(&args(&["--stateless", "--tag", "work", "--list-sessions"])), Ok(()));
+    }
+
+    #[test]
+    fn timestamps_with_raw() {
+        rejected(&["--raw", "--timestamps"], "--timestamps has no effect with --raw");
+    }
+
+    #[test]
+    fn assistant_name_with_raw() {
+        rejected(&["--raw", "--assistant-name", "Bot"], "--assistant-name");
+    }
+
+    #[test]
+    fn thinking_delay_with_raw() {
+        rejected(&["--raw", "--thinking-delay", "2"], "--thinking-delay has no effect with --raw");
+    }
+
+    #[test]
+    fn typewriter_natural_with_raw() {
+        rejected(&["--raw", "--typewriter-natural"], "--typewriter-natural");
+    }
+
+    #[test]
+    fn timestamps_with_stream() {
+        rejected(&["--stream", "--timestamps"], "--timestamps has no effect with --stream");
+    }
+
+    #[test]
+    fn thinking_delay_with_stream() {
+        rejected(&["--stream", "--thinking-delay", "2"], "--thinking-delay has no effect with --stream");
+    }
+
+    #[test]
+    fn fallback_model_with_compare() {
+        rejected(&["--compare", "gpt-4o,gpt-4.1", "--fallback-model", "gpt-4o-mini"], "--fallback-model");
+    }
+
+    #[test]
+    fn show_latency_with_compare() {
+        rejected(&["--compare", "gpt-4o,gpt-4.1", "--show-latency"], "--show-latency");
+    }
+
+    #[test]
+    fn show_logprobs_with_prompt_format() {
+        rejected(&["--show-logprobs", "--format", "prompt"], "--show-logprobs");
+    }
+
+    #[test]
+    fn verify_summary_with_no_passes() {
+        rejected(&["--verify-summary", "--verify-summary-passes", "0"], "--verify-summary-passes");
+    }
+
+    #[cfg(feature = "audio")]
+    #[test]
+    fn audio_player_with_audio_save() {
+        rejected(&["--audio-output", "--audio-save", "out", "--audio-player", "mpv"], "--audio-player");
+    }
+
+    #[test]
+    fn out_of_range_values() {
+        rejected(&["--retry-jitter", "1.5"], "--retry-jitter");
+        rejected(&["--currency", "EUR", "--currency-rate", "0"], "--currency-rate");
+        rejected(&["--max-context-messages", "0"], "--max-context-messages");
+        rejected(&["--summary-max-tokens", "0"], "--summary-max-tokens");
+        rejected(&["--target-length", "0"], "--target-length");
+        rejected(&["--stream", "--stream-idle-timeout", "0"], "--stream-idle-timeout");
+    }
+
+    #[test]
+    fn conflicts_clap_catches() {
+        conflicting(&["--stateless", "--debug"]);
+        conflicting(&["--stateless", "--session", "s.json"]);
+        conflicting(&["--system", "Be brief.", "--system-file", "system.txt"]);
+        conflicting(&["--record", "a.jsonl", "--replay", "b.jsonl"]);
+        conflicting(&["--batch", "prompts.txt", "--compare", "gpt-4o,gpt-4.1"]);
+        conflicting(&["--stream", "--compare", "gpt-4o,gpt-4.1"]);
+        conflicting(&["--stream", "--format-code"]);
+        conflicting(&["--tui", "--confirm-expensive"]);
+        conflicting(&["--tui", "--speak"]);
+        conflicting(&["--tui", "--on-empty-reply", "retry"]);
+    }
+
+    #[test]
+    fn tui_takes_the_flags_it_sends_turns_with() {
+        let flags = [
+            "--tui", "--pre-hook", "tr a-z A-Z", "--post-hook", "cat", "--redact-pattern", "sk-[a-z0-9]+", "--strict",
+            "--output", "replies.txt", "--reply-sink", "http://localhost:9000", "--debug", "--fallback-model", "gpt-4.1",
+            "--show-latency", "--notify",
+        ];
+        assert!(Args::try_parse_from(std::iter::once("gpt").chain(flags)).is_ok());
+    }
 }
@@ -0,0 +1,54 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::Args;
+use crate::session::Session;
+use crate::usage::{ModelUsage, UsageTotals};
+
+/// Machine-readable metadata about a finished session, written with `--manifest`.
+#[derive(Serialize, Debug)]
+pub struct Manifest<'a> {
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub models: &'a BTreeMap<String, ModelUsage>,
+    pub turns: usize,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub estimated_cost_usd: f64,
+    pub settings: &'a Args,
+    pub title: Option<&'a str>,
+    pub tags: &'a [String],
+    pub transcript: Option<&'a str>,
+}
+
+impl<'a> Manifest<'a> {
+    pub fn new(
+        started_at: DateTime<Utc>,
+        turns: usize,
+        usage: &'a UsageTotals,
+        settings: &'a Args,
+        session: &'a Session,
+        transcript: Option<&'a str>,
+    ) -> Self {
+        Manifest {
+            started_at,
+            ended_at: Utc::now(),
+            models: &usage.by_model,
+            turns,
+            prompt_tokens: usage.prompt_tokens(),
+            completion_tokens: usage.completion_tokens(),
+            estimated_cost_usd: usage.cost(),
+            settings,
+            title: session.title.as_deref(),
+            tags: &session.tags,
+            transcript,
+        }
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
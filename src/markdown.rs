@@ -0,0 +1,84 @@
+//! Sessions as Markdown: one `## Role` section per message, so a conversation can be
+//! edited in a text editor and loaded back.
+
+use crate::Message;
+use crate::session::Session;
+
+const TITLE_PREFIX: &str = "# Conversation with ";
+
+fn heading(role: &str) -> String {
+    let mut chars = role.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Renders the session as Markdown. The summary is not included.
+pub fn export(session: &Session) -> String {
+    let mut out = format!("{}{}\n", TITLE_PREFIX, session.model);
+    if let Some(system) = session.current_system() {
+        out.push_str(&format!("\n## System\n\n{}\n", system.trim()));
+    }
+    for msg in &session.conversation {
+        out.push_str(&format!(
+            "\n## {}\n\n{}\n",
+            heading(&msg.role),
+            msg.display_content().trim()
+        ));
+    }
+    out
+}
+
+/// Parses Markdown written by [`export`] (or by hand in the same shape). A level-two
+/// heading naming a role starts a new message; `## System` before the first message sets
+/// the session's system prompt.
+pub fn import(text: &str, default_model: &str) -> Result<Session, String> {
+    let mut session = Session::new(default_model);
+    let mut current: Option<(String, Vec<&str>)> = None;
+    let mut in_code = false;
+
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+        }
+        if !in_code {
+            if current.is_none()
+                && let Some(model) = line.strip_prefix(TITLE_PREFIX)
+            {
+                session.model = model.trim().to_string();
+                continue;
+            }
+            if let Some(role) = line.strip_prefix("## ").map(|r| r.trim().to_lowercase())
+                && matches!(role.as_str(), "user" | "assistant" | "system" | "developer")
+            {
+                finish_section(current.take(), &mut session);
+                current = Some((role, Vec::new()));
+                continue;
+            }
+        }
+        match &mut current {
+            Some((_, lines)) => lines.push(line),
+            None if line.trim().is_empty() => {}
+            None => {
+                return Err(format!(
+                    "unexpected text before the first message: '{}'",
+                    line.trim()
+                ));
+            }
+        }
+    }
+    finish_section(current, &mut session);
+    Ok(session)
+}
+
+fn finish_section(section: Option<(String, Vec<&str>)>, session: &mut Session) {
+    if let Some((role, lines)) = section {
+        let content = lines.join("\n").trim().to_string();
+        if role == "system" && session.conversation.is_empty() && session.system.is_none() {
+            session.system = Some(content);
+        } else {
+            session.push(Message::new(&role, content));
+        }
+    }
+}
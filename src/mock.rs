@@ -0,0 +1,45 @@
+//! `--mock`: answers every request locally with a canned reply, for trying out scripts,
+//! sessions and exports without a key or a bill. Nothing is sent over the network.
+
+use serde_json::{Value, json};
+
+/// The reply used when `--mock-reply` isn't given: the prompt, echoed back.
+pub const DEFAULT_TEMPLATE: &str = "{prompt}";
+
+/// The raw response body the API would have sent for `request` to `path`, with the reply
+/// made from `template`: `{prompt}` becomes the last user message and `{model}` the model.
+pub fn respond(template: &str, path: &str, request: &Value) -> String {
+    let model = request["model"].as_str().unwrap_or_default();
+    let reply = template
+        .replace("{prompt}", &prompt(path, request))
+        .replace("{model}", model);
+    let choice = if path == "/completions" {
+        json!({ "text": reply, "finish_reason": "stop" })
+    } else {
+        json!({
+            "message": { "role": "assistant", "content": reply },
+            "finish_reason": "stop",
+        })
+    };
+    json!({ "model": model, "choices": [choice] }).to_string()
+}
+
+/// The text being answered: the last user message, or the whole prompt for `/completions`.
+fn prompt(path: &str, request: &Value) -> String {
+    if path == "/completions" {
+        return request["prompt"].as_str().unwrap_or_default().to_string();
+    }
+    let last = request["messages"]
+        .as_array()
+        .and_then(|messages| messages.iter().rev().find(|m| m["role"] == "user"));
+    match last.map(|m| &m["content"]) {
+        Some(Value::String(text)) => text.clone(),
+        // Messages with attachments or cache hints are sent as a list of parts.
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|part| part["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
@@ -0,0 +1,181 @@
+use serde::Deserialize;
+use std::error::Error;
+
+use crate::api::{self, ApiClient};
+use crate::usage::Currency;
+
+/// Details about a model that the API itself does not report.
+#[derive(Debug)]
+pub struct ModelInfo {
+    pub id: &'static str,
+    /// Maximum number of tokens the model accepts (prompt + completion).
+    pub context_window: u32,
+    /// Price in USD per million input tokens.
+    pub input_price: f64,
+    /// Price in USD per million output tokens.
+    pub output_price: f64,
+}
+
+/// Local table of known models. Prices are USD per million tokens and may drift from
+/// OpenAI's current pricing page.
+pub const KNOWN_MODELS: &[ModelInfo] = &[
+    info("gpt-5", 400_000, 1.25, 10.00),
+    info("gpt-5-mini", 400_000, 0.25, 2.00),
+    info("gpt-5-nano", 400_000, 0.05, 0.40),
+    info("gpt-4.5-preview", 128_000, 75.00, 150.00),
+    info("gpt-4.1", 1_047_576, 2.00, 8.00),
+    info("gpt-4.1-mini", 1_047_576, 0.40, 1.60),
+    info("gpt-4.1-nano", 1_047_576, 0.10, 0.40),
+    info("gpt-4o", 128_000, 2.50, 10.00),
+    info("gpt-4o-mini", 128_000, 0.15, 0.60),
+    info("chatgpt-4o-latest", 128_000, 5.00, 15.00),
+    info("gpt-4-turbo", 128_000, 10.00, 30.00),
+    info("gpt-4", 8_192, 30.00, 60.00),
+    info("gpt-3.5-turbo", 16_385, 0.50, 1.50),
+    info("o1", 200_000, 15.00, 60.00),
+    info("o1-mini", 128_000, 1.10, 4.40),
+    info("o3", 200_000, 2.00, 8.00),
+    info("o3-mini", 200_000, 1.10, 4.40),
+    info("o4-mini", 200_000, 1.10, 4.40),
+];
+
+const fn info(
+    id: &'static str,
+    context_window: u32,
+    input_price: f64,
+    output_price: f64,
+) -> ModelInfo {
+    ModelInfo {
+        id,
+        context_window,
+        input_price,
+        output_price,
+    }
+}
+
+/// Looks up a model in the local table. Dated snapshots such as `gpt-4o-2024-08-06`
+/// resolve to the longest matching base entry.
+pub fn lookup(model: &str) -> Option<&'static ModelInfo> {
+    KNOWN_MODELS
+        .iter()
+        .filter(|info| model == info.id || model.starts_with(&format!("{}-", info.id)))
+        .max_by_key(|info| info.id.len())
+}
+
+/// Reasoning models (o1 and later) take instructions as `developer` messages instead of
+/// `system` messages.
+pub fn uses_developer_role(model: &str) -> bool {
+    const PREFIXES: &[&str] = &["o1", "o3", "o4", "gpt-5"];
+    // The earliest o1 releases accept neither role, so leave them alone.
+    if model.starts_with("o1-mini") || model.starts_with("o1-preview") {
+        return false;
+    }
+    PREFIXES.iter().any(|p| model.starts_with(p))
+}
+
+/// Maps an internal message role to the role `model` expects on the wire. Instructions
+/// are stored as `system`, or as `developer` when added with `/developer`; either is sent
+/// in whichever of the two the model understands.
+pub fn wire_role<'a>(model: &str, role: &'a str) -> &'a str {
+    match role {
+        "system" if uses_developer_role(model) => "developer",
+        "developer" if !uses_developer_role(model) => "system",
+        _ => role,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ModelEntry {
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ModelsResponse {
+    data: Vec<ModelEntry>,
+}
+
+/// Returns true for model ids that can be used with the chat completions endpoint.
+fn is_chat_model(id: &str) -> bool {
+    const PREFIXES: &[&str] = &["gpt-", "chatgpt-", "o1", "o3", "o4"];
+    const EXCLUDED: &[&str] = &[
+        "instruct",
+        "audio",
+        "realtime",
+        "tts",
+        "transcribe",
+        "search",
+        "image",
+        "embedding",
+    ];
+    PREFIXES.iter().any(|p| id.starts_with(p)) && !EXCLUDED.iter().any(|e| id.contains(e))
+}
+
+/// Fetches `/v1/models` and prints the chat-capable models, along with any context-window
+/// and pricing details we know about locally.
+pub async fn list_models(client: &ApiClient, currency: &Currency) -> Result<(), Box<dyn Error>> {
+    let res = client.get("/models").send().await?;
+
+    if !res.status().is_success() {
+        let error_text = res.text().await?;
+        return Err(format!("Error listing models: {}", error_text).into());
+    }
+
+    let models: ModelsResponse = api::decode(&res.text().await?)?;
+    let mut ids: Vec<String> = models
+        .data
+        .into_iter()
+        .map(|m| m.id)
+        .filter(|id| is_chat_model(id))
+        .collect();
+    ids.sort();
+
+    println!(
+        "{:<36} {:>10} {:>24}",
+        "MODEL", "CONTEXT", "PRICE (in/out per 1M)"
+    );
+    for id in &ids {
+        match lookup(id) {
+            Some(info) => println!(
+                "{:<36} {:>10} {:>24}",
+                id,
+                info.context_window,
+                format!(
+                    "{} / {}",
+                    currency.format(info.input_price, 2),
+                    currency.format(info.output_price, 2)
+                )
+            ),
+            None => println!("{:<36} {:>10} {:>24}", id, "-", "-"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_goes_out_as_developer_to_reasoning_models() {
+        for model in ["o1", "o3-mini", "o4-mini-2025-04-16", "gpt-5"] {
+            assert_eq!(wire_role(model, "system"), "developer", "{}", model);
+            assert_eq!(wire_role(model, "developer"), "developer", "{}", model);
+        }
+    }
+
+    #[test]
+    fn developer_goes_out_as_system_to_older_models() {
+        for model in ["gpt-4o", "gpt-4.1-mini", "gpt-3.5-turbo", "o1-mini"] {
+            assert_eq!(wire_role(model, "developer"), "system", "{}", model);
+            assert_eq!(wire_role(model, "system"), "system", "{}", model);
+        }
+    }
+
+    #[test]
+    fn other_roles_are_unchanged() {
+        for role in ["user", "assistant", "tool"] {
+            assert_eq!(wire_role("o3", role), role);
+            assert_eq!(wire_role("gpt-4o", role), role);
+        }
+    }
+}
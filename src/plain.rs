@@ -0,0 +1,308 @@
+//! `--strip-markdown`: replies as plain prose, for places where `**` and `#` are just noise.
+//!
+//! Fenced code keeps its contents verbatim. Everything else loses its heading marks, quote
+//! markers, emphasis, code spans and link syntax; link targets are kept in parentheses.
+
+/// Converts a Markdown reply into plain text.
+pub fn strip(markdown: &str) -> String {
+    let mut out = Vec::new();
+    let mut fence: Option<String> = None;
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if let Some(marker) = &fence {
+            if trimmed.starts_with(marker.as_str()) {
+                fence = None;
+            } else {
+                out.push(line.to_string());
+            }
+            continue;
+        }
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            let marker: String = trimmed
+                .chars()
+                .take_while(|&c| c == trimmed.as_bytes()[0] as char)
+                .collect();
+            fence = Some(marker);
+            continue;
+        }
+        if let Some(line) = block(line) {
+            out.push(line);
+        }
+    }
+    let mut text = out.join("\n");
+    if markdown.ends_with('\n') {
+        text.push('\n');
+    }
+    text
+}
+
+/// Strips the block-level syntax from one line outside a fence. `None` drops the line.
+fn block(line: &str) -> Option<String> {
+    let indent = &line[..line.len() - line.trim_start().len()];
+    let mut rest = line.trim_start();
+
+    // Horizontal rules, setext underlines and table alignment rows.
+    if rest.len() >= 3
+        && (rest.chars().all(|c| matches!(c, '-' | '*' | '_' | ' '))
+            && rest.chars().filter(|c| !c.is_whitespace()).count() >= 3
+            || rest.chars().all(|c| c == '='))
+    {
+        return None;
+    }
+    if rest.starts_with('|') && rest.chars().all(|c| matches!(c, '|' | '-' | ':' | ' ')) {
+        return None;
+    }
+
+    while let Some(quoted) = rest.strip_prefix('>') {
+        rest = quoted.strip_prefix(' ').unwrap_or(quoted);
+    }
+
+    let hashes = rest.chars().take_while(|&c| c == '#').count();
+    if (1..=6).contains(&hashes) && rest[hashes..].starts_with([' ', '\t']) {
+        let heading = rest[hashes..].trim();
+        return Some(inline(heading.trim_end_matches('#').trim_end()));
+    }
+
+    if let Some(cells) = rest.strip_prefix('|') {
+        let cells: Vec<String> = cells
+            .trim_end()
+            .trim_end_matches('|')
+            .split('|')
+            .map(|cell| inline(cell.trim()))
+            .collect();
+        return Some(format!("{}{}", indent, cells.join("  ")));
+    }
+
+    let bullet = ["* ", "+ "].iter().find_map(|b| rest.strip_prefix(b));
+    Some(match bullet {
+        Some(item) => format!("{}- {}", indent, inline(item)),
+        None => format!("{}{}", indent, inline(rest)),
+    })
+}
+
+/// Strips inline syntax: escapes, code spans, links, images, autolinks and emphasis.
+fn inline(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' if chars.get(i + 1).is_some_and(|n| n.is_ascii_punctuation()) => {
+                out.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            '`' => {
+                let run = run_length(&chars, i);
+                if let Some(end) = find_run(&chars, i + run, '`', run) {
+                    let code: String = chars[i + run..end].iter().collect();
+                    out.push_str(code.trim());
+                    i = end + run;
+                    continue;
+                }
+                out.extend(&chars[i..i + run]);
+                i += run;
+                continue;
+            }
+            '!' if chars.get(i + 1) == Some(&'[') => {
+                if let Some((label, _, end)) = link(&chars, i + 1) {
+                    out.push_str(&inline(&label));
+                    i = end;
+                    continue;
+                }
+            }
+            '[' => {
+                if let Some((label, url, end)) = link(&chars, i) {
+                    let label = inline(&label);
+                    if label == url || url.is_empty() {
+                        out.push_str(&label);
+                    } else {
+                        out.push_str(&format!("{} ({})", label, url));
+                    }
+                    i = end;
+                    continue;
+                }
+            }
+            '<' => {
+                if let Some(len) = chars[i + 1..].iter().position(|&c| c == '>') {
+                    let inner: String = chars[i + 1..i + 1 + len].iter().collect();
+                    if inner.contains("://") && !inner.contains(char::is_whitespace) {
+                        out.push_str(&inner);
+                        i += len + 2;
+                        continue;
+                    }
+                }
+            }
+            '*' | '_' | '~' => {
+                let run = run_length(&chars, i);
+                if let Some(end) = emphasis(&chars, i, run) {
+                    let inner: String = chars[i + run..end].iter().collect();
+                    out.push_str(&inline(&inner));
+                    i = end + run;
+                    continue;
+                }
+                out.extend(&chars[i..i + run]);
+                i += run;
+                continue;
+            }
+            _ => {}
+        }
+        out.push(c);
+        i += 1;
+    }
+    out
+}
+
+/// How many times the character at `start` repeats from there.
+fn run_length(chars: &[char], start: usize) -> usize {
+    chars[start..]
+        .iter()
+        .take_while(|&&c| c == chars[start])
+        .count()
+}
+
+/// The start of the next run of exactly `len` `c`s at or after `from`.
+fn find_run(chars: &[char], from: usize, c: char, len: usize) -> Option<usize> {
+    let mut i = from;
+    while i < chars.len() {
+        if chars[i] == c {
+            let run = run_length(chars, i);
+            if run == len {
+                return Some(i);
+            }
+            i += run;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Where the emphasis opened by the run of `len` delimiters at `start` closes, if it is
+/// emphasis at all. Underscores inside words (`snake_case`) never count, and `~` only
+/// counts doubled.
+fn emphasis(chars: &[char], start: usize, len: usize) -> Option<usize> {
+    let c = chars[start];
+    if len > 3 || c == '~' && len != 2 {
+        return None;
+    }
+    let alnum = |i: Option<&char>| i.is_some_and(|c| c.is_alphanumeric());
+    let opens = chars
+        .get(start + len)
+        .is_some_and(|next| !next.is_whitespace())
+        && !(c == '_' && start > 0 && alnum(chars.get(start - 1)));
+    if !opens {
+        return None;
+    }
+    let mut from = start + len;
+    while let Some(end) = find_run(chars, from, c, len) {
+        let closes = !chars[end - 1].is_whitespace()
+            && end > start + len
+            && !(c == '_' && alnum(chars.get(end + len)));
+        if closes {
+            return Some(end);
+        }
+        from = end + len;
+    }
+    None
+}
+
+/// Parses `[label](url)` starting at the `[` at `start`, returning the label, the url and
+/// the index just past the closing parenthesis.
+fn link(chars: &[char], start: usize) -> Option<(String, String, usize)> {
+    let mut depth = 0;
+    let mut close = None;
+    for (i, &c) in chars.iter().enumerate().skip(start) {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(i);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let close = close?;
+    if chars.get(close + 1) != Some(&'(') {
+        return None;
+    }
+    let end = close + 2 + chars[close + 2..].iter().position(|&c| c == ')')?;
+    let label = chars[start + 1..close].iter().collect();
+    let target: String = chars[close + 2..end].iter().collect();
+    // Drop an optional title: [label](url "title").
+    let url = target.split_whitespace().next().unwrap_or_default();
+    let url = url
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .to_string();
+    Some((label, url, end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::strip;
+
+    #[test]
+    fn headings_and_rules() {
+        assert_eq!(
+            strip("# Title\n\n## Part two ##\n---\nText"),
+            "Title\n\nPart two\nText"
+        );
+        assert_eq!(strip("Setext\n======\nbody"), "Setext\nbody");
+        assert_eq!(strip("#hashtag stays"), "#hashtag stays");
+    }
+
+    #[test]
+    fn emphasis_and_code_spans() {
+        assert_eq!(
+            strip("Some **bold**, *italic*, ***both***, ~~gone~~ and `code`."),
+            "Some bold, italic, both, gone and code."
+        );
+        assert_eq!(strip("``a `tick` here``"), "a `tick` here");
+        assert_eq!(
+            strip("snake_case_name and 2 * 3 * 4"),
+            "snake_case_name and 2 * 3 * 4"
+        );
+        assert_eq!(strip(r"\*not emphasis\*"), "*not emphasis*");
+    }
+
+    #[test]
+    fn lists_and_quotes() {
+        assert_eq!(
+            strip("* one\n+ two\n- three\n  * nested"),
+            "- one\n- two\n- three\n  - nested"
+        );
+        assert_eq!(strip("> quoted **text**\n> > twice"), "quoted text\ntwice");
+        assert_eq!(strip("1. first\n2. second"), "1. first\n2. second");
+    }
+
+    #[test]
+    fn links_and_images() {
+        assert_eq!(
+            strip("See [the docs](https://docs.rs \"Docs\") or <https://example.com>."),
+            "See the docs (https://docs.rs) or https://example.com."
+        );
+        assert_eq!(strip("![a diagram](img.png)"), "a diagram");
+        assert_eq!(strip("[https://x.io](https://x.io)"), "https://x.io");
+    }
+
+    #[test]
+    fn fences_keep_their_contents() {
+        let reply = "Run this:\n```rust\nlet x = **not bold**;\n# not a heading\n```\nDone.\n";
+        assert_eq!(
+            strip(reply),
+            "Run this:\nlet x = **not bold**;\n# not a heading\nDone.\n"
+        );
+        assert_eq!(strip("~~~\n*raw*\n~~~"), "*raw*");
+    }
+
+    #[test]
+    fn tables() {
+        let table = "| Name | Value |\n|------|:-----:|\n| **a** | `1` |";
+        assert_eq!(strip(table), "Name  Value\na  1");
+    }
+}
@@ -0,0 +1,28 @@
+//! `--format prompt`: the context flattened into one string for completion-style backends
+//! that don't take a messages array.
+
+use crate::api::ApiMessage;
+
+/// How each message is rendered; `{role}` and `{content}` are replaced.
+pub const DEFAULT_TEMPLATE: &str = "{role}: {content}\n\n";
+
+/// Turns the `\n` and `\t` escapes typed on the command line into the real characters.
+pub fn unescape(template: &str) -> String {
+    template.replace("\\n", "\n").replace("\\t", "\t")
+}
+
+fn render(template: &str, role: &str, content: &str) -> String {
+    template.replace("{role}", role).replace("{content}", content)
+}
+
+/// Renders every message with `template`, then the start of an assistant message (the
+/// template up to `{content}`) for the model to continue.
+pub fn flatten(messages: &[ApiMessage], template: &str) -> String {
+    let mut prompt = String::new();
+    for msg in messages {
+        prompt.push_str(&render(template, &msg.role, msg.content.trim()));
+    }
+    let cue = template.split("{content}").next().unwrap_or_default();
+    prompt.push_str(render(cue, "assistant", "").trim_end_matches([' ', '\t']));
+    prompt
+}
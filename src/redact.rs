@@ -0,0 +1,33 @@
+use crate::regex::{Regex, RegexError};
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Scrubs user-supplied text (messages, staged context, attachments, system prompts) of
+/// anything matching the `--redact-pattern` regexes before it leaves the machine.
+#[derive(Debug, Default)]
+pub struct Redactor {
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    pub fn new(patterns: &[String]) -> Result<Redactor, RegexError> {
+        let patterns = patterns
+            .iter()
+            .map(|p| Regex::new(p))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Redactor { patterns })
+    }
+
+    /// Returns the redacted text and how many matches were replaced, or an error if a
+    /// pattern took too long on this text.
+    pub fn apply(&self, text: &str) -> Result<(String, usize), RegexError> {
+        let mut redacted = text.to_string();
+        let mut total = 0;
+        for pattern in &self.patterns {
+            let (replaced, count) = pattern.replace_all(&redacted, REDACTED)?;
+            redacted = replaced;
+            total += count;
+        }
+        Ok((redacted, total))
+    }
+}
@@ -0,0 +1,661 @@
+//! A small regular expression engine.
+//!
+//! Supports the subset of syntax needed for redaction patterns: literals, `.`, character
+//! classes (`[a-z]`, `[^...]`, `\d`, `\w`, `\s` and their negations), groups (`(...)`,
+//! `(?:...)`), alternation, the quantifiers `*`, `+`, `?`, `{n}`, `{n,}`, `{n,m}` (greedy or
+//! lazy with a trailing `?`), the anchors `^`/`$`, word boundaries `\b`/`\B`, and a leading
+//! `(?i)` for case-insensitive matching.
+//!
+//! Patterns are compiled to a small program and run on every thread at once (a Pike VM)
+//! instead of by backtracking, so neither long input nor nested repeats like `(a+)+b` can
+//! blow up the stack or the running time.
+
+use std::fmt;
+
+/// Most instructions a compiled pattern may have; counted repeats like `{1000}` are
+/// expanded, so this keeps them from growing without bound.
+const MAX_PROGRAM: usize = 20_000;
+
+/// Most steps one [`Regex::replace_all`] may take before giving up with an error.
+const MAX_STEPS: usize = 50_000_000;
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+    pattern: String,
+    program: Vec<Inst>,
+    case_insensitive: bool,
+}
+
+/// One step of a compiled pattern.
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    WordBoundary(bool),
+    /// Continue at both targets, preferring the first.
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    WordBoundary(bool),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Class {
+    negated: bool,
+    items: Vec<ClassItem>,
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Range(char, char),
+    Digit(bool),
+    Word(bool),
+    Space(bool),
+}
+
+#[derive(Debug)]
+pub struct RegexError {
+    pattern: String,
+    message: String,
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid pattern '{}': {}", self.pattern, self.message)
+    }
+}
+
+impl std::error::Error for RegexError {}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, RegexError> {
+        let error = |message: String| RegexError {
+            pattern: pattern.to_string(),
+            message,
+        };
+        let (case_insensitive, body) = match pattern.strip_prefix("(?i)") {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        let mut parser = Parser {
+            chars: body.chars().collect(),
+            pos: 0,
+        };
+        let root = parser.parse_alt().map_err(error)?;
+        if parser.pos < parser.chars.len() {
+            return Err(error("unmatched ')'".to_string()));
+        }
+        let mut program = Vec::new();
+        compile(&root, &mut program).map_err(error)?;
+        program.push(Inst::Match);
+        Ok(Regex {
+            pattern: pattern.to_string(),
+            program,
+            case_insensitive,
+        })
+    }
+
+    /// Finds the leftmost match at or after `start`, returning its char range. Every
+    /// position is visited once with all the threads still alive there, so the time is
+    /// linear in the input whatever the pattern; `steps` is how much work is left.
+    fn find_at(
+        &self,
+        input: &[char],
+        start: usize,
+        steps: &mut usize,
+    ) -> Result<Option<(usize, usize)>, RegexError> {
+        // Threads are (instruction, start of their match), in priority order.
+        let mut current = Vec::new();
+        let mut next = Vec::new();
+        // The position each instruction was last added at, so it is added once per position.
+        let mut added = vec![usize::MAX; self.program.len()];
+        let mut found = None;
+        for pos in start..=input.len() {
+            // A thread that starts here ranks below every thread that started earlier.
+            if found.is_none() {
+                self.add_thread(&mut current, &mut added, 0, pos, input, pos, steps)?;
+            }
+            if current.is_empty() && found.is_some() {
+                break;
+            }
+            for &(pc, from) in &current {
+                self.step(steps)?;
+                match &self.program[pc] {
+                    Inst::Match => {
+                        // Lower-priority threads can't beat this match.
+                        found = Some((from, pos));
+                        break;
+                    }
+                    inst => {
+                        if pos < input.len() && self.matches_char(inst, input[pos]) {
+                            self.add_thread(
+                                &mut next,
+                                &mut added,
+                                pc + 1,
+                                from,
+                                input,
+                                pos + 1,
+                                steps,
+                            )?;
+                        }
+                    }
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        Ok(found)
+    }
+
+    /// Adds the thread at `pc` to `list`, following jumps, splits and assertions at `pos`
+    /// until it reaches instructions that consume a character (or match). Uses a stack
+    /// rather than recursion, keeping the preferred branch of each split first.
+    #[allow(clippy::too_many_arguments)]
+    fn add_thread(
+        &self,
+        list: &mut Vec<(usize, usize)>,
+        added: &mut [usize],
+        pc: usize,
+        from: usize,
+        input: &[char],
+        pos: usize,
+        steps: &mut usize,
+    ) -> Result<(), RegexError> {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            self.step(steps)?;
+            if added[pc] == pos {
+                continue;
+            }
+            added[pc] = pos;
+            match &self.program[pc] {
+                Inst::Jump(to) => stack.push(*to),
+                Inst::Split(first, second) => {
+                    stack.push(*second);
+                    stack.push(*first);
+                }
+                Inst::Start if pos == 0 => stack.push(pc + 1),
+                Inst::End if pos == input.len() => stack.push(pc + 1),
+                Inst::WordBoundary(expected) if is_word_boundary(input, pos) == *expected => {
+                    stack.push(pc + 1)
+                }
+                Inst::Start | Inst::End | Inst::WordBoundary(_) => {}
+                Inst::Char(_) | Inst::Any | Inst::Class(_) | Inst::Match => list.push((pc, from)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes one step from the budget, failing once it is spent.
+    fn step(&self, steps: &mut usize) -> Result<(), RegexError> {
+        *steps = steps.checked_sub(1).ok_or_else(|| RegexError {
+            pattern: self.pattern.clone(),
+            message: "it took too long to match this text".to_string(),
+        })?;
+        Ok(())
+    }
+
+    /// Replaces every non-overlapping match with `replacement`, returning the new text and
+    /// the number of replacements made, or an error if matching took too long.
+    pub fn replace_all(
+        &self,
+        text: &str,
+        replacement: &str,
+    ) -> Result<(String, usize), RegexError> {
+        self.replace_all_within(text, replacement, MAX_STEPS)
+    }
+
+    fn replace_all_within(
+        &self,
+        text: &str,
+        replacement: &str,
+        mut steps: usize,
+    ) -> Result<(String, usize), RegexError> {
+        let input: Vec<char> = text.chars().collect();
+        let mut out = String::with_capacity(text.len());
+        let mut count = 0;
+        let mut pos = 0;
+        while pos <= input.len() {
+            match self.find_at(&input, pos, &mut steps)? {
+                Some((start, end)) => {
+                    out.extend(&input[pos..start]);
+                    if end == start {
+                        // Empty match: keep the next character and move past it.
+                        if start < input.len() {
+                            out.push(input[start]);
+                        }
+                        pos = start + 1;
+                        continue;
+                    }
+                    out.push_str(replacement);
+                    count += 1;
+                    pos = end;
+                }
+                None => {
+                    out.extend(&input[pos..]);
+                    break;
+                }
+            }
+        }
+        Ok((out, count))
+    }
+
+    fn chars_eq(&self, a: char, b: char) -> bool {
+        if self.case_insensitive {
+            a.to_lowercase().eq(b.to_lowercase())
+        } else {
+            a == b
+        }
+    }
+
+    /// Whether an instruction that consumes one character accepts `c`.
+    fn matches_char(&self, inst: &Inst, c: char) -> bool {
+        match inst {
+            Inst::Char(expected) => self.chars_eq(*expected, c),
+            Inst::Any => c != '\n',
+            Inst::Class(class) => {
+                class.matches(c)
+                    || (self.case_insensitive
+                        && (class.matches_any(c.to_lowercase())
+                            || class.matches_any(c.to_uppercase())))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Appends the instructions for `node` to `program`.
+fn compile(node: &Node, program: &mut Vec<Inst>) -> Result<(), String> {
+    if program.len() > MAX_PROGRAM {
+        return Err("the pattern is too large; use smaller repetition counts".to_string());
+    }
+    match node {
+        Node::Char(c) => program.push(Inst::Char(*c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class(class) => program.push(Inst::Class(class.clone())),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::WordBoundary(expected) => program.push(Inst::WordBoundary(*expected)),
+        Node::Concat(nodes) => {
+            for node in nodes {
+                compile(node, program)?;
+            }
+        }
+        Node::Alt(alternatives) => {
+            let mut jumps = Vec::new();
+            for (i, alternative) in alternatives.iter().enumerate() {
+                if i + 1 == alternatives.len() {
+                    compile(alternative, program)?;
+                    break;
+                }
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                compile(alternative, program)?;
+                jumps.push(program.len());
+                program.push(Inst::Jump(0));
+                let after = program.len();
+                program[split] = Inst::Split(split + 1, after);
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat {
+            node,
+            min,
+            max,
+            greedy,
+        } => {
+            for _ in 0..*min {
+                compile(node, program)?;
+            }
+            let split = |body: usize, out: usize| {
+                if *greedy {
+                    Inst::Split(body, out)
+                } else {
+                    Inst::Split(out, body)
+                }
+            };
+            match max {
+                None => {
+                    let start = program.len();
+                    program.push(Inst::Jump(0));
+                    compile(node, program)?;
+                    program.push(Inst::Jump(start));
+                    program[start] = split(start + 1, program.len());
+                }
+                Some(max) => {
+                    // Each optional copy can skip straight past the rest.
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Jump(0));
+                        compile(node, program)?;
+                    }
+                    let end = program.len();
+                    for at in splits {
+                        program[at] = split(at + 1, end);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn is_word_boundary(input: &[char], pos: usize) -> bool {
+    let before = pos > 0 && is_word_char(input[pos - 1]);
+    let after = pos < input.len() && is_word_char(input[pos]);
+    before != after
+}
+
+impl Class {
+    fn matches(&self, c: char) -> bool {
+        let hit = self.items.iter().any(|item| match item {
+            ClassItem::Range(lo, hi) => (*lo..=*hi).contains(&c),
+            ClassItem::Digit(positive) => c.is_ascii_digit() == *positive,
+            ClassItem::Word(positive) => is_word_char(c) == *positive,
+            ClassItem::Space(positive) => c.is_whitespace() == *positive,
+        });
+        hit != self.negated
+    }
+
+    fn matches_any(&self, mut chars: impl Iterator<Item = char>) -> bool {
+        chars.any(|c| self.matches(c))
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_alt(&mut self) -> Result<Node, String> {
+        let mut alternatives = vec![self.parse_concat()?];
+        while self.eat('|') {
+            alternatives.push(self.parse_concat()?);
+        }
+        Ok(if alternatives.len() == 1 {
+            alternatives.remove(0)
+        } else {
+            Node::Alt(alternatives)
+        })
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_quantifier(atom)?);
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn parse_quantifier(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('{') => match self.parse_braces() {
+                Some(bounds) => bounds,
+                None => return Ok(atom),
+            },
+            Some(c @ ('*' | '+' | '?')) => {
+                self.pos += 1;
+                match c {
+                    '*' => (0, None),
+                    '+' => (1, None),
+                    _ => (0, Some(1)),
+                }
+            }
+            _ => return Ok(atom),
+        };
+        if matches!(atom, Node::Start | Node::End | Node::WordBoundary(_)) {
+            return Err("nothing to repeat".to_string());
+        }
+        if max.is_some_and(|max| max < min) {
+            return Err("repetition range is out of order".to_string());
+        }
+        let greedy = !self.eat('?');
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+            greedy,
+        })
+    }
+
+    /// Parses `{n}`, `{n,}` or `{n,m}`. Anything else leaves `{` to be read as a literal.
+    fn parse_braces(&mut self) -> Option<(usize, Option<usize>)> {
+        let rest: String = self.chars[self.pos..].iter().collect();
+        let close = rest.find('}')?;
+        let inner = &rest[1..close];
+        let bounds = match inner.split_once(',') {
+            None => {
+                let n = inner.parse().ok()?;
+                (n, Some(n))
+            }
+            Some((lo, "")) => (lo.parse().ok()?, None),
+            Some((lo, hi)) => (lo.parse().ok()?, Some(hi.parse().ok()?)),
+        };
+        self.pos += rest[..=close].chars().count();
+        Some(bounds)
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some('(') => {
+                if self.chars[self.pos..].starts_with(&['?', ':']) {
+                    self.pos += 2;
+                }
+                let inner = self.parse_alt()?;
+                if !self.eat(')') {
+                    return Err("unclosed group".to_string());
+                }
+                Ok(inner)
+            }
+            Some('[') => self.parse_class().map(Node::Class),
+            Some('.') => Ok(Node::Any),
+            Some('^') => Ok(Node::Start),
+            Some('$') => Ok(Node::End),
+            Some('\\') => match self.next() {
+                Some('b') => Ok(Node::WordBoundary(true)),
+                Some('B') => Ok(Node::WordBoundary(false)),
+                Some(c) => Ok(match class_escape(c) {
+                    Some(item) => Node::Class(Class {
+                        negated: false,
+                        items: vec![item],
+                    }),
+                    None => Node::Char(literal_escape(c)),
+                }),
+                None => Err("trailing backslash".to_string()),
+            },
+            Some(c @ ('*' | '+' | '?')) => Err(format!("nothing to repeat before '{}'", c)),
+            Some(c) => Ok(Node::Char(c)),
+            None => Err("unexpected end of pattern".to_string()),
+        }
+    }
+
+    fn parse_class(&mut self) -> Result<Class, String> {
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let c = match self.next() {
+                Some(']') if !first => break,
+                Some(c) => c,
+                None => return Err("unclosed character class".to_string()),
+            };
+            first = false;
+            let lo = if c == '\\' {
+                let escaped = self.next().ok_or("trailing backslash")?;
+                if let Some(item) = class_escape(escaped) {
+                    items.push(item);
+                    continue;
+                }
+                literal_escape(escaped)
+            } else {
+                c
+            };
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&n| n != ']') {
+                self.pos += 1;
+                let hi = match self.next() {
+                    Some('\\') => literal_escape(self.next().ok_or("trailing backslash")?),
+                    Some(hi) => hi,
+                    None => return Err("unclosed character class".to_string()),
+                };
+                if hi < lo {
+                    return Err(format!("invalid range {}-{}", lo, hi));
+                }
+                items.push(ClassItem::Range(lo, hi));
+            } else {
+                items.push(ClassItem::Range(lo, lo));
+            }
+        }
+        Ok(Class { negated, items })
+    }
+}
+
+fn class_escape(c: char) -> Option<ClassItem> {
+    match c {
+        'd' => Some(ClassItem::Digit(true)),
+        'D' => Some(ClassItem::Digit(false)),
+        'w' => Some(ClassItem::Word(true)),
+        'W' => Some(ClassItem::Word(false)),
+        's' => Some(ClassItem::Space(true)),
+        'S' => Some(ClassItem::Space(false)),
+        _ => None,
+    }
+}
+
+fn literal_escape(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redact(pattern: &str, text: &str) -> (String, usize) {
+        Regex::new(pattern)
+            .unwrap()
+            .replace_all(text, "[X]")
+            .unwrap()
+    }
+
+    #[test]
+    fn replaces_emails() {
+        let pattern = r"[\w.+-]+@[\w-]+\.[\w.]+";
+        assert_eq!(
+            redact(pattern, "mail ann.lee+cli@example.co.uk or bob@test.io now"),
+            ("mail [X] or [X] now".to_string(), 2)
+        );
+    }
+
+    #[test]
+    fn replaces_phone_numbers() {
+        let pattern = r"\(?\d{3}\)?[ .-]?\d{3}[ .-]\d{4}";
+        assert_eq!(
+            redact(pattern, "call (555) 123-4567 or 555.987.6543, not 12-34"),
+            ("call [X] or [X], not 12-34".to_string(), 2)
+        );
+    }
+
+    #[test]
+    fn case_insensitive_prefix() {
+        assert_eq!(
+            redact("(?i)secret-[a-z]+", "SECRET-ABC and Secret-xyz"),
+            ("[X] and [X]".to_string(), 2)
+        );
+        assert_eq!(redact("secret", "SECRET").1, 0);
+    }
+
+    #[test]
+    fn prefers_leftmost_then_greedy_or_lazy() {
+        assert_eq!(redact("a+", "baaab").0, "b[X]b");
+        assert_eq!(redact("a+?", "baaab").0, "b[X][X][X]b");
+        assert_eq!(redact("ab|abc", "abc").0, "[X]c");
+        assert_eq!(redact(r"\bcat\b", "cat concat cat").0, "[X] concat [X]");
+        assert_eq!(redact("^a|b$", "aab").0, "[X]a[X]");
+    }
+
+    #[test]
+    fn long_line_with_repeated_group() {
+        let line = "x".repeat(20_000) + " user.name@example";
+        assert_eq!(redact(r"(\w|\.)+@\w+", &line).1, 1);
+        let line = "word ".repeat(5_000);
+        assert_eq!(redact(r"(\w|\.)+@\w+", &line).1, 0);
+    }
+
+    #[test]
+    fn nested_repeats_finish() {
+        let input = "a".repeat(30);
+        assert_eq!(redact("(a+)+b", &input), (input.clone(), 0));
+        assert_eq!(redact("(a*)*b", &(input.clone() + "b")).1, 1);
+    }
+
+    #[test]
+    fn step_budget_is_an_error() {
+        let regex = Regex::new(r"(\w|\.)+@\w+").unwrap();
+        let text = "x".repeat(1_000);
+        assert!(regex.replace_all_within(&text, "[X]", 100).is_err());
+        assert!(regex.replace_all_within(&text, "[X]", 1_000_000).is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_patterns() {
+        for pattern in ["(a", "a)", "[a-", "*a", "a{3,1}", r"a\", "(a{5000}){5000}"] {
+            assert!(Regex::new(pattern).is_err(), "{}", pattern);
+        }
+    }
+}
@@ -0,0 +1,413 @@
+//! `--serve ADDR`: a small HTTP front end, built only with the `serve` feature.
+//!
+//! `POST /chat` takes `{"session": "ID", "prompt": "TEXT"}` and answers with server-sent
+//! events: a `data:` event carrying `{"delta"}` for each piece of the reply as it streams in,
+//! one carrying `{"model", "content"}` with the whole reply and then `data: [DONE]`, or an
+//! `event: error` on failure. Browser requests are only answered for the pages allowed with
+//! `--serve-origin`. Every session id gets its own conversation and summary, kept until
+//! [`MAX_SESSIONS`] newer ones have pushed it out or the server stops.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+
+use crate::api::{ApiClient, ChatRequest, RequestOptions};
+use crate::redact::Redactor;
+use crate::session::Session;
+use crate::usage::UsageTotals;
+use crate::{Args, Message, RECENT_EXCHANGES};
+
+/// Request bodies larger than this are refused.
+const MAX_BODY_BYTES: usize = 1 << 20;
+
+/// Requests whose request line and headers run longer than this are refused.
+const MAX_HEAD_BYTES: u64 = 64 << 10;
+
+/// How long a client may take to send its whole request.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Sessions kept at once; starting another forgets the one left unused the longest.
+const MAX_SESSIONS: usize = 1000;
+
+/// A session, locked by each turn while it runs.
+type Shared = Arc<Mutex<Session>>;
+
+struct State {
+    client: ApiClient,
+    args: Args,
+    options: RequestOptions,
+    /// Scrubs every prompt, as `--redact-pattern` does in the terminal.
+    redactor: Redactor,
+    /// What every new session starts from: model, system prompt and pins.
+    template: Session,
+    /// Each session and when it was last used.
+    sessions: Mutex<HashMap<String, (Shared, Instant)>>,
+    usage: Mutex<UsageTotals>,
+}
+
+#[derive(serde::Deserialize)]
+struct ChatBody {
+    #[serde(default = "default_session")]
+    session: String,
+    prompt: String,
+}
+
+fn default_session() -> String {
+    "default".to_string()
+}
+
+/// Serves `POST /chat` on `addr` until the process is stopped.
+pub async fn run(
+    addr: &str,
+    client: ApiClient,
+    args: Args,
+    options: RequestOptions,
+    redactor: Redactor,
+    template: Session,
+) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("could not listen on {}: {}", addr, e))?;
+    println!(
+        "Serving {} on http://{}/chat. Press Ctrl+C to stop.",
+        template.model,
+        listener.local_addr()?
+    );
+    let state = Arc::new(State {
+        client,
+        args,
+        options,
+        redactor,
+        template,
+        sessions: Mutex::new(HashMap::new()),
+        usage: Mutex::new(UsageTotals::default()),
+    });
+
+    // The request code isn't `Send`, so connections are handled as tasks on this thread.
+    let local = tokio::task::LocalSet::new();
+    local
+        .run_until(async {
+            loop {
+                let (stream, _) = tokio::select! {
+                    accepted = listener.accept() => accepted?,
+                    _ = tokio::signal::ctrl_c() => break,
+                };
+                let state = Arc::clone(&state);
+                tokio::task::spawn_local(async move {
+                    if let Err(e) = handle(stream, &state).await {
+                        eprintln!("Connection error: {}", e);
+                    }
+                });
+            }
+            Ok::<(), std::io::Error>(())
+        })
+        .await?;
+
+    let usage = state.usage.lock().await;
+    println!(
+        "\nStopped. {} prompt and {} completion tokens used.",
+        usage.prompt_tokens(),
+        usage.completion_tokens()
+    );
+    Ok(())
+}
+
+/// The request line and the headers the server looks at.
+struct Head {
+    method: String,
+    path: String,
+    content_length: usize,
+    origin: Option<String>,
+}
+
+/// Reads the request line and headers, or `None` if they run past [`MAX_HEAD_BYTES`].
+async fn read_head(reader: &mut BufReader<TcpStream>) -> std::io::Result<Option<Head>> {
+    let mut reader = reader.take(MAX_HEAD_BYTES);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut parts = request_line.split_whitespace();
+    let mut head = Head {
+        method: parts.next().unwrap_or_default().to_string(),
+        path: parts.next().unwrap_or_default().to_string(),
+        content_length: 0,
+        origin: None,
+    };
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let name = name.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                head.content_length = value.trim().parse().unwrap_or(0);
+            } else if name.eq_ignore_ascii_case("origin") {
+                head.origin = Some(value.trim().to_string());
+            }
+        }
+    }
+    Ok((reader.limit() > 0).then_some(head))
+}
+
+async fn handle(stream: TcpStream, state: &State) -> std::io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    // A client that stalls can't hold its connection open for good.
+    let deadline = tokio::time::Instant::now() + READ_TIMEOUT;
+    let head = match tokio::time::timeout_at(deadline, read_head(&mut stream)).await {
+        Ok(head) => head?,
+        Err(_) => return timed_out(&mut stream, "").await,
+    };
+    let Some(Head {
+        method,
+        path,
+        content_length,
+        origin,
+    }) = head
+    else {
+        return respond(
+            &mut stream,
+            "431 Request Header Fields Too Large",
+            "",
+            "text/plain",
+            "headers too large\n",
+        )
+        .await;
+    };
+
+    // Browsers say which page sent the request; only pages given with --serve-origin may
+    // use the server. Clients that aren't browsers send no origin and are always served.
+    let cors = match origin {
+        Some(origin) if !state.args.serve_origins.contains(&origin) => {
+            let message = format!(
+                "{} is not allowed; pass --serve-origin to allow it\n",
+                origin
+            );
+            return respond(&mut stream, "403 Forbidden", "", "text/plain", &message).await;
+        }
+        Some(origin) => format!(
+            "Access-Control-Allow-Origin: {}\r\nVary: Origin\r\nAccess-Control-Allow-Methods: POST, OPTIONS\r\nAccess-Control-Allow-Headers: Content-Type\r\n",
+            origin
+        ),
+        None => String::new(),
+    };
+    match (method.as_str(), path.as_str()) {
+        ("OPTIONS", _) => respond(&mut stream, "204 No Content", &cors, "text/plain", "").await,
+        ("POST", "/chat") if content_length > MAX_BODY_BYTES => {
+            respond(
+                &mut stream,
+                "413 Payload Too Large",
+                &cors,
+                "text/plain",
+                "too large\n",
+            )
+            .await
+        }
+        ("POST", "/chat") => {
+            let mut body = vec![0; content_length];
+            match tokio::time::timeout_at(deadline, stream.read_exact(&mut body)).await {
+                Ok(read) => read?,
+                Err(_) => return timed_out(&mut stream, &cors).await,
+            };
+            let body: ChatBody = match serde_json::from_slice(&body) {
+                Ok(body) => body,
+                Err(e) => {
+                    let message = format!("expected {{\"session\", \"prompt\"}}: {}\n", e);
+                    return respond(
+                        &mut stream,
+                        "400 Bad Request",
+                        &cors,
+                        "text/plain",
+                        &message,
+                    )
+                    .await;
+                }
+            };
+            stream
+                .write_all(
+                    format!(
+                        "HTTP/1.1 200 OK\r\n{}Content-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+                        cors
+                    )
+                    .as_bytes(),
+                )
+                .await?;
+            let (sender, mut deltas) = tokio::sync::mpsc::unbounded_channel::<String>();
+            let turn = chat(state, &body.session, &body.prompt, |delta| {
+                let _ = sender.send(delta.to_string());
+            });
+            tokio::pin!(turn);
+            // A client that goes away mid-reply stops getting events, but the turn still
+            // finishes so its session is left whole.
+            let mut connected = true;
+            let result = loop {
+                tokio::select! {
+                    result = &mut turn => break result,
+                    Some(delta) = deltas.recv() => {
+                        connected = connected && send_delta(&mut stream, &delta).await.is_ok();
+                    }
+                }
+            };
+            while let Ok(delta) = deltas.try_recv() {
+                connected = connected && send_delta(&mut stream, &delta).await.is_ok();
+            }
+            if !connected {
+                return Ok(());
+            }
+            let event = match result {
+                Ok((model, content)) => format!(
+                    "data: {}\n\ndata: [DONE]\n\n",
+                    serde_json::json!({ "model": model, "content": content })
+                ),
+                Err(e) => format!(
+                    "event: error\ndata: {}\n\n",
+                    serde_json::json!({ "error": e })
+                ),
+            };
+            stream.write_all(event.as_bytes()).await?;
+            stream.flush().await
+        }
+        _ => {
+            respond(
+                &mut stream,
+                "404 Not Found",
+                &cors,
+                "text/plain",
+                "POST /chat\n",
+            )
+            .await
+        }
+    }
+}
+
+/// Writes one piece of a streaming reply as its own event.
+async fn send_delta(stream: &mut (impl AsyncWriteExt + Unpin), delta: &str) -> std::io::Result<()> {
+    let event = format!("data: {}\n\n", serde_json::json!({ "delta": delta }));
+    stream.write_all(event.as_bytes()).await?;
+    stream.flush().await
+}
+
+async fn timed_out(stream: &mut (impl AsyncWriteExt + Unpin), cors: &str) -> std::io::Result<()> {
+    let message = format!(
+        "the request took longer than {}s to arrive\n",
+        READ_TIMEOUT.as_secs()
+    );
+    respond(stream, "408 Request Timeout", cors, "text/plain", &message).await
+}
+
+async fn respond(
+    stream: &mut (impl AsyncWriteExt + Unpin),
+    status: &str,
+    cors: &str,
+    content_type: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\n{}Content-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        cors,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await
+}
+
+/// Runs one turn of the named session, the same way the interactive loop does, passing each
+/// piece of the reply to `on_delta` as it streams in.
+async fn chat(
+    state: &State,
+    id: &str,
+    prompt: &str,
+    on_delta: impl FnMut(&str),
+) -> Result<(String, String), String> {
+    let session = {
+        let mut sessions = state.sessions.lock().await;
+        if !sessions.contains_key(id)
+            && sessions.len() >= MAX_SESSIONS
+            && let Some(oldest) = sessions
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(id, _)| id.clone())
+        {
+            // A turn still running in it finishes; the session is just not kept.
+            sessions.remove(&oldest);
+        }
+        let (session, used) = sessions.entry(id.to_string()).or_insert_with(|| {
+            let mut session = Session::new(&state.template.model);
+            session.system = state.template.system.clone();
+            session.pinned = state.template.pinned.clone();
+            session.target_length = state.template.target_length;
+            session.datetime_format = state.template.datetime_format.clone();
+            (Arc::new(Mutex::new(session)), Instant::now())
+        });
+        *used = Instant::now();
+        Arc::clone(session)
+    };
+    // One turn at a time per session; different sessions run side by side.
+    let mut session = session.lock().await;
+    let prompt = prompt.trim();
+    if prompt.is_empty() {
+        return Err("empty prompt".to_string());
+    }
+    let prompt =
+        crate::redact(&state.redactor, prompt, "a served prompt").map_err(|e| e.to_string())?;
+
+    session.push(Message::new("user", prompt));
+    let context = crate::build_context(&session, state.args.max_context_messages);
+    let model = session.model.clone();
+    let mut usage = UsageTotals::default();
+    let request = ChatRequest::new(&model, &context)
+        .with_options(&state.options)
+        .streamed();
+    let reply = match state.client.chat_stream(&request, on_delta).await {
+        Ok(response) => {
+            response.check_service_tier(&state.options);
+            usage.record(&model, response.usage.as_ref());
+            Ok(response.choices.into_iter().next())
+        }
+        Err(e) => Err(e.to_string()),
+    };
+    let result = match reply {
+        Ok(Some(choice)) => {
+            let message = Message {
+                model: Some(model.clone()),
+                ..Message::reply(&choice.message)
+            };
+            let content = message.display_content();
+            session.push(message);
+            Ok((model, content))
+        }
+        Ok(None) => Err("the API returned no choices".to_string()),
+        Err(e) => Err(e),
+    };
+    if result.is_err() {
+        session.pop_unanswered();
+    }
+
+    let recent_start = session.recent_start(RECENT_EXCHANGES);
+    if session.summarized < recent_start {
+        crate::fold_into_summary(
+            &state.client,
+            &mut session,
+            recent_start,
+            &mut usage,
+            &state.args,
+        )
+        .await;
+    }
+
+    let mut totals = state.usage.lock().await;
+    for (model, used) in usage.by_model {
+        let entry = totals.by_model.entry(model).or_default();
+        entry.requests += used.requests;
+        entry.prompt_tokens += used.prompt_tokens;
+        entry.completion_tokens += used.completion_tokens;
+    }
+    result
+}
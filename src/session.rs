@@ -0,0 +1,553 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::config;
+use crate::gzip;
+use crate::markdown;
+use crate::{Attachment, Message, RECENT_EXCHANGES};
+
+/// The session file format written by this build. Bump it whenever saved files change in
+/// a way older ones need [`migrate`] for.
+pub const VERSION: u64 = 2;
+
+/// A conversation and the state derived from it. This is also the on-disk session format.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Session {
+    /// The [`VERSION`] of the format this session was saved in.
+    #[serde(default)]
+    pub version: u64,
+    pub model: String,
+    /// System prompt sent ahead of every request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    /// Prompts from `/push-system`, most recent last. The top one is sent instead of
+    /// `system` until `/pop-system` takes it off.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub system_stack: Vec<String>,
+    /// Facts the user pinned; always sent, and never dropped by the summarizer.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pinned: Vec<String>,
+    /// A few words describing the conversation, from `--auto-title`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Labels from `--tag`, for finding the session again with `--list-sessions`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub summary: Option<String>,
+    /// Number of leading messages of `conversation` already folded into `summary`.
+    #[serde(default)]
+    pub summarized: usize,
+    pub conversation: Vec<Message>,
+    /// Id of the most recently added message.
+    #[serde(default)]
+    pub last_id: u64,
+    /// Context from `/context-add`, sent with the next request only and never saved.
+    #[serde(skip)]
+    pub staged: Vec<String>,
+    /// Files from `/attach` waiting to go out with the next message.
+    #[serde(skip)]
+    pub attachments: Vec<Attachment>,
+    /// Set by `/rehydrate`: requests carry the whole conversation instead of the summary
+    /// and the recent messages.
+    #[serde(skip)]
+    pub rehydrated: bool,
+    /// `--target-length`: roughly how many words replies should run to.
+    #[serde(skip)]
+    pub target_length: Option<u32>,
+    /// `--inject-datetime`: how to write the current time at the top of the system prompt.
+    #[serde(skip)]
+    pub datetime_format: Option<String>,
+}
+
+impl Session {
+    pub fn new(model: &str) -> Self {
+        Session {
+            version: VERSION,
+            model: model.to_string(),
+            system: None,
+            system_stack: Vec::new(),
+            pinned: Vec::new(),
+            title: None,
+            tags: Vec::new(),
+            summary: None,
+            summarized: 0,
+            conversation: Vec::new(),
+            last_id: 0,
+            staged: Vec::new(),
+            attachments: Vec::new(),
+            rehydrated: false,
+            target_length: None,
+            datetime_format: None,
+        }
+    }
+
+    /// Appends a message to the conversation, assigning it the next message id.
+    pub fn push(&mut self, mut message: Message) {
+        self.last_id += 1;
+        message.id = Some(self.last_id);
+        self.conversation.push(message);
+    }
+
+    /// Drops the last message if it is a user message that never got a reply, so a failed
+    /// request doesn't leave it dangling in the history.
+    pub fn pop_unanswered(&mut self) {
+        if self.conversation.last().is_some_and(|m| m.role == "user") {
+            self.conversation.pop();
+        }
+    }
+
+    /// Removes the message with the given id, returning it and the index it was at. Keeps
+    /// `summarized` pointing at the same messages.
+    pub fn delete(&mut self, id: u64) -> Option<(Message, usize)> {
+        let index = self.conversation.iter().position(|m| m.id == Some(id))?;
+        let message = self.conversation.remove(index);
+        if index < self.summarized {
+            self.summarized -= 1;
+        }
+        Some((message, index))
+    }
+
+    /// Merges adjacent messages with the same role, keeping the summarized messages apart
+    /// from the rest. Returns how many messages were folded into their neighbour.
+    pub fn merge_consecutive(&mut self) -> usize {
+        let before = self.conversation.len();
+        self.summarized = merge_consecutive(&mut self.conversation, self.summarized);
+        before - self.conversation.len()
+    }
+
+    /// Replaces the first `count` messages with `message`, which gets a fresh id. If the
+    /// rolling summary only covered messages that are now gone, it is dropped, since
+    /// `message` says all it did; otherwise `message` counts as summarized too.
+    pub fn compact(&mut self, count: usize, mut message: Message) {
+        self.last_id += 1;
+        message.id = Some(self.last_id);
+        self.conversation.splice(..count, [message]);
+        if self.summarized >= count {
+            self.summarized -= count - 1;
+        } else {
+            self.summary = None;
+            self.summarized = 0;
+        }
+    }
+
+    /// Drops the text of all but the last `keep` summarized messages, which are only ever
+    /// needed again for exports. The last few exchanges are never dropped.
+    pub fn drop_summarized(&mut self, keep: usize) {
+        let end = self
+            .summarized
+            .saturating_sub(keep)
+            .min(self.recent_start(RECENT_EXCHANGES));
+        for message in &mut self.conversation[..end] {
+            message.content = String::new();
+            message.refusal = None;
+            message.dropped = true;
+        }
+    }
+
+    /// Index of the first message whose text is still in memory: everything after the last
+    /// message dropped by `--keep-summarized`.
+    pub fn first_kept(&self) -> usize {
+        self.conversation
+            .iter()
+            .rposition(|m| m.dropped)
+            .map_or(0, |i| i + 1)
+    }
+
+    /// The system prompt as a message, if there is one.
+    pub fn system_message(&self) -> Option<Message> {
+        system_prompt(
+            self.current_system(),
+            self.target_length,
+            self.datetime_format.as_deref(),
+        )
+        .map(|s| Message::new("system", s))
+    }
+
+    /// The system prompt in effect: the top of the `/push-system` stack, or `system`.
+    pub fn current_system(&self) -> Option<&str> {
+        self.system_stack
+            .last()
+            .or(self.system.as_ref())
+            .map(String::as_str)
+    }
+
+    /// The pinned facts as a system message, if any are pinned.
+    pub fn pinned_message(&self) -> Option<Message> {
+        if self.pinned.is_empty() {
+            return None;
+        }
+        let facts: Vec<String> = self.pinned.iter().map(|p| format!("- {}", p)).collect();
+        Some(Message::new(
+            "system",
+            format!("Facts to keep in mind:\n{}", facts.join("\n")),
+        ))
+    }
+
+    /// Index where the last `exchanges` exchanges begin, counting user messages rather than
+    /// assuming strict user/assistant pairs. 0 if there aren't that many yet.
+    pub fn recent_start(&self, exchanges: usize) -> usize {
+        if exchanges == 0 {
+            return self.conversation.len();
+        }
+        self.conversation
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, m)| m.role == "user")
+            .nth(exchanges - 1)
+            .map_or(0, |(i, _)| i)
+    }
+
+    /// The staged context as a single system message, if any is staged.
+    pub fn staged_message(&self) -> Option<Message> {
+        if self.staged.is_empty() {
+            return None;
+        }
+        Some(Message::new(
+            "system",
+            format!(
+                "Reference material for the next reply:\n\n{}",
+                self.staged.join("\n\n---\n\n")
+            ),
+        ))
+    }
+
+    /// Loads a session saved as JSON, or as Markdown when the file ends in `.md`; a
+    /// Markdown session that doesn't name its model uses `default_model`.
+    /// Gzip-compressed files (`.json.gz`, `.md.gz`) are decompressed first.
+    pub fn load(path: &Path, default_model: &str) -> Result<Session, Box<dyn Error>> {
+        let mut bytes = std::fs::read(path)?;
+        if gzip::is_gzip(&bytes) {
+            bytes = gzip::decompress(&bytes)?;
+        }
+        let text = String::from_utf8(bytes)?;
+        if is_markdown(path) {
+            return Ok(markdown::import(&text, default_model)?);
+        }
+        let json = migrate(serde_json::from_str(&text)?)?;
+        Ok(serde_json::from_value(json)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        let contents = if is_markdown(path) {
+            markdown::export(self)
+        } else {
+            serde_json::to_string_pretty(self)?
+        };
+        if is_compressed(path) {
+            std::fs::write(path, gzip::compress(contents.as_bytes()))?;
+        } else {
+            std::fs::write(path, contents)?;
+        }
+        Ok(())
+    }
+}
+
+/// Upgrades a saved session, as JSON, from whatever version it was written in to
+/// [`VERSION`], one version at a time. Files from before versioning count as version 1.
+pub fn migrate(mut json: serde_json::Value) -> Result<serde_json::Value, String> {
+    if !json.is_object() {
+        return Err("not a session file (expected a JSON object)".to_string());
+    }
+    let version = json.get("version").and_then(|v| v.as_u64()).unwrap_or(1);
+    if version > VERSION {
+        return Err(format!(
+            "this session was saved in format version {}, but this build only reads up to {}; update gpt to open it",
+            version, VERSION
+        ));
+    }
+    if version < 2 {
+        // Version 1 sessions may predate message ids; number those messages now.
+        let mut last_id = json.get("last_id").and_then(|v| v.as_u64()).unwrap_or(0);
+        if let Some(conversation) = json.get_mut("conversation").and_then(|c| c.as_array_mut()) {
+            for msg in conversation
+                .iter_mut()
+                .filter(|m| m.get("id").is_none_or(|id| id.is_null()))
+            {
+                last_id += 1;
+                msg["id"] = last_id.into();
+            }
+        }
+        json["last_id"] = last_id.into();
+    }
+    json["version"] = VERSION.into();
+    Ok(json)
+}
+
+/// The system prompt actually sent: `system` plus, with `--target-length`, a request to
+/// aim for about that many words. A soft hint cuts replies short far more gracefully than
+/// `max_tokens` does. With `--inject-datetime` the local time as of now, written with
+/// `datetime_format`, goes first, since models have no idea what day it is.
+pub fn system_prompt(
+    system: Option<&str>,
+    target_length: Option<u32>,
+    datetime_format: Option<&str>,
+) -> Option<String> {
+    let now = datetime_format.map(|format| {
+        format!(
+            "The current date and time is {}.",
+            chrono::Local::now().format(format)
+        )
+    });
+    let hint = target_length.map(|words| {
+        format!(
+            "Aim for replies of roughly {} words, unless the question clearly needs more.",
+            words
+        )
+    });
+    let parts: Vec<String> = [now, system.map(str::to_string), hint]
+        .into_iter()
+        .flatten()
+        .collect();
+    (!parts.is_empty()).then(|| parts.join("\n\n"))
+}
+
+/// How `--inject-datetime` writes the time by default, e.g. `Tuesday, 14 October 2025, 09:30 (UTC+02:00)`.
+pub const DATETIME_FORMAT: &str = "%A, %-d %B %Y, %H:%M (UTC%:z)";
+
+/// Whether `format` is a strftime format chrono can write; a bad one would panic mid-request.
+pub fn valid_datetime_format(format: &str) -> bool {
+    !chrono::format::StrftimeItems::new(format)
+        .any(|item| matches!(item, chrono::format::Item::Error))
+}
+
+fn is_markdown(path: &Path) -> bool {
+    let path = if is_compressed(path) {
+        Path::new(path.file_stem().unwrap_or_default())
+    } else {
+        path
+    };
+    path.extension().is_some_and(|ext| ext == "md")
+}
+
+fn is_compressed(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "gz")
+}
+
+/// Merges each run of adjacent same-role messages into its first message, joining the
+/// contents with a blank line. Refusals are left alone and no run crosses `boundary`.
+/// Returns the index `boundary` ends up at.
+pub fn merge_consecutive(messages: &mut Vec<Message>, boundary: usize) -> usize {
+    let original_len = messages.len();
+    let mut merged: Vec<Message> = Vec::with_capacity(original_len);
+    let mut new_boundary = 0;
+    for (i, message) in messages.drain(..).enumerate() {
+        if i == boundary {
+            new_boundary = merged.len();
+        }
+        match merged.last_mut() {
+            Some(last)
+                if i != boundary
+                    && last.role == message.role
+                    && last.refusal.is_none()
+                    && message.refusal.is_none() =>
+            {
+                last.content = format!(
+                    "{}\n\n{}",
+                    last.content.trim_end(),
+                    message.content.trim_start()
+                );
+            }
+            _ => merged.push(message),
+        }
+    }
+    if boundary >= original_len {
+        new_boundary = merged.len();
+    }
+    *messages = merged;
+    new_boundary
+}
+
+/// Where sessions are saved when no `--session` path is given.
+pub fn sessions_dir() -> Option<PathBuf> {
+    config::config_dir().map(|dir| dir.join("sessions"))
+}
+
+/// A fresh path in the sessions directory for a session started now, ending in `.json.gz`
+/// when `compress` is set.
+pub fn new_session_path(compress: bool) -> Option<PathBuf> {
+    let name = format!(
+        "session_{}.json{}",
+        chrono::Utc::now().format("%Y%m%dT%H%M%SZ"),
+        if compress { ".gz" } else { "" }
+    );
+    sessions_dir().map(|dir| dir.join(name))
+}
+
+/// Most characters of a title that go into a session's file name.
+const TITLE_IN_NAME: usize = 40;
+
+/// `path` with `title` added to the file name, e.g. `session_<time>_rust-lifetimes.json`.
+pub fn titled_path(path: &Path, title: &str) -> PathBuf {
+    let mut slug = String::new();
+    for c in title.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug: String = slug.chars().take(TITLE_IN_NAME).collect();
+    let slug = slug.trim_end_matches('-');
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let Some((stem, extension)) = name.split_once('.') else {
+        return path.to_path_buf();
+    };
+    if slug.is_empty() {
+        return path.to_path_buf();
+    }
+    path.with_file_name(format!("{}_{}.{}", stem, slug, extension))
+}
+
+/// The session files in the sessions directory with their modification times, newest
+/// first.
+pub fn saved_sessions() -> Vec<(SystemTime, PathBuf)> {
+    let Some(entries) = sessions_dir().and_then(|dir| std::fs::read_dir(dir).ok()) else {
+        return Vec::new();
+    };
+    let mut sessions: Vec<(SystemTime, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.ends_with(".json") || name.ends_with(".json.gz")
+        })
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .collect();
+    sessions.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    sessions
+}
+
+/// The most recently modified session file in the sessions directory.
+pub fn latest_session() -> Option<PathBuf> {
+    saved_sessions().into_iter().next().map(|(_, path)| path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(roles: &[&str]) -> Session {
+        let mut session = Session::new("gpt-4o");
+        for (i, role) in roles.iter().enumerate() {
+            session.push(Message::new(role, format!("message {}", i)));
+        }
+        session
+    }
+
+    #[test]
+    fn recent_start_counts_user_messages() {
+        let session = session(&[
+            "user",
+            "assistant",
+            "user",
+            "assistant",
+            "user",
+            "assistant",
+        ]);
+        assert_eq!(session.recent_start(1), 4);
+        assert_eq!(session.recent_start(2), 2);
+        assert_eq!(session.recent_start(3), 0);
+        assert_eq!(session.recent_start(4), 0);
+        assert_eq!(session.recent_start(0), 6);
+    }
+
+    #[test]
+    fn recent_start_with_mixed_roles() {
+        // A developer note, a failed turn with no reply and a tool message between replies.
+        let session = session(&[
+            "developer",
+            "user",
+            "assistant",
+            "user",
+            "user",
+            "assistant",
+            "tool",
+            "assistant",
+            "user",
+            "assistant",
+        ]);
+        assert_eq!(session.recent_start(1), 8);
+        assert_eq!(session.recent_start(2), 4);
+        assert_eq!(session.recent_start(3), 3);
+        assert_eq!(session.recent_start(4), 1);
+        // Fewer user messages than asked for: nothing is old enough to summarize.
+        assert_eq!(session.recent_start(5), 0);
+    }
+
+    #[test]
+    fn recent_start_without_user_messages() {
+        let session = session(&["system", "assistant", "developer"]);
+        assert_eq!(session.recent_start(1), 0);
+    }
+
+    /// A session as version 1 wrote it: no `version`, no `last_id` and no message ids.
+    const V1_SESSION: &str = r#"{
+        "model": "gpt-4o",
+        "summary": "Talked about Rust.",
+        "summarized": 2,
+        "conversation": [
+            {"role": "user", "content": "What is a lifetime?"},
+            {"role": "assistant", "content": "A scope for references."},
+            {"role": "user", "content": "Thanks"}
+        ]
+    }"#;
+
+    #[test]
+    fn migrates_a_v1_session() {
+        let json = migrate(serde_json::from_str(V1_SESSION).unwrap()).unwrap();
+        let session: Session = serde_json::from_value(json).unwrap();
+        assert_eq!(session.version, VERSION);
+        assert_eq!(session.last_id, 3);
+        let ids: Vec<Option<u64>> = session.conversation.iter().map(|m| m.id).collect();
+        assert_eq!(ids, [Some(1), Some(2), Some(3)]);
+        assert_eq!(session.summary.as_deref(), Some("Talked about Rust."));
+        assert_eq!(session.summarized, 2);
+        assert_eq!(session.conversation[1].content, "A scope for references.");
+    }
+
+    #[test]
+    fn loads_a_v1_session_file() {
+        let path = std::env::temp_dir().join(format!("gpt-v1-session-{}.json", std::process::id()));
+        std::fs::write(&path, V1_SESSION).unwrap();
+        let session = Session::load(&path, "gpt-4o");
+        std::fs::remove_file(&path).unwrap();
+        let session = session.unwrap();
+        assert_eq!(session.version, VERSION);
+        assert_eq!(session.conversation.len(), 3);
+        assert_eq!(session.last_id, 3);
+    }
+
+    #[test]
+    fn migration_keeps_existing_ids() {
+        let json = serde_json::json!({
+            "model": "gpt-4o",
+            "last_id": 7,
+            "conversation": [
+                {"id": 7, "role": "user", "content": "kept"},
+                {"role": "assistant", "content": "numbered"}
+            ]
+        });
+        let session: Session = serde_json::from_value(migrate(json).unwrap()).unwrap();
+        let ids: Vec<Option<u64>> = session.conversation.iter().map(|m| m.id).collect();
+        assert_eq!(ids, [Some(7), Some(8)]);
+        assert_eq!(session.last_id, 8);
+    }
+
+    #[test]
+    fn refuses_newer_and_malformed_files() {
+        let newer =
+            serde_json::json!({"version": VERSION + 1, "model": "gpt-4o", "conversation": []});
+        assert!(migrate(newer).unwrap_err().contains("update gpt"));
+        assert!(migrate(serde_json::json!([1, 2])).is_err());
+    }
+}
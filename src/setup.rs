@@ -0,0 +1,61 @@
+//! First-run setup: asks for an API key, a preferred model and an optional system prompt,
+//! and writes them to the config file.
+
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use crate::config::{self, Config};
+
+fn ask(question: &str) -> io::Result<String> {
+    print!("{}", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+/// Runs the wizard and saves the answers to `path`, keeping anything already in the file.
+/// Returns `None` if the user declines.
+pub fn run(path: &Path, config: &mut Config) -> Result<Option<String>, Box<dyn Error>> {
+    println!("No OpenAI API key found (set OPENAI_API_KEY or add api_key to the config file).");
+    let answer = ask("Set one up now? [Y/n] ")?;
+    if answer.eq_ignore_ascii_case("n") || answer.eq_ignore_ascii_case("no") {
+        return Ok(None);
+    }
+
+    let api_key = ask("OpenAI API key: ")?;
+    if api_key.is_empty() {
+        return Ok(None);
+    }
+    let model = ask("Preferred model [gpt-4o]: ")?;
+    let system = ask("Default system prompt (optional): ")?;
+
+    let mut settings = format!("api_key = {}\n", config::quote(&api_key));
+    if !model.is_empty() {
+        settings.push_str(&format!("model = {}\n", config::quote(&model)));
+        config.model = Some(model);
+    }
+    if !system.is_empty() {
+        settings.push_str(&format!("system = {}\n", config::quote(&system)));
+        config.system = Some(system);
+    }
+    config.api_key = Some(api_key.clone());
+
+    // Top-level keys have to come before any [section], so they go at the start.
+    let existing = std::fs::read_to_string(path).unwrap_or_default();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    write!(file, "{}", settings)?;
+    if !existing.is_empty() {
+        write!(file, "\n{}", existing)?;
+    }
+    println!("Saved to {}\n", path.display());
+    Ok(Some(api_key))
+}
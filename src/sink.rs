@@ -0,0 +1,107 @@
+//! `--reply-sink PATH`: writes every reply to a FIFO or Unix socket as well as the usual
+//! output, one JSON object per line (`{"model": …, "content": …}`), so an editor or a
+//! custom front end can follow the replies without parsing stdout.
+//!
+//! The sink is opened at the first reply and kept open. If it isn't there, nothing is
+//! reading it or the reader goes away, a warning is printed once and the session carries
+//! on; the next reply tries again, so a reader can come and go.
+
+use std::time::Duration;
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// How long a reply may take to write before the sink is given up on, so a reader that
+/// stops reading can't stall the session.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+type Writer = Box<dyn AsyncWrite + Unpin + Send>;
+
+pub struct ReplySink {
+    path: String,
+    writer: Option<Writer>,
+    // Set while the sink is unavailable, so it is only warned about once.
+    warned: bool,
+}
+
+impl ReplySink {
+    pub fn new(path: &str) -> Self {
+        ReplySink {
+            path: path.to_string(),
+            writer: None,
+            warned: false,
+        }
+    }
+
+    /// Writes a reply from `model` to the sink, reconnecting once if the last reader left.
+    pub async fn send(&mut self, model: &str, content: &str) {
+        let line = format!(
+            "{}\n",
+            serde_json::json!({ "model": model, "content": content })
+        );
+        let mut error = String::new();
+        for _ in 0..2 {
+            let mut writer = match self.writer.take() {
+                Some(writer) => writer,
+                None => match connect(&self.path).await {
+                    Ok(writer) => writer,
+                    Err(e) => {
+                        error = e.to_string();
+                        break;
+                    }
+                },
+            };
+            let write = async {
+                writer.write_all(line.as_bytes()).await?;
+                writer.flush().await
+            };
+            match tokio::time::timeout(WRITE_TIMEOUT, write).await {
+                Ok(Ok(())) => {
+                    self.writer = Some(writer);
+                    self.warned = false;
+                    return;
+                }
+                Ok(Err(e)) => error = e.to_string(),
+                Err(_) => error = format!("nothing read it within {}s", WRITE_TIMEOUT.as_secs()),
+            }
+        }
+        if !self.warned {
+            eprintln!(
+                "Warning: could not write the reply to --reply-sink {}: {}; carrying on without it.",
+                self.path, error
+            );
+            self.warned = true;
+        }
+    }
+}
+
+/// Opens `path` for writing: connects if it is a Unix socket, or opens it without
+/// blocking if it is a FIFO.
+#[cfg(unix)]
+async fn connect(path: &str) -> std::io::Result<Writer> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // ENXIO: a FIFO that nobody has open for reading.
+    const ENXIO: i32 = 6;
+
+    let kind = std::fs::metadata(path)?.file_type();
+    if kind.is_socket() {
+        Ok(Box::new(tokio::net::UnixStream::connect(path).await?))
+    } else if kind.is_fifo() {
+        match tokio::net::unix::pipe::OpenOptions::new().open_sender(path) {
+            Ok(sender) => Ok(Box::new(sender)),
+            Err(e) if e.raw_os_error() == Some(ENXIO) => {
+                Err(std::io::Error::other("nothing is reading from the FIFO"))
+            }
+            Err(e) => Err(e),
+        }
+    } else {
+        Err(std::io::Error::other("it is not a FIFO or a Unix socket"))
+    }
+}
+
+#[cfg(not(unix))]
+async fn connect(_path: &str) -> std::io::Result<Writer> {
+    Err(std::io::Error::other(
+        "FIFOs and Unix sockets are only supported on Unix",
+    ))
+}
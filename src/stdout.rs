@@ -0,0 +1,52 @@
+//! `print!` and `println!` for a stdout that may be a closed pipe, e.g. `gpt --session s |
+//! head -1`. The standard macros panic there; these stop writing and remember it, so the
+//! session can end normally, and be saved, the next time the loop checks [`closed`].
+
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static CLOSED: AtomicBool = AtomicBool::new(false);
+
+/// Whether a write found stdout closed. Nothing is written to it after that.
+pub fn closed() -> bool {
+    CLOSED.load(Ordering::Relaxed)
+}
+
+fn check(result: std::io::Result<()>) {
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {
+            CLOSED.store(true, Ordering::Relaxed)
+        }
+        Err(e) => panic!("failed printing to stdout: {}", e),
+        Ok(()) => {}
+    }
+}
+
+/// What `print!` does, short of panicking on a closed pipe.
+pub fn write(args: std::fmt::Arguments) {
+    if !closed() {
+        check(std::io::stdout().lock().write_fmt(args));
+    }
+}
+
+/// Flushes stdout, noting rather than returning a closed pipe.
+pub fn flush() {
+    if !closed() {
+        check(std::io::stdout().flush());
+    }
+}
+
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::stdout::write(format_args!($($arg)*))
+    };
+}
+
+macro_rules! println {
+    () => {
+        $crate::stdout::write(format_args!("\n"))
+    };
+    ($($arg:tt)*) => {
+        $crate::stdout::write(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
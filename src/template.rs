@@ -0,0 +1,98 @@
+//! Prompt templates from the config file's `[templates]` section, with `{{name}}`
+//! placeholders filled in by `/template NAME key=value ...`.
+
+use std::collections::BTreeMap;
+
+/// Splits `/template` arguments into the template name and its `key=value` pairs. Values
+/// may be quoted with `"` or `'` to include spaces.
+pub fn parse_args(input: &str) -> Result<(String, BTreeMap<String, String>), String> {
+    let mut words = split_words(input)?.into_iter();
+    let name = words
+        .next()
+        .ok_or("Usage: /template NAME [key=value ...]")?;
+    let mut vars = BTreeMap::new();
+    for word in words {
+        let (key, value) = word
+            .split_once('=')
+            .ok_or_else(|| format!("expected key=value, got '{}'", word))?;
+        vars.insert(key.to_string(), value.to_string());
+    }
+    Ok((name, vars))
+}
+
+fn split_words(input: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut chars = input.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            return Ok(words);
+        }
+        let mut word = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' | '\'' => {
+                    let quote = c;
+                    loop {
+                        match chars.next() {
+                            Some('\\') if quote == '"' => match chars.next() {
+                                Some(escaped) => word.push(escaped),
+                                None => return Err("unterminated quote".to_string()),
+                            },
+                            Some(c) if c == quote => break,
+                            Some(c) => word.push(c),
+                            None => return Err("unterminated quote".to_string()),
+                        }
+                    }
+                }
+                c if c.is_whitespace() => break,
+                c => word.push(c),
+            }
+        }
+        words.push(word);
+    }
+}
+
+/// Fills every `{{name}}` in `template` from `vars`. Placeholders without a value and
+/// values without a placeholder are both errors, so typos don't go unnoticed.
+pub fn render(template: &str, vars: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut out = String::new();
+    let mut missing = Vec::new();
+    let mut used = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let name = rest[start + 2..start + 2 + len].trim();
+        match vars.get(name) {
+            Some(value) => {
+                out.push_str(value);
+                used.push(name);
+            }
+            None => {
+                if !missing.contains(&name) {
+                    missing.push(name);
+                }
+            }
+        }
+        rest = &rest[start + 2 + len + 2..];
+    }
+    out.push_str(rest);
+
+    if !missing.is_empty() {
+        return Err(format!("missing value for {}", missing.join(", ")));
+    }
+    let unused: Vec<&str> = vars
+        .keys()
+        .map(String::as_str)
+        .filter(|k| !used.contains(k))
+        .collect();
+    if !unused.is_empty() {
+        return Err(format!("the template has no {}", unused.join(", ")));
+    }
+    Ok(out)
+}
@@ -0,0 +1,42 @@
+/// Rough token count for `text`, using the usual ~4 characters per token for English.
+/// Good enough for budgeting; it is not a real tokenizer.
+pub fn estimate(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Which part of an oversized message `--truncate-mode` keeps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TruncateMode {
+    /// The beginning.
+    Head,
+    /// The end.
+    Tail,
+    /// The beginning and the end, dropping the middle.
+    #[default]
+    Both,
+}
+
+/// Cuts `text` down to about `max_tokens`, keeping the part `mode` names, and marks where
+/// text was dropped. Returns the shortened text and how many characters were dropped.
+pub fn truncate(text: &str, max_tokens: usize, mode: TruncateMode) -> (String, usize) {
+    let chars: Vec<char> = text.chars().collect();
+    let marker = |dropped: usize| format!("\n\n[… {} characters truncated …]\n\n", dropped);
+    let keep = (max_tokens * 4).saturating_sub(marker(chars.len()).chars().count());
+    if chars.len() <= max_tokens * 4 {
+        return (text.to_string(), 0);
+    }
+    let dropped = chars.len() - keep;
+    let head: String = chars[..keep].iter().collect();
+    let tail: String = chars[chars.len() - keep..].iter().collect();
+    let truncated = match mode {
+        TruncateMode::Head => head + &marker(dropped),
+        TruncateMode::Tail => marker(dropped) + &tail,
+        TruncateMode::Both => {
+            let head: String = chars[..keep / 2].iter().collect();
+            let tail: String = chars[chars.len() - (keep - keep / 2)..].iter().collect();
+            head + &marker(dropped) + &tail
+        }
+    };
+    (truncated.trim().to_string(), dropped)
+}
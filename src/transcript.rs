@@ -0,0 +1,81 @@
+//! The debug transcript (`chat_transcript_<time>.txt`), written as the session goes.
+
+use std::fs::File;
+use std::io::{self, Write};
+
+use crate::session::Session;
+use crate::{format_id, format_timestamp};
+
+/// A transcript file that only ever has new messages appended to it.
+pub struct Transcript {
+    pub path: String,
+    file: File,
+    /// Id of the last message written.
+    written: u64,
+    /// The roles written; empty for all of them.
+    roles: Vec<String>,
+}
+
+impl Transcript {
+    /// Starts a transcript of the messages with one of `roles`, or all of them when empty.
+    /// The system prompt heads it when system messages are included.
+    pub fn create(path: String, roles: &[String], system: Option<&str>) -> io::Result<Transcript> {
+        let mut file = File::create(&path)?;
+        writeln!(file, "Conversation Transcript:")?;
+        let mut transcript = Transcript {
+            path,
+            file,
+            written: 0,
+            roles: roles.to_vec(),
+        };
+        if let Some(system) = system
+            && transcript.includes("system")
+        {
+            writeln!(transcript.file, "System prompt: {}", system)?;
+        }
+        Ok(transcript)
+    }
+
+    fn includes(&self, role: &str) -> bool {
+        self.roles.is_empty() || self.roles.iter().any(|r| r == role)
+    }
+
+    /// Writes the messages added to the conversation since the last call.
+    pub fn append(&mut self, session: &Session) -> io::Result<()> {
+        for msg in &session.conversation {
+            let id = msg.id.unwrap_or_default();
+            if id <= self.written {
+                continue;
+            }
+            self.written = id;
+            if !self.includes(&msg.role) {
+                continue;
+            }
+            writeln!(
+                self.file,
+                "{} {}{}: {}",
+                format_timestamp(msg),
+                format_id(msg),
+                msg.role,
+                msg.display_content()
+            )?;
+        }
+        self.file.flush()
+    }
+
+    /// Records something that happened to the conversation itself, such as `/compact`.
+    pub fn note(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.file, "[{}]", text)?;
+        self.file.flush()
+    }
+
+    /// Writes any remaining messages, then the summary as it stands at the end. The summary
+    /// changes over the session, so it is only written once, last.
+    pub fn finish(&mut self, session: &Session) -> io::Result<()> {
+        self.append(session)?;
+        if let Some(summary) = &session.summary {
+            writeln!(self.file, "\nFinal summary:\n{}", summary)?;
+        }
+        self.file.flush()
+    }
+}
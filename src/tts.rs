@@ -0,0 +1,73 @@
+//! Reading replies aloud for `--speak`.
+
+use std::error::Error;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+
+use crate::hooks;
+
+/// The platform's usual text-to-speech command, if it has one.
+pub fn default_command() -> Option<&'static str> {
+    if cfg!(target_os = "macos") {
+        Some("say")
+    } else if cfg!(target_os = "linux") {
+        Some("espeak")
+    } else {
+        None
+    }
+}
+
+/// Turns a Markdown reply into something worth listening to: code blocks are skipped and
+/// formatting characters dropped.
+pub fn speech_text(reply: &str) -> String {
+    let mut text = String::new();
+    let mut in_code = false;
+    for line in reply.lines() {
+        if line.trim_start().starts_with("```") {
+            if !in_code {
+                text.push_str("Code block omitted.\n");
+            }
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            continue;
+        }
+        let line = line.trim_start_matches(|c: char| c == '#' || c == '>' || c.is_whitespace());
+        let line = line.strip_prefix("- ").unwrap_or(line);
+        text.extend(line.chars().filter(|c| !matches!(c, '*' | '`' | '_')));
+        text.push('\n');
+    }
+    text
+}
+
+/// Speaks `text` with `command`, which reads it on stdin. Ctrl+C stops the speech without
+/// ending the session.
+pub async fn speak(command: &str, text: &str) -> Result<(), Box<dyn Error>> {
+    let mut child = hooks::shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("could not start '{}': {}", command, e))?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    let text = text.to_string();
+    tokio::spawn(async move {
+        let _ = stdin.write_all(text.as_bytes()).await;
+    });
+
+    tokio::select! {
+        status = child.wait() => {
+            let status = status?;
+            if !status.success() {
+                return Err(format!("'{}' failed ({})", command, status).into());
+            }
+        }
+        _ = tokio::signal::ctrl_c() => {
+            let _ = child.kill().await;
+            println!("(speech stopped)");
+        }
+    }
+    Ok(())
+}
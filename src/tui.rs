@@ -0,0 +1,644 @@
+//! `--tui`: a full-screen view of the conversation, with the history in a scrollable pane
+//! above an input line. It is drawn with plain ANSI escapes, and `stty` puts the terminal
+//! into raw mode for as long as it is up. Prompts and replies go through the same
+//! [`Pipeline`] as in the prompt loop: hooks, redaction, --output and the rest.
+
+use std::error::Error;
+use std::io::{IsTerminal, Read, Write};
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+use tokio::sync::mpsc;
+
+use crate::api::{ApiClient, RequestOptions};
+use crate::commands::{self, Command as SlashCommand};
+use crate::config::Config;
+use crate::session::Session;
+use crate::usage::UsageTotals;
+use crate::{Args, Message, NOTIFY_AFTER, Pipeline, RECENT_EXCHANGES, wrap};
+
+/// The keys, shown on the status line until something else needs saying.
+const HELP: &str = "Enter send | PgUp PgDn Up Down scroll | Ctrl+L clear | Ctrl+N switch model | Esc give up | Ctrl+C quit";
+
+/// Size of a terminal whose size can't be read, as rows and columns.
+const FALLBACK_SIZE: (usize, usize) = (24, 80);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Esc,
+    /// Ctrl+L: clear the history pane; the conversation itself is kept.
+    ClearView,
+    /// Ctrl+U: clear the input line.
+    ClearInput,
+    /// Ctrl+N: switch to the next of the `--favorite-models`, or without any, start typing
+    /// `/model`.
+    NextModel,
+    /// Ctrl+C or Ctrl+D.
+    Quit,
+}
+
+/// Turns the bytes read from a raw terminal into keys. A UTF-8 character split between
+/// reads is kept in `pending` until the rest arrives; unknown escape sequences are dropped.
+fn parse_keys(pending: &mut Vec<u8>, bytes: &[u8]) -> Vec<Key> {
+    pending.extend_from_slice(bytes);
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < pending.len() {
+        let byte = pending[i];
+        i += 1;
+        let key = match byte {
+            0x03 | 0x04 => Key::Quit,
+            0x0C => Key::ClearView,
+            0x0E => Key::NextModel,
+            0x15 => Key::ClearInput,
+            b'\r' | b'\n' => Key::Enter,
+            0x08 | 0x7F => Key::Backspace,
+            0x1B if matches!(pending.get(i), Some(b'[' | b'O')) => {
+                let params = i + 1;
+                let Some(end) = pending[params..]
+                    .iter()
+                    .position(|b| (0x40..=0x7E).contains(b))
+                else {
+                    i = pending.len();
+                    continue;
+                };
+                let param = String::from_utf8_lossy(&pending[params..params + end]).to_string();
+                let last = pending[params + end];
+                i = params + end + 1;
+                match (last, param.as_str()) {
+                    (b'A', _) => Key::Up,
+                    (b'B', _) => Key::Down,
+                    (b'H', _) | (b'~', "1" | "7") => Key::Home,
+                    (b'F', _) | (b'~', "4" | "8") => Key::End,
+                    (b'~', "5") => Key::PageUp,
+                    (b'~', "6") => Key::PageDown,
+                    _ => continue,
+                }
+            }
+            0x1B => Key::Esc,
+            0x00..=0x1F => continue,
+            _ => {
+                let start = i - 1;
+                let len = match byte {
+                    0xF0.. => 4,
+                    0xE0.. => 3,
+                    0xC0.. => 2,
+                    _ => 1,
+                };
+                if start + len > pending.len() {
+                    pending.drain(..start);
+                    return keys;
+                }
+                i = start + len;
+                match std::str::from_utf8(&pending[start..i]) {
+                    Ok(text) => {
+                        Key::Char(text.chars().next().unwrap_or(char::REPLACEMENT_CHARACTER))
+                    }
+                    Err(_) => Key::Char(char::REPLACEMENT_CHARACTER),
+                }
+            }
+        };
+        keys.push(key);
+    }
+    pending.clear();
+    keys
+}
+
+/// Runs `stty` on the terminal and returns what it printed.
+fn stty(args: &[&str]) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("could not run stty: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("stty {} failed", args.join(" ")).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The terminal's size as rows and columns.
+fn terminal_size() -> (usize, usize) {
+    let size = stty(&["size"]).ok().and_then(|size| {
+        let (rows, cols) = size.split_once(' ')?;
+        Some((rows.parse().ok()?, cols.parse().ok()?))
+    });
+    size.filter(|&(rows, cols)| rows > 2 && cols > 0)
+        .unwrap_or(FALLBACK_SIZE)
+}
+
+/// Raw mode on the alternate screen, undone when dropped. Output processing is left on,
+/// so stray warnings still start at the left edge until the next redraw.
+struct Screen {
+    saved: String,
+}
+
+impl Screen {
+    fn enter() -> Result<Screen, Box<dyn Error>> {
+        let saved = stty(&["-g"])?;
+        stty(&[
+            "-icanon", "-echo", "-isig", "-ixon", "-icrnl", "-iexten", "min", "1",
+        ])?;
+        print!("\x1b[?1049h");
+        Ok(Screen { saved })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        print!("\x1b[?1049l\x1b[?25h");
+        let _ = std::io::stdout().flush();
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+/// Reads stdin on its own thread, since a read can't be cancelled, and sends what arrives.
+fn read_stdin() -> mpsc::UnboundedReceiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin();
+        let mut buf = [0u8; 1024];
+        while let Ok(n @ 1..) = stdin.read(&mut buf) {
+            if sender.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// The keys typed at the terminal.
+struct Keys {
+    bytes: mpsc::UnboundedReceiver<Vec<u8>>,
+    pending: Vec<u8>,
+    ready: std::collections::VecDeque<Key>,
+}
+
+impl Keys {
+    /// The next key, or `None` once stdin is closed.
+    async fn next(&mut self) -> Option<Key> {
+        while self.ready.is_empty() {
+            let bytes = self.bytes.recv().await?;
+            self.ready.extend(parse_keys(&mut self.pending, &bytes));
+        }
+        self.ready.pop_front()
+    }
+}
+
+/// What is on screen besides the conversation.
+struct View {
+    rows: usize,
+    cols: usize,
+    input: String,
+    /// How many lines the history is scrolled up from the bottom.
+    scroll: usize,
+    /// Messages before this index were cleared from the pane with Ctrl+L.
+    cleared: usize,
+    status: Option<String>,
+}
+
+impl View {
+    fn pane_height(&self) -> usize {
+        self.rows.saturating_sub(2)
+    }
+
+    /// The history pane's lines for the messages not cleared away.
+    fn history(&self, session: &Session) -> Vec<String> {
+        let mut lines = Vec::new();
+        for message in session.conversation.iter().skip(self.cleared) {
+            let label = match message.role.as_str() {
+                "user" => "You",
+                "assistant" => message.model.as_deref().unwrap_or(&session.model),
+                role => role,
+            };
+            let text = format!("{}: {}", label, message.display_content().trim());
+            lines.extend(wrap::wrap(&text, self.cols).lines().map(str::to_string));
+            lines.push(String::new());
+        }
+        lines
+    }
+
+    /// Scrolls by `lines`, up for positive values, staying within the history.
+    fn scroll_by(&mut self, lines: isize, history: usize) {
+        let most = history.saturating_sub(self.pane_height());
+        self.scroll = self.scroll.saturating_add_signed(lines).min(most);
+    }
+
+    /// Applies a key that edits the input or moves the view, which work at any time.
+    /// Returns false for keys it leaves to the caller.
+    fn edit(&mut self, key: Key, session: &Session) -> bool {
+        let history = self.history(session).len();
+        let page = self.pane_height().max(1) as isize;
+        match key {
+            Key::Char(c) => self.input.push(c),
+            Key::Backspace => {
+                self.input.pop();
+            }
+            Key::ClearInput => self.input.clear(),
+            Key::Up => self.scroll_by(1, history),
+            Key::Down => self.scroll_by(-1, history),
+            Key::PageUp => self.scroll_by(page, history),
+            Key::PageDown => self.scroll_by(-page, history),
+            Key::Home => self.scroll_by(isize::MAX, history),
+            Key::End => self.scroll = 0,
+            Key::ClearView => {
+                self.cleared = session.conversation.len();
+                self.scroll = 0;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Ctrl+N: moves on to the next of the `favorites`, or without any, starts a `/model`
+    /// command for the user to finish.
+    fn next_model(&mut self, session: &mut Session, favorites: &[String]) {
+        if favorites.is_empty() {
+            self.input = "/model ".to_string();
+            self.status = Some("Type a model name and press Enter.".to_string());
+        } else {
+            session.model = crate::next_favorite(favorites, &session.model).to_string();
+            self.status = Some(format!("Switched to model {}.", session.model));
+        }
+    }
+
+    fn draw(&self, session: &Session) {
+        let history = self.history(session);
+        let pane = self.pane_height();
+        let bottom = history.len().saturating_sub(self.scroll);
+        let top = bottom.saturating_sub(pane);
+        let mut out = String::from("\x1b[?25l\x1b[H");
+        for row in 0..pane {
+            let line = history.get(top + row).filter(|_| top + row < bottom);
+            out.extend(
+                line.into_iter()
+                    .flat_map(|line| line.chars().take(self.cols)),
+            );
+            out.push_str("\x1b[K\r\n");
+        }
+        let scrolled = if self.scroll > 0 {
+            format!(" (scrolled up {})", self.scroll)
+        } else {
+            String::new()
+        };
+        let status = format!(
+            " {}{} | {}",
+            session.model,
+            scrolled,
+            self.status.as_deref().unwrap_or(HELP)
+        );
+        let status: String = status
+            .chars()
+            .chain(std::iter::repeat(' '))
+            .take(self.cols)
+            .collect();
+        out.push_str(&format!("\x1b[7m{}\x1b[0m\r\n", status));
+        // Long input scrolls sideways so its end, where typing happens, stays in view.
+        let room = self.cols.saturating_sub(3);
+        let typed = self.input.chars().count();
+        let shown: String = self
+            .input
+            .chars()
+            .skip(typed.saturating_sub(room))
+            .collect();
+        out.push_str(&format!("> {}\x1b[K\x1b[?25h", shown));
+        print!("{}", out);
+        let _ = std::io::stdout().flush();
+    }
+}
+
+/// Runs the session in the full-screen view until the user quits or stdin closes.
+pub async fn run(
+    client: &ApiClient,
+    session: &mut Session,
+    args: &Args,
+    options: &RequestOptions,
+    config: &Config,
+    pipeline: &mut Pipeline,
+    usage: &mut UsageTotals,
+) -> Result<(), Box<dyn Error>> {
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        return Err("--tui needs a terminal on stdin and stdout".into());
+    }
+    let _screen = Screen::enter()?;
+    let (rows, cols) = terminal_size();
+    let mut view = View {
+        rows,
+        cols,
+        input: String::new(),
+        scroll: 0,
+        cleared: 0,
+        status: None,
+    };
+    let mut keys = Keys {
+        bytes: read_stdin(),
+        pending: Vec::new(),
+        ready: Default::default(),
+    };
+    let favorites: Vec<String> = args
+        .favorite_models
+        .iter()
+        .map(|m| config.resolve_model(m))
+        .collect();
+    let fallbacks: Vec<String> = args
+        .fallback_models
+        .iter()
+        .map(|m| config.resolve_model(m))
+        .collect();
+    #[cfg(unix)]
+    let mut resized =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::window_change()).ok();
+
+    loop {
+        view.draw(session);
+        let resize = async {
+            #[cfg(unix)]
+            if let Some(resized) = &mut resized {
+                resized.recv().await;
+                return;
+            }
+            std::future::pending::<()>().await
+        };
+        let key = tokio::select! {
+            key = keys.next() => key,
+            _ = resize => {
+                (view.rows, view.cols) = terminal_size();
+                print!("\x1b[2J");
+                continue;
+            }
+        };
+        let Some(key) = key else {
+            break;
+        };
+        view.status = None;
+        if view.edit(key, session) {
+            continue;
+        }
+        match key {
+            Key::Quit => break,
+            Key::NextModel => view.next_model(session, &favorites),
+            Key::Enter => {
+                let typed = std::mem::take(&mut view.input);
+                let prompt = typed.trim();
+                match commands::parse(prompt) {
+                    _ if prompt.is_empty() => {}
+                    Some(Ok(SlashCommand::Model(Some(model)))) => {
+                        session.model = config.resolve_model(&model);
+                        view.status = Some(format!("Switched to model {}.", session.model));
+                    }
+                    Some(Ok(SlashCommand::Model(None))) => {
+                        view.status = Some(format!("Using model {}.", session.model));
+                    }
+                    Some(Ok(SlashCommand::NextModel)) => view.next_model(session, &favorites),
+                    Some(Ok(_)) => {
+                        view.status =
+                            Some("Only /model and /next-model work in --tui.".to_string());
+                    }
+                    Some(Err(usage)) => view.status = Some(usage),
+                    None => {
+                        let prompt = commands::unescape(prompt).to_string();
+                        let turn = Turn {
+                            client,
+                            args,
+                            options,
+                            fallbacks: &fallbacks,
+                        };
+                        turn.send(session, pipeline, usage, &mut view, &mut keys, prompt)
+                            .await;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// What sending a prompt needs that stays the same from one turn to the next.
+struct Turn<'a> {
+    client: &'a ApiClient,
+    args: &'a Args,
+    options: &'a RequestOptions,
+    /// The resolved --fallback-model list.
+    fallbacks: &'a [String],
+}
+
+impl Turn<'_> {
+    /// Sends `prompt` through the [`Pipeline`] and keeps the reply, redrawing while it is
+    /// on its way. Typing and scrolling carry on meanwhile; Esc gives up on the reply.
+    async fn send(
+        &self,
+        session: &mut Session,
+        pipeline: &mut Pipeline,
+        usage: &mut UsageTotals,
+        view: &mut View,
+        keys: &mut Keys,
+        prompt: String,
+    ) {
+        let args = self.args;
+        let model = session.model.clone();
+        view.scroll = 0;
+        view.status = Some(format!("Waiting for {}... (Esc to give up)", model));
+        view.draw(session);
+
+        let prompt = match pipeline.outgoing(args, &prompt).await {
+            Ok(prompt) => prompt,
+            Err(e) => {
+                view.status = Some(format!("Error: {}; nothing was sent.", e));
+                return;
+            }
+        };
+        session.push(Message {
+            attachments: session.attachments.clone(),
+            ..Message::new("user", prompt)
+        });
+        let context = crate::conversation_context(session, args);
+        let warning = match crate::check_context(args, &context) {
+            Ok(warning) => warning.map(|problem| format!("Warning: {}. ", problem)),
+            Err(problem) => {
+                session.pop_unanswered();
+                view.status = Some(format!("Error: {}; nothing was sent.", problem));
+                return;
+            }
+        };
+
+        let started = Instant::now();
+        let reply = {
+            let request = crate::request_with_fallback(
+                self.client,
+                &model,
+                self.fallbacks,
+                &context,
+                self.options,
+                usage,
+                None,
+            );
+            tokio::pin!(request);
+            loop {
+                tokio::select! {
+                    reply = &mut request => break Some(reply),
+                    key = keys.next() => match key {
+                        Some(Key::Esc | Key::Quit) | None => break None,
+                        Some(key) => {
+                            view.edit(key, session);
+                            view.draw(session);
+                        }
+                    },
+                }
+            }
+        };
+        let status = match reply {
+            Some(Ok(Some((model, choice)))) if !crate::is_empty_reply(&choice) => {
+                let message = Message {
+                    model: Some(model.clone()),
+                    ..Message::reply(&choice.message)
+                };
+                pipeline.deliver(args, &model, &message, &message).await;
+                // Staged context and attachments are for one request only.
+                session.staged.clear();
+                session.attachments.clear();
+                session.push(message);
+                if args.notify && started.elapsed() >= NOTIFY_AFTER {
+                    crate::notify_reply_ready(&model);
+                }
+                args.show_latency
+                    .then(|| format!("Replied in {:.1}s.", started.elapsed().as_secs_f64()))
+            }
+            Some(Ok(_)) => {
+                session.pop_unanswered();
+                Some("The reply came back empty; your message was not kept.".to_string())
+            }
+            Some(Err(e)) => {
+                session.pop_unanswered();
+                Some(format!("Error: {}. Your message was not kept.", e))
+            }
+            None => {
+                session.pop_unanswered();
+                Some("Gave up on the reply; your message was not kept.".to_string())
+            }
+        };
+
+        let recent_start = session.recent_start(RECENT_EXCHANGES);
+        if !session.rehydrated && session.summarized < recent_start {
+            view.status = Some("Updating the summary...".to_string());
+            view.draw(session);
+        }
+        pipeline.after_turn(self.client, session, usage, args).await;
+        // Whatever the hooks or the summary update printed is drawn over.
+        print!("\x1b[2J");
+        view.status = match (warning, status) {
+            (None, None) => None,
+            (warning, status) => Some(warning.unwrap_or_default() + &status.unwrap_or_default()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(bytes: &[u8]) -> Vec<Key> {
+        parse_keys(&mut Vec::new(), bytes)
+    }
+
+    #[test]
+    fn parses_control_keys_and_escape_sequences() {
+        assert_eq!(keys(b"hi\r"), [Key::Char('h'), Key::Char('i'), Key::Enter]);
+        assert_eq!(keys(b"\x1b[A\x1b[B\x1bOA"), [Key::Up, Key::Down, Key::Up]);
+        assert_eq!(
+            keys(b"\x1b[5~\x1b[6~\x1b[H\x1b[4~"),
+            [Key::PageUp, Key::PageDown, Key::Home, Key::End]
+        );
+        assert_eq!(
+            keys(b"\x0c\x0e\x15\x7f\x03"),
+            [
+                Key::ClearView,
+                Key::NextModel,
+                Key::ClearInput,
+                Key::Backspace,
+                Key::Quit
+            ]
+        );
+        assert_eq!(keys(b"\x1b"), [Key::Esc]);
+        // Unknown sequences, such as F5, are skipped.
+        assert_eq!(keys(b"\x1b[15~x"), [Key::Char('x')]);
+    }
+
+    #[test]
+    fn keeps_a_character_split_between_reads() {
+        let mut pending = Vec::new();
+        let e = "\u{e9}".as_bytes();
+        assert_eq!(parse_keys(&mut pending, &[b'a', e[0]]), [Key::Char('a')]);
+        assert_eq!(parse_keys(&mut pending, &e[1..]), [Key::Char('\u{e9}')]);
+        assert!(pending.is_empty());
+    }
+
+    fn view(rows: usize) -> View {
+        View {
+            rows,
+            cols: 40,
+            input: String::new(),
+            scroll: 0,
+            cleared: 0,
+            status: None,
+        }
+    }
+
+    #[test]
+    fn scrolling_stays_within_the_history() {
+        let mut session = Session::new("gpt-4o");
+        for i in 0..10 {
+            session.push(Message::new("user", format!("question {}", i)));
+        }
+        let mut view = view(7);
+        let history = view.history(&session).len();
+        assert_eq!(history, 20);
+        view.edit(Key::PageUp, &session);
+        assert_eq!(view.scroll, 5);
+        view.edit(Key::Home, &session);
+        assert_eq!(view.scroll, history - view.pane_height());
+        view.edit(Key::Up, &session);
+        assert_eq!(view.scroll, history - view.pane_height());
+        view.edit(Key::End, &session);
+        assert_eq!(view.scroll, 0);
+        view.edit(Key::Down, &session);
+        assert_eq!(view.scroll, 0);
+    }
+
+    #[test]
+    fn next_model_without_favorites_starts_a_model_command() {
+        let mut session = Session::new("gpt-4o");
+        let mut view = view(10);
+        view.next_model(&mut session, &[]);
+        assert_eq!(view.input, "/model ");
+        assert_eq!(session.model, "gpt-4o");
+
+        let favorites = ["gpt-4o".to_string(), "o3-mini".to_string()];
+        view.next_model(&mut session, &favorites);
+        assert_eq!(session.model, "o3-mini");
+        view.next_model(&mut session, &favorites);
+        assert_eq!(session.model, "gpt-4o");
+    }
+
+    #[test]
+    fn clearing_the_view_keeps_the_conversation() {
+        let mut session = Session::new("gpt-4o");
+        session.push(Message::new("user", "hello"));
+        let mut view = view(10);
+        assert!(view.edit(Key::ClearView, &session));
+        assert!(view.history(&session).is_empty());
+        assert_eq!(session.conversation.len(), 1);
+        session.push(Message::new("user", "again"));
+        assert_eq!(view.history(&session), ["You: again", ""]);
+    }
+}
@@ -0,0 +1,33 @@
+//! `--typewriter-natural`: prints replies a character at a time, at an uneven, human pace,
+//! for demos and screencasts. Only the display is slowed; the reply is stored as it arrived.
+
+use std::io::Write;
+use std::time::Duration;
+
+use crate::api::random_unit;
+
+/// Prints `text` to stdout as if it were being typed.
+pub fn type_out(text: &str) {
+    let mut stdout = std::io::stdout();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        print!("{}", c);
+        let _ = stdout.flush();
+        std::thread::sleep(delay(c, chars.peek().copied()));
+    }
+}
+
+/// How long to wait after typing `c`: quick within words, a little longer between them
+/// and clearly longer at the end of a clause or sentence. Each delay varies by up to half
+/// either way.
+fn delay(c: char, next: Option<char>) -> Duration {
+    let ends_here = next.is_none_or(char::is_whitespace);
+    let millis = match c {
+        '.' | '!' | '?' if ends_here => 350.0,
+        ',' | ';' | ':' if ends_here => 180.0,
+        '\n' => 220.0,
+        c if c.is_whitespace() => 55.0,
+        _ => 25.0,
+    };
+    Duration::from_secs_f64(millis * (0.5 + random_unit()) / 1000.0)
+}
@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::models;
+
+/// Token usage as reported by the API for a single request.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+}
+
+/// Accumulated usage for one model over the session.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ModelUsage {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+/// Running usage totals for the session, kept per model so cost can be priced correctly.
+#[derive(Debug, Default)]
+pub struct UsageTotals {
+    pub by_model: BTreeMap<String, ModelUsage>,
+}
+
+impl UsageTotals {
+    /// Records one request against `model`. Requests without usage still count as requests.
+    pub fn record(&mut self, model: &str, usage: Option<&Usage>) {
+        let entry = self.by_model.entry(model.to_string()).or_default();
+        entry.requests += 1;
+        if let Some(usage) = usage {
+            entry.prompt_tokens += usage.prompt_tokens;
+            entry.completion_tokens += usage.completion_tokens;
+        }
+    }
+
+    pub fn prompt_tokens(&self) -> u64 {
+        self.by_model.values().map(|u| u.prompt_tokens).sum()
+    }
+
+    pub fn completion_tokens(&self) -> u64 {
+        self.by_model.values().map(|u| u.completion_tokens).sum()
+    }
+
+    /// Estimated cost in USD. Models missing from the local price table count as free.
+    pub fn cost(&self) -> f64 {
+        self.by_model
+            .iter()
+            .filter_map(|(model, u)| {
+                models::lookup(model).map(|info| {
+                    (u.prompt_tokens as f64 * info.input_price
+                        + u.completion_tokens as f64 * info.output_price)
+                        / 1_000_000.0
+                })
+            })
+            .sum()
+    }
+}
+
+/// How costs are shown: USD converted at `rate` and written with `symbol`.
+#[derive(Debug, Clone)]
+pub struct Currency {
+    pub symbol: String,
+    pub rate: f64,
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Currency {
+            symbol: "$".to_string(),
+            rate: 1.0,
+        }
+    }
+}
+
+impl Currency {
+    /// A currency given by ISO code (`EUR`) or by symbol (`€`). USD amounts are
+    /// multiplied by `rate`.
+    pub fn new(name: &str, rate: f64) -> Self {
+        let symbol = match name.to_ascii_uppercase().as_str() {
+            "USD" => "$".to_string(),
+            "EUR" => "€".to_string(),
+            "GBP" => "£".to_string(),
+            "JPY" | "CNY" => "¥".to_string(),
+            "INR" => "₹".to_string(),
+            code if code.len() == 3 && code.chars().all(|c| c.is_ascii_alphabetic()) => {
+                format!("{} ", code)
+            }
+            _ => name.to_string(),
+        };
+        Currency { symbol, rate }
+    }
+
+    /// Formats a USD amount with a fixed number of decimals and thousands separators,
+    /// e.g. `$1,234.5000`.
+    pub fn format(&self, usd: f64, decimals: usize) -> String {
+        let fixed = format!("{:.*}", decimals, (usd * self.rate).abs());
+        let (whole, fraction) = fixed.split_once('.').unwrap_or((&fixed, ""));
+        let mut grouped = String::new();
+        for (i, digit) in whole.chars().enumerate() {
+            if i > 0 && (whole.len() - i) % 3 == 0 {
+                grouped.push(',');
+            }
+            grouped.push(digit);
+        }
+        let sign = if usd < 0.0 { "-" } else { "" };
+        if fraction.is_empty() {
+            format!("{}{}{}", sign, self.symbol, grouped)
+        } else {
+            format!("{}{}{}.{}", sign, self.symbol, grouped, fraction)
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Wraps replies to the terminal width so long lines don't overflow.
+
+use std::io::IsTerminal;
+
+/// Width used on a terminal whose size can't be read.
+const FALLBACK_WIDTH: usize = 80;
+
+/// The width to wrap output to, or `None` when stdout isn't a terminal and shouldn't be
+/// wrapped. `COLUMNS` takes precedence over the size the terminal reports.
+pub fn terminal_width() -> Option<usize> {
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    let columns = std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok());
+    Some(
+        columns
+            .or_else(reported_width)
+            .filter(|&w| w > 0)
+            .unwrap_or(FALLBACK_WIDTH),
+    )
+}
+
+#[cfg(unix)]
+fn reported_width() -> Option<usize> {
+    #[repr(C)]
+    #[derive(Default)]
+    struct WinSize {
+        rows: u16,
+        cols: u16,
+        x_pixels: u16,
+        y_pixels: u16,
+    }
+    unsafe extern "C" {
+        fn ioctl(fd: i32, request: std::ffi::c_ulong, ...) -> i32;
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const TIOCGWINSZ: std::ffi::c_ulong = 0x5413;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    const TIOCGWINSZ: std::ffi::c_ulong = 0x4008_7468;
+
+    let mut size = WinSize::default();
+    // SAFETY: TIOCGWINSZ only writes a `struct winsize`, which `WinSize` mirrors.
+    let status = unsafe { ioctl(1, TIOCGWINSZ, &mut size as *mut WinSize) };
+    (status == 0 && size.cols > 0).then_some(usize::from(size.cols))
+}
+
+#[cfg(not(unix))]
+fn reported_width() -> Option<usize> {
+    None
+}
+
+/// Wraps each line of `text` at word boundaries to `width` columns. Continuation lines
+/// keep the original line's indentation, words longer than a line are left whole, and
+/// fenced code blocks are not touched.
+pub fn wrap(text: &str, width: usize) -> String {
+    let mut out = Vec::new();
+    let mut in_code = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+            out.push(line.to_string());
+            continue;
+        }
+        if in_code || line.chars().count() <= width {
+            out.push(line.to_string());
+            continue;
+        }
+
+        let indent: String = line.chars().take_while(|c| c.is_whitespace()).collect();
+        let mut current = String::new();
+        let mut current_len = 0;
+        for word in line.split_whitespace() {
+            let word_len = word.chars().count();
+            if current_len > 0 && current_len + 1 + word_len > width {
+                out.push(std::mem::take(&mut current));
+                current_len = 0;
+            }
+            if current_len == 0 {
+                // The first line already starts with its indentation.
+                current.push_str(&indent);
+                current_len = indent.chars().count();
+            } else {
+                current.push(' ');
+                current_len += 1;
+            }
+            current.push_str(word);
+            current_len += word_len;
+        }
+        out.push(current);
+    }
+    out.join("\n")
+}
@@ -0,0 +1,181 @@
+//! End-to-end checks that run the built `gpt` binary offline, through `--mock` or a
+//! `--replay` cassette.
+
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// A fresh directory for one test, also used as the config directory.
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gpt-cli-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The binary, run in `dir` with no key and no user config.
+fn gpt(dir: &PathBuf, flags: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_gpt"));
+    command
+        .args(flags)
+        .current_dir(dir)
+        .env_remove("OPENAI_API_KEY")
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("HOME", dir);
+    command
+}
+
+/// Runs `command` with `stdin` piped in and waits for it.
+fn run(mut command: Command, stdin: &str) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// One cassette line answering `message` with `response`.
+fn recorded(message: &str, response: serde_json::Value) -> String {
+    let entry = serde_json::json!({
+        "path": "/chat/completions",
+        "request": {"model": "gpt-4o", "messages": [{"role": "user", "content": message}]},
+        "response": response.to_string(),
+    });
+    format!("{}\n", entry)
+}
+
+#[test]
+fn reply_without_choices_drops_the_message() {
+    let dir = scratch("no-choices");
+    // The second request only matches if the first message was dropped from the history.
+    let cassette = recorded("hi", serde_json::json!({"choices": []}))
+        + &recorded(
+            "again",
+            serde_json::json!({"choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "hello"},
+                "finish_reason": "stop",
+            }]}),
+        );
+    std::fs::write(dir.join("cassette.jsonl"), cassette).unwrap();
+
+    let output = run(
+        gpt(&dir, &["--replay", "cassette.jsonl", "--session", "s.json"]),
+        "hi\nagain\n",
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains("no choices"), "{}", stderr);
+    assert!(stderr.contains("Your message was not kept"), "{}", stderr);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("hello"));
+
+    let saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.join("s.json")).unwrap()).unwrap();
+    let contents: Vec<&str> = saved["conversation"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|message| message["content"].as_str().unwrap())
+        .collect();
+    assert_eq!(contents, ["again", "hello"]);
+}
+
+#[test]
+fn closed_stdout_ends_the_session_and_saves_it() {
+    let dir = scratch("broken-pipe");
+    let reply = "word ".repeat(20_000);
+    let mut child = gpt(
+        &dir,
+        &["--mock", "--mock-reply", &reply, "--session", "s.json"],
+    )
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"go\nnever sent\n")
+        .unwrap();
+    // Like `| head`: read the start of the reply, then close the pipe on the rest of it.
+    let mut stdout = child.stdout.take().unwrap();
+    let mut read = Vec::new();
+    let mut buffer = [0; 4096];
+    while !String::from_utf8_lossy(&read).contains("word") {
+        let n = stdout.read(&mut buffer).unwrap();
+        assert!(n > 0, "{}", String::from_utf8_lossy(&read));
+        read.extend_from_slice(&buffer[..n]);
+    }
+    drop(stdout);
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(141), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+
+    // The session ends after the first reply, which is still saved.
+    let saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.join("s.json")).unwrap()).unwrap();
+    let conversation = saved["conversation"].as_array().unwrap();
+    assert_eq!(conversation.len(), 2, "{:?}", conversation);
+    assert_eq!(conversation[0]["content"], "go");
+}
+
+#[test]
+fn empty_stdin_is_an_error() {
+    let dir = scratch("empty-stdin");
+    let output = run(gpt(&dir, &["--mock", "--stateless"]), "");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(
+        stderr.contains("there was no message on stdin"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn batch_lines_are_redacted_before_sending() {
+    let dir = scratch("batch-redact");
+    std::fs::write(
+        dir.join("prompts.txt"),
+        "my key is sk-abc123\nnothing secret\n",
+    )
+    .unwrap();
+    // The mock reply is the user message exactly as the request carried it.
+    let output = run(
+        gpt(
+            &dir,
+            &[
+                "--mock",
+                "--mock-reply",
+                "sent: {prompt}",
+                "--batch",
+                "prompts.txt",
+                "--redact-pattern",
+                "sk-[a-z0-9]+",
+            ],
+        ),
+        "",
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stdout.contains("sent: my key is [REDACTED]"), "{}", stdout);
+    assert!(stdout.contains("sent: nothing secret"), "{}", stdout);
+    assert!(!stdout.contains("sk-abc123"), "{}", stdout);
+    assert!(
+        stderr.contains("Redacted 1 match(es) from prompts.txt:1"),
+        "{}",
+        stderr
+    );
+}